@@ -0,0 +1,124 @@
+use vector::Vector;
+use ray::Ray;
+
+use std::f64;
+
+// An axis-aligned bounding box, described by its minimum and maximum corners
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingBox {
+    pub min: Vector,
+    pub max: Vector,
+}
+
+impl BoundingBox {
+    pub fn new(a: &Vector, b: &Vector) -> BoundingBox {
+        BoundingBox {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: self.min.min(&other.min),
+            max: self.max.max(&other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vector {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn diagonal(&self) -> Vector {
+        self.max - self.min
+    }
+
+    pub fn contains(&self, p: &Vector) -> bool {
+        p.x >= self.min.x && p.x <= self.max.x && p.y >= self.min.y && p.y <= self.max.y &&
+        p.z >= self.min.z && p.z <= self.max.z
+    }
+
+    // Slab test: returns the parametric distances at which the ray enters and
+    // exits the box, which may be negative if the box is (partially) behind the
+    // ray origin
+    pub fn intersect(&self, r: &Ray) -> Option<(f64, f64)> {
+        let mut t_near = -f64::INFINITY;
+        let mut t_far = f64::INFINITY;
+
+        for axis in 0..3 {
+            // Dividing by a zero direction component yields +/- infinity, which
+            // correctly rejects or accepts the whole slab
+            let inv_d = 1.0 / r.direction[axis];
+            let mut t0 = (self.min[axis] - r.origin[axis]) * inv_d;
+            let mut t1 = (self.max[axis] - r.origin[axis]) * inv_d;
+            if inv_d < 0.0 {
+                ::std::mem::swap(&mut t0, &mut t1);
+            }
+
+            // Note that `f64::max` and `f64::min` ignore NaNs, which occur when the
+            // ray origin lies exactly on a slab boundary
+            t_near = t_near.max(t0);
+            t_far = t_far.min(t1);
+            if t_near > t_far {
+                return None;
+            }
+        }
+        Some((t_near, t_far))
+    }
+
+    // Returns the outward normal, uv-coordinates and the partial derivatives of
+    // the position with respect to u and v on the face closest to `p`, where the
+    // uv-coordinates span [0..1] across each face
+    pub fn surface_at(&self, p: &Vector) -> (Vector, (f64, f64), Vector, Vector) {
+        let half_extents = self.diagonal() * 0.5;
+        let local = (*p - self.center()) / half_extents;
+
+        // The face that was hit is the one along the axis with the largest
+        // (relative) offset from the center
+        let abs = local.abs();
+        let axis = if abs.x > abs.y && abs.x > abs.z {
+            0
+        } else if abs.y > abs.z {
+            1
+        } else {
+            2
+        };
+        let sign = if local[axis] > 0.0 { 1.0 } else { -1.0 };
+
+        // Each face is parameterized such that its tangents, along with its normal,
+        // form a right-handed frame: note that u and v span the full size of the
+        // face, so the derivatives are the edge lengths
+        let size = self.diagonal();
+        let (normal, u, v, dpdu, dpdv) = match axis {
+            0 => (Vector::new(sign, 0.0, 0.0),
+                  -sign * local.z,
+                  local.y,
+                  Vector::new(0.0, 0.0, -sign * size.z),
+                  Vector::new(0.0, size.y, 0.0)),
+            1 => (Vector::new(0.0, sign, 0.0),
+                  local.x,
+                  -sign * local.z,
+                  Vector::new(size.x, 0.0, 0.0),
+                  Vector::new(0.0, 0.0, -sign * size.z)),
+            _ => (Vector::new(0.0, 0.0, sign),
+                  sign * local.x,
+                  local.y,
+                  Vector::new(sign * size.x, 0.0, 0.0),
+                  Vector::new(0.0, size.y, 0.0)),
+        };
+        (normal, ((u + 1.0) * 0.5, (v + 1.0) * 0.5), dpdu, dpdv)
+    }
+}
+
+#[test]
+fn test_slab_intersection() {
+    let bounds = BoundingBox::new(&Vector::new(-1.0, -1.0, -1.0), &Vector::one());
+
+    // A ray starting inside of the box enters behind the origin
+    let r = Ray::new(&Vector::zero(), &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    assert_eq!(bounds.intersect(&r), Some((-1.0, 1.0)));
+
+    // A ray parallel to (but outside of) a slab misses
+    let r = Ray::new(&Vector::new(0.0, 2.0, 5.0), &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    assert_eq!(bounds.intersect(&r), None);
+}
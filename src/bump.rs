@@ -0,0 +1,153 @@
+use vector::Vector;
+use shape::DifferentialGeometry;
+use texture::Texture;
+
+use std::sync::Arc;
+
+// Normal mappings perturb the shading normal of a surface to add detail that
+// isn't present in the geometry
+pub trait NormalMapping: Sync + Send {
+    fn apply(&self, dg: &mut DifferentialGeometry);
+}
+
+// The smallest cosine allowed between a perturbed shading normal and the
+// geometric normal
+const MIN_COSINE: f64 = 0.05;
+
+// Perturbed normals that point below the geometric surface would let light leak
+// through it, so they are bent back towards the tangent plane rather than being
+// flipped, which would invert the detail
+fn bend_above(ns: &Vector, n: &Vector) -> Vector {
+    let cosine = ns.dot(n);
+    if cosine >= MIN_COSINE {
+        return *ns;
+    }
+    let tangent = *ns - *n * cosine;
+    if tangent.length() < 1.0e-8 {
+        return *n;
+    }
+    tangent.normalize() * (1.0 - MIN_COSINE * MIN_COSINE).sqrt() + *n * MIN_COSINE
+}
+
+// Bump mapping (Blinn 1978): offsets the surface along its normal by a scalar
+// height texture and uses the partial derivatives of the displaced surface to
+// compute the new normal
+pub struct BumpMap {
+    pub height: Arc<dyn Texture<f64>>,
+    // The height is multiplied by this before being applied, in world-space units
+    pub scale: f64,
+}
+
+impl NormalMapping for BumpMap {
+    fn apply(&self, dg: &mut DifferentialGeometry) {
+        // Use the footprint of the ray to choose the finite difference offsets,
+        // falling back to a small fixed offset if there are no differentials
+        let offset = |dx: f64, dy: f64| {
+            let delta = 0.5 * (dx.abs() + dy.abs());
+            if delta == 0.0 { 0.0005 } else { delta }
+        };
+        let du = offset(dg.dudx, dg.dudy);
+        let dv = offset(dg.dvdx, dg.dvdy);
+
+        let displace = self.height.evaluate(dg) * self.scale;
+        let mut shifted = dg.clone();
+        shifted.position = dg.position + dg.dpdu * du;
+        shifted.uv = (dg.uv.0 + du, dg.uv.1);
+        let u_displace = self.height.evaluate(&shifted) * self.scale;
+        shifted.position = dg.position + dg.dpdv * dv;
+        shifted.uv = (dg.uv.0, dg.uv.1 + dv);
+        let v_displace = self.height.evaluate(&shifted) * self.scale;
+
+        // The curvature of the underlying surface is ignored
+        let n = dg.shading_normal;
+        let dpdu = dg.dpdu + n * ((u_displace - displace) / du);
+        let dpdv = dg.dpdv + n * ((v_displace - displace) / dv);
+        let mut ns = dpdu.cross(&dpdv).normalize();
+        if !ns.x.is_finite() {
+            return;
+        }
+
+        // The tangents may form a left-handed frame
+        if ns.dot(&n) < 0.0 {
+            ns = -ns;
+        }
+        dg.shading_normal = bend_above(&ns, &dg.normal);
+    }
+}
+
+impl BumpMap {
+    pub fn new(height: Arc<dyn Texture<f64>>, scale: f64) -> BumpMap {
+        BumpMap { height, scale }
+    }
+}
+
+// Tangent-space normal mapping: the texture stores normals in the frame formed by
+// the surface's tangents and shading normal, encoded as colors in the range 0..1
+// (i.e. with green pointing towards increasing v)
+pub struct NormalMap {
+    pub normals: Arc<dyn Texture<Vector>>,
+    // Scales the tangential components of the stored normals, where zero leaves
+    // the surface unperturbed
+    pub strength: f64,
+}
+
+impl NormalMapping for NormalMap {
+    fn apply(&self, dg: &mut DifferentialGeometry) {
+        let n = dg.shading_normal;
+
+        // Build an orthonormal frame around the shading normal, aligned with the
+        // surface's parameterization
+        let tangent = dg.dpdu - n * n.dot(&dg.dpdu);
+        if tangent.length() < 1.0e-8 {
+            return;
+        }
+        let tangent = tangent.normalize();
+        let mut bitangent = n.cross(&tangent);
+        if bitangent.dot(&dg.dpdv) < 0.0 {
+            bitangent = -bitangent;
+        }
+
+        let encoded = self.normals.evaluate(dg) * 2.0 - Vector::one();
+        let ns = tangent * (encoded.x * self.strength) + bitangent * (encoded.y * self.strength) +
+                 n * encoded.z;
+        if ns.length() < 1.0e-8 {
+            return;
+        }
+        dg.shading_normal = bend_above(&ns.normalize(), &dg.normal);
+    }
+}
+
+impl NormalMap {
+    pub fn new(normals: Arc<dyn Texture<Vector>>, strength: f64) -> NormalMap {
+        NormalMap { normals, strength }
+    }
+}
+
+#[test]
+fn test_normal_mapping() {
+    use ray::Ray;
+    use shape::{Shape, Plane};
+    use texture::{ConstantTexture, GradientTexture, UvMapping};
+
+    let plane = Plane::default();
+    let r = Ray::new(&Vector::new(0.25, 0.5, 0.25), &Vector::new(0.0, -1.0, 0.0), 0.0, 10.0);
+    let dg = plane.intersect(&r).unwrap();
+
+    // A flat normal map leaves the shading normal untouched
+    let flat = NormalMap::new(Arc::new(ConstantTexture::new(Vector::new(0.5, 0.5, 1.0))), 1.0);
+    let mut flattened = dg.clone();
+    flat.apply(&mut flattened);
+    assert!((flattened.shading_normal - dg.normal).length() < 1.0e-9);
+
+    // A height that increases along u tilts the normal towards -u, while a steep
+    // one is kept above the geometric surface
+    let ramp = Arc::new(GradientTexture::new(Arc::new(ConstantTexture::new(0.0)),
+                                             Arc::new(ConstantTexture::new(1.0)),
+                                             Arc::new(UvMapping::new((0.01, 0.01), (0.5, 0.5)))));
+    for &(scale, expected) in &[(10.0, -0.1f64.atan().sin()), (1.0e4, -(1.0 - MIN_COSINE * MIN_COSINE).sqrt())] {
+        let mut bumped = dg.clone();
+        BumpMap::new(ramp.clone(), scale).apply(&mut bumped);
+        assert!((bumped.shading_normal.dot(&dg.dpdu.normalize()) - expected).abs() < 1.0e-6);
+        assert!(bumped.shading_normal.dot(&dg.normal) >= MIN_COSINE - 1.0e-9);
+    }
+}
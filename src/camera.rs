@@ -1,5 +1,5 @@
 use vector::Vector;
-use ray::Ray;
+use ray::{Ray, RayDifferential};
 
 use std::f64;
 
@@ -25,8 +25,8 @@ impl Camera {
         let half_height = (theta * 0.5).tan();
         let half_width = aspect_ratio * half_height;
         Camera {
-            fov: fov,
-            aspect_ratio: aspect_ratio,
+            fov,
+            aspect_ratio,
             origin: Vector::zero(),
             lower_left_corner: Vector::new(-half_width, -half_height, -1.0),
             horizontal: Vector::new(2.0 * half_width, 0.0, 0.0),
@@ -40,4 +40,17 @@ impl Camera {
                  0.001,
                  f64::MAX)
     }
+
+    // Generates a ray along with its differentials, where `du` and `dv` are the
+    // distances between adjacent pixels in uv-space
+    pub fn generate_ray_differential(&self, u: f64, v: f64, du: f64, dv: f64) -> Ray {
+        let mut ray = self.generate_ray(u, v);
+        ray.differential = Some(RayDifferential {
+            rx_origin: self.origin,
+            rx_direction: self.generate_ray(u + du, v).direction,
+            ry_origin: self.origin,
+            ry_direction: self.generate_ray(u, v + dv).direction,
+        });
+        ray
+    }
 }
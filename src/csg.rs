@@ -0,0 +1,142 @@
+use ray::Ray;
+use shape::{Shape, DifferentialGeometry, Interval, EPSILON};
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CsgOperation {
+    // Points inside of either shape
+    Union,
+    // Points inside of both shapes
+    Intersection,
+    // Points inside of the left shape but not the right shape
+    Difference,
+}
+
+impl CsgOperation {
+    fn inside(&self, in_left: bool, in_right: bool) -> bool {
+        match *self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+// A boolean combination of two closed shapes: both operands must report their
+// intervals via `Shape::intervals`
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Arc<dyn Shape>,
+    pub right: Arc<dyn Shape>,
+}
+
+impl Csg {
+    pub fn new(op: CsgOperation, l: Arc<dyn Shape>, r: Arc<dyn Shape>) -> Csg {
+        Csg {
+            operation: op,
+            left: l,
+            right: r,
+        }
+    }
+
+    pub fn union(l: Arc<dyn Shape>, r: Arc<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Union, l, r)
+    }
+
+    pub fn intersection(l: Arc<dyn Shape>, r: Arc<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Intersection, l, r)
+    }
+
+    pub fn difference(l: Arc<dyn Shape>, r: Arc<dyn Shape>) -> Csg {
+        Csg::new(CsgOperation::Difference, l, r)
+    }
+}
+
+impl Shape for Csg {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        // The intervals are sorted, so the first boundary in front of the ray
+        // origin is the closest point of intersection
+        for interval in self.intervals(r) {
+            if interval.enter.t > EPSILON {
+                return Some(interval.enter);
+            } else if interval.exit.t > EPSILON {
+                return Some(interval.exit);
+            }
+        }
+        None
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        // Gather the boundaries of both operands as a list of events along the ray:
+        // each event records which operand it belongs to and whether the ray is
+        // entering or exiting that operand
+        // Degenerate rays can produce intervals with undefined bounds, which are
+        // dropped as a whole so that the sweep below stays balanced
+        let defined = |interval: &Interval| !interval.enter.t.is_nan() && !interval.exit.t.is_nan();
+        let mut events = Vec::new();
+        for interval in self.left.intervals(r).into_iter().filter(&defined) {
+            events.push((interval.enter, true, true));
+            events.push((interval.exit, true, false));
+        }
+        for interval in self.right.intervals(r).into_iter().filter(&defined) {
+            let (mut enter, mut exit) = (interval.enter, interval.exit);
+
+            // The surface of a carved-out region faces into the right operand
+            if self.operation == CsgOperation::Difference {
+                enter.flip();
+                exit.flip();
+            }
+            events.push((enter, false, true));
+            events.push((exit, false, false));
+        }
+        events.sort_by(|a, b| a.0.t.total_cmp(&b.0.t));
+
+        // Sweep along the ray, keeping track of whether or not we are inside of
+        // each operand and emitting an interval whenever the combined state changes
+        let mut intervals = Vec::new();
+        let mut enter: Option<DifferentialGeometry> = None;
+        let (mut in_left, mut in_right) = (false, false);
+        for (dg, is_left, entering) in events {
+            let was_inside = self.operation.inside(in_left, in_right);
+            if is_left {
+                in_left = entering;
+            } else {
+                in_right = entering;
+            }
+            let is_inside = self.operation.inside(in_left, in_right);
+
+            if !was_inside && is_inside {
+                enter = Some(dg);
+            } else if was_inside && !is_inside {
+                if let Some(start) = enter.take() {
+                    intervals.push(Interval::new(start, dg));
+                }
+            }
+        }
+        intervals
+    }
+}
+
+#[test]
+fn test_difference() {
+    use vector::Vector;
+    use shape::{Sphere, AxisAlignedBox};
+    use std::f64;
+
+    // A unit sphere with the half-space z > 0 carved out by a large box
+    let sphere = Arc::new(Sphere::new(&Vector::zero(), 1.0));
+    let carve = Arc::new(AxisAlignedBox::new(&Vector::new(-2.0, -2.0, 0.0), &Vector::new(2.0, 2.0, 2.0)));
+    let csg = Csg::difference(sphere, carve);
+
+    // Looking down the z-axis, the ray should hit the flat face of the hemisphere
+    let r = Ray::new(&Vector::new(0.0, 0.0, 5.0), &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    let dg = csg.intersect(&r).unwrap();
+    assert!((dg.t - 5.0).abs() < 1e-9);
+    assert_eq!(dg.normal, Vector::new(0.0, 0.0, 1.0));
+
+    // Starting inside of the carved-out region and travelling along the cut
+    // plane, the ray never enters what remains of the sphere and misses
+    let r = Ray::new(&Vector::new(0.0, 0.0, 0.5), &Vector::new(1.0, 0.0, 0.0), 0.0, f64::MAX);
+    assert!(csg.intersect(&r).is_none());
+}
@@ -0,0 +1,378 @@
+// A decoder for single-part, scanline OpenEXR images with HALF, FLOAT or UINT
+// channels, compressed with NONE, RLE, ZIPS or ZIP: tiled, deep and multi-part
+// images and the lossy / wavelet compression schemes are not supported
+use vector::Vector;
+use image::{Image, invalid_data};
+use inflate::zlib_decompress;
+
+use std::io;
+
+pub const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+#[derive(Copy, Clone, PartialEq)]
+enum PixelType {
+    Uint,
+    Half,
+    Float,
+}
+
+impl PixelType {
+    fn size(&self) -> usize {
+        match *self {
+            PixelType::Half => 2,
+            _ => 4,
+        }
+    }
+}
+
+struct Channel {
+    name: String,
+    pixel_type: PixelType,
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        let slice = self.cursor
+            .checked_add(count)
+            .and_then(|end| self.bytes.get(self.cursor..end))
+            .ok_or_else(|| invalid_data("unexpected end of exr data"))?;
+        self.cursor += count;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(self.take(4)?);
+        Ok(i32::from_le_bytes(raw))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        let mut raw = [0u8; 8];
+        raw.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(raw))
+    }
+
+    // Reads a null-terminated string
+    fn string(&mut self) -> io::Result<String> {
+        let start = self.cursor;
+        while *self.bytes.get(self.cursor).ok_or_else(|| invalid_data("unterminated exr string"))? != 0 {
+            self.cursor += 1;
+        }
+        let string = String::from_utf8_lossy(&self.bytes[start..self.cursor]).into_owned();
+        self.cursor += 1;
+        Ok(string)
+    }
+}
+
+// Converts an IEEE 754 half-precision float to single precision
+fn half_to_f32(bits: u16) -> f32 {
+    let sign = u32::from(bits >> 15) << 31;
+    let exponent = u32::from((bits >> 10) & 0x1f);
+    let mantissa = u32::from(bits & 0x3ff);
+
+    let bits = match (exponent, mantissa) {
+        (0, 0) => sign,
+        (0, _) => {
+            // Subnormal: renormalize the mantissa
+            let mut exponent = 127 - 15 + 1;
+            let mut mantissa = mantissa;
+            while mantissa & 0x400 == 0 {
+                mantissa <<= 1;
+                exponent -= 1;
+            }
+            sign | (exponent << 23) | ((mantissa & 0x3ff) << 13)
+        }
+        (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+// Undoes the byte reordering and delta encoding applied before ZIP and RLE
+// compression
+fn reconstruct(data: &mut [u8]) -> Vec<u8> {
+    for i in 1..data.len() {
+        data[i] = data[i - 1].wrapping_add(data[i]).wrapping_sub(128);
+    }
+
+    // The first half of the data holds the even bytes, the second half the odd
+    let half = data.len().div_ceil(2);
+    let mut output = Vec::with_capacity(data.len());
+    for i in 0..half {
+        output.push(data[i]);
+        if half + i < data.len() {
+            output.push(data[half + i]);
+        }
+    }
+    output
+}
+
+fn rle_decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    let mut cursor = 0;
+    while cursor < bytes.len() {
+        let count = bytes[cursor] as i8;
+        cursor += 1;
+        if count < 0 {
+            // A run of literal bytes
+            let count = (-i32::from(count)) as usize;
+            let run = bytes.get(cursor..cursor + count)
+                .ok_or_else(|| invalid_data("truncated exr rle data"))?;
+            output.extend_from_slice(run);
+            cursor += count;
+        } else {
+            // A single byte, repeated
+            let value = *bytes.get(cursor).ok_or_else(|| invalid_data("truncated exr rle data"))?;
+            output.extend(::std::iter::repeat_n(value, count as usize + 1));
+            cursor += 1;
+        }
+    }
+    Ok(output)
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+    let mut reader = Reader {
+        bytes,
+        cursor: 0,
+    };
+    if reader.take(4)? != MAGIC {
+        return Err(invalid_data("missing exr magic number"));
+    }
+    let version = reader.i32()?;
+    if version & 0x200 != 0 {
+        return Err(invalid_data("tiled exr images are not supported"));
+    }
+    if version & 0x1800 != 0 {
+        return Err(invalid_data("deep and multi-part exr images are not supported"));
+    }
+
+    // Parse the header's attributes, keeping only those that we need
+    let mut channels = Vec::new();
+    let mut compression = None;
+    let mut data_window = None;
+    loop {
+        let name = reader.string()?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = reader.string()?;
+        let size = reader.i32()? as usize;
+        let mut value = Reader {
+            bytes: reader.take(size)?,
+            cursor: 0,
+        };
+        match (name.as_str(), kind.as_str()) {
+            ("channels", "chlist") => {
+                loop {
+                    let channel = value.string()?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    let pixel_type = match value.i32()? {
+                        0 => PixelType::Uint,
+                        1 => PixelType::Half,
+                        2 => PixelType::Float,
+                        _ => return Err(invalid_data("invalid exr pixel type")),
+                    };
+
+                    // Skip the linearity flag, the reserved bytes and the subsampling
+                    value.take(4)?;
+                    if value.i32()? != 1 || value.i32()? != 1 {
+                        return Err(invalid_data("subsampled exr channels are not supported"));
+                    }
+                    channels.push(Channel {
+                        name: channel,
+                        pixel_type,
+                    });
+                }
+            }
+            ("compression", "compression") => compression = Some(value.u8()?),
+            ("dataWindow", "box2i") => {
+                data_window = Some((value.i32()?, value.i32()?, value.i32()?, value.i32()?));
+            }
+            _ => {}
+        }
+    }
+
+    let (x_min, y_min, x_max, y_max) =
+        data_window.ok_or_else(|| invalid_data("missing exr data window"))?;
+    let width = i64::from(x_max) - i64::from(x_min) + 1;
+    let height = i64::from(y_max) - i64::from(y_min) + 1;
+    if width <= 0 || height <= 0 {
+        return Err(invalid_data("empty exr data window"));
+    }
+    let (width, height) = (width as usize, height as usize);
+
+    // How many bytes each compression scheme can expand a byte into at most: RLE
+    // repeats a byte up to 128 times for every two, and DEFLATE is limited to
+    // about 1032 to 1
+    let (lines_per_block, max_expansion) = match compression.unwrap_or(0) {
+        0 => (1, 1),
+        1 => (1, 64),
+        2 => (1, 1032),
+        3 => (16, 1032),
+        _ => return Err(invalid_data("unsupported exr compression")),
+    };
+
+    // Luminance-only images are expanded to gray
+    let find = |name: &str| channels.iter().position(|c| c.name == name);
+    let indices = match (find("R"), find("G"), find("B"), find("Y")) {
+        (Some(r), Some(g), Some(b), _) => [r, g, b],
+        (_, _, _, Some(y)) => [y, y, y],
+        _ => return Err(invalid_data("exr image has no RGB or luminance channels")),
+    };
+    let alpha_index = find("A");
+
+    // Channels are stored one after the other within each scanline, in the
+    // (alphabetical) order of the channel list
+    let line_size: usize = channels.iter().map(|c| c.pixel_type.size() * width).sum();
+    let block_count = height.div_ceil(lines_per_block);
+
+    // The window comes from the header, so check that the offset table, and the
+    // pixel data after it, could describe that many pixels before allocating
+    let remaining = bytes.len() - reader.cursor;
+    let invalid_window = || invalid_data("exr data window doesn't match its pixel data");
+    if block_count > remaining / 8 {
+        return Err(invalid_window());
+    }
+    let image_size = line_size.checked_mul(height).ok_or_else(invalid_window)?;
+    if image_size / max_expansion > remaining - block_count * 8 {
+        return Err(invalid_window());
+    }
+    let mut offsets = Vec::with_capacity(block_count);
+    for _ in 0..block_count {
+        offsets.push(reader.u64()? as usize);
+    }
+
+    let mut image = Image::new(width, height);
+    let mut alpha = vec![1.0; width * height];
+    for offset in offsets {
+        let mut block = Reader {
+            bytes,
+            cursor: offset,
+        };
+        let first_line = i64::from(block.i32()?) - i64::from(y_min);
+        if first_line < 0 || first_line >= height as i64 {
+            return Err(invalid_data("exr block lies outside of the data window"));
+        }
+        let first_line = first_line as usize;
+        let size = block.i32()?;
+        if size < 0 {
+            return Err(invalid_data("invalid exr block size"));
+        }
+        let size = size as usize;
+        let lines = lines_per_block.min(height.saturating_sub(first_line));
+        let expected = line_size * lines;
+
+        let compressed = block.take(size)?;
+        let data = if size == expected {
+            // Blocks that don't compress well are stored uncompressed
+            compressed.to_vec()
+        } else {
+            match compression.unwrap_or(0) {
+                0 => compressed.to_vec(),
+                1 => reconstruct(&mut rle_decompress(compressed)?),
+                _ => reconstruct(&mut zlib_decompress(compressed)?),
+            }
+        };
+        if data.len() < expected {
+            return Err(invalid_data("truncated exr pixel data"));
+        }
+
+        for line in 0..lines {
+            let mut offset = line * line_size;
+            let mut values = vec![Vec::new(); channels.len()];
+            for (channel, values) in channels.iter().zip(values.iter_mut()) {
+                for x in 0..width {
+                    let raw = &data[offset + x * channel.pixel_type.size()..];
+                    values.push(match channel.pixel_type {
+                        PixelType::Half => f64::from(half_to_f32(u16::from_le_bytes([raw[0], raw[1]]))),
+                        PixelType::Float => {
+                            f64::from(f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+                        }
+                        PixelType::Uint => f64::from(u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]])),
+                    });
+                }
+                offset += channel.pixel_type.size() * width;
+            }
+
+            let y = first_line + line;
+            for x in 0..width {
+                image.set(x,
+                          y,
+                          &Vector::new(values[indices[0]][x], values[indices[1]][x], values[indices[2]][x]));
+                if let Some(a) = alpha_index {
+                    alpha[y * width + x] = values[a][x];
+                }
+            }
+        }
+    }
+
+    if alpha_index.is_some() {
+        image.alpha = Some(alpha);
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_half_to_f32() {
+    assert_eq!(half_to_f32(0x3c00), 1.0);
+    assert_eq!(half_to_f32(0xc000), -2.0);
+    assert_eq!(half_to_f32(0x7bff), 65504.0);
+    assert_eq!(half_to_f32(0x0001), 2.0f32.powi(-24));
+}
+
+
+#[test]
+fn test_decode_exr_window() {
+    // A single uncompressed scanline of luminance values, as floats
+    let exr = |window: [i32; 4], values: &[f32]| {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&2i32.to_le_bytes());
+        let mut attribute = |name: &str, kind: &str, value: &[u8]| {
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(kind.as_bytes());
+            bytes.push(0);
+            bytes.extend_from_slice(&(value.len() as i32).to_le_bytes());
+            bytes.extend_from_slice(value);
+        };
+        let mut channel = b"Y\0".to_vec();
+        for &word in &[2i32, 0, 1, 1] {
+            channel.extend_from_slice(&word.to_le_bytes());
+        }
+        channel.push(0);
+        attribute("channels", "chlist", &channel);
+        attribute("compression", "compression", &[0]);
+        let window: Vec<u8> = window.iter().flat_map(|w| w.to_le_bytes().to_vec()).collect();
+        attribute("dataWindow", "box2i", &window);
+        bytes.push(0);
+
+        let offset = bytes.len() as u64 + 8;
+        bytes.extend_from_slice(&offset.to_le_bytes());
+        bytes.extend_from_slice(&0i32.to_le_bytes());
+        bytes.extend_from_slice(&(values.len() as i32 * 4).to_le_bytes());
+        for value in values {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    };
+    let image = decode(&exr([0, 0, 1, 0], &[0.25, 2.0])).unwrap();
+    assert_eq!((image.width, image.height), (2, 1));
+    assert_eq!(image.get(1, 0), Vector::one() * 2.0);
+
+    // Inverted, overflowing and oversized windows are rejected before allocating
+    assert!(decode(&exr([0, 0, -1, 0], &[])).is_err());
+    assert!(decode(&exr([i32::MIN, 0, i32::MAX, 0], &[0.0])).is_err());
+    assert!(decode(&exr([0, 0, 0, i32::MAX], &[0.0])).is_err());
+    assert!(decode(&exr([0, 0, 100000, 0], &[0.0])).is_err());
+}
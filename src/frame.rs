@@ -0,0 +1,41 @@
+use vector::Vector;
+
+// An orthonormal basis, used to express directions relative to a surface: in the
+// local coordinate system, the normal is the z-axis
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub s: Vector,
+    pub t: Vector,
+    pub n: Vector,
+}
+
+impl Frame {
+    // Builds a frame around a normal with arbitrary tangents
+    pub fn from_normal(n: &Vector) -> Frame {
+        let (s, t) = n.coordinate_system();
+        Frame { s, t, n: *n }
+    }
+
+    // Builds a frame around a normal whose first tangent is aligned with the
+    // projection of `dpdu`, which orients anisotropic materials consistently
+    pub fn new(n: &Vector, dpdu: &Vector) -> Frame {
+        let s = *dpdu - *n * n.dot(dpdu);
+        if s.length() < 1.0e-8 {
+            return Frame::from_normal(n);
+        }
+        let s = s.normalize();
+        Frame {
+            s,
+            t: n.cross(&s),
+            n: *n,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vector) -> Vector {
+        Vector::new(v.dot(&self.s), v.dot(&self.t), v.dot(&self.n))
+    }
+
+    pub fn to_world(&self, v: &Vector) -> Vector {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
@@ -0,0 +1,185 @@
+// The Fresnel equations, which give the fraction of light that is reflected at a
+// smooth interface between two media for unpolarized light
+use vector::Vector;
+use spectrum;
+
+use std::f64;
+
+// Refracts a direction about a normal in the same hemisphere, where `eta` is the
+// ratio of the index of refraction on the transmitted side to that on the
+// incident side: returns `None` on total internal reflection
+pub fn refract(wo: &Vector, n: &Vector, eta: f64) -> Option<Vector> {
+    let cos_theta_i = wo.dot(n);
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i).max(0.0) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return None;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+    Some(-*wo / eta + *n * (cos_theta_i / eta - cos_theta_t))
+}
+
+// Reflectance at an interface between two dielectrics, where `eta` is the ratio of
+// the index of refraction on the transmitted side to that on the incident side
+// and `cos_theta_i` is measured on the incident side (negative values are treated
+// as arriving from the other side)
+pub fn dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+    let cos_theta_i = cos_theta_i.min(1.0);
+
+    // Compute the angle of refraction using Snell's law, checking for total
+    // internal reflection
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let parallel = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let perpendicular = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    0.5 * (parallel * parallel + perpendicular * perpendicular)
+}
+
+// Reflectance at an interface between a dielectric and a conductor, whose complex
+// index of refraction is `eta + i k` (relative to the dielectric)
+pub fn conductor(cos_theta_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cos_theta_i.clamp(0.0, 1.0).powi(2);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_theta_i.clamp(0.0, 1.0) * a;
+    let perpendicular = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let parallel = perpendicular * (t3 - t4) / (t3 + t4);
+    0.5 * (parallel + perpendicular)
+}
+
+// Evaluates the conductor Fresnel equations for each color channel
+pub fn conductor_rgb(cos_theta_i: f64, eta: &Vector, k: &Vector) -> Vector {
+    Vector::new(conductor(cos_theta_i, eta.x, k.x),
+                conductor(cos_theta_i, eta.y, k.y),
+                conductor(cos_theta_i, eta.z, k.z))
+}
+
+// A minimal complex number, for the amplitudes of waves
+#[derive(Copy, Clone, Debug)]
+struct Complex {
+    re: f64,
+    im: f64,
+}
+
+impl Complex {
+    fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    fn add(self, rhs: Complex) -> Complex {
+        Complex::new(self.re + rhs.re, self.im + rhs.im)
+    }
+
+    fn sub(self, rhs: Complex) -> Complex {
+        Complex::new(self.re - rhs.re, self.im - rhs.im)
+    }
+
+    fn mul(self, rhs: Complex) -> Complex {
+        Complex::new(self.re * rhs.re - self.im * rhs.im, self.re * rhs.im + self.im * rhs.re)
+    }
+
+    fn div(self, rhs: Complex) -> Complex {
+        let denominator = rhs.norm2();
+        Complex::new((self.re * rhs.re + self.im * rhs.im) / denominator,
+                     (self.im * rhs.re - self.re * rhs.im) / denominator)
+    }
+
+    fn norm2(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    // The principal square root, whose real part is non-negative
+    fn sqrt(self) -> Complex {
+        let r = self.norm2().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    // Computes e^(i phi) for a complex phase, which decays when the phase has a
+    // positive imaginary part
+    fn exp_i(self) -> Complex {
+        let magnitude = (-self.im).exp();
+        Complex::new(magnitude * self.re.cos(), magnitude * self.re.sin())
+    }
+}
+
+// Reflectance of a thin dielectric film on top of a substrate, seen from a medium
+// with an index of refraction of one, which sums the waves that are reflected back
+// and forth within the film in closed form (Airy's formula): `thickness` and
+// `lambda` are in nanometers, and the substrate's index of refraction is
+// `eta + i k`, i.e. it is a conductor if `k` is non-zero
+pub fn thin_film(cos_theta_i: f64, film_ior: f64, thickness: f64, eta: f64, k: f64, lambda: f64) -> f64 {
+    let one = Complex::new(1.0, 0.0);
+    let cos_theta_i = cos_theta_i.clamp(0.0, 1.0);
+    let sin2_theta_i = Complex::new(1.0 - cos_theta_i * cos_theta_i, 0.0);
+    let n = [one, Complex::new(film_ior, 0.0), Complex::new(eta, k)];
+
+    // The cosines of the angles in each medium, which are complex when light is
+    // evanescent or absorbed
+    let cosine = |n: Complex| one.sub(sin2_theta_i.div(n.mul(n))).sqrt();
+    let cos_theta = [Complex::new(cos_theta_i, 0.0), cosine(n[1]), cosine(n[2])];
+
+    // The amplitude reflection coefficients at each interface, for light that is
+    // polarized perpendicular and parallel to the plane of incidence
+    let perpendicular = |i: usize, j: usize| {
+        let (a, b) = (n[i].mul(cos_theta[i]), n[j].mul(cos_theta[j]));
+        a.sub(b).div(a.add(b))
+    };
+    let parallel = |i: usize, j: usize| {
+        let (a, b) = (n[j].mul(cos_theta[i]), n[i].mul(cos_theta[j]));
+        a.sub(b).div(a.add(b))
+    };
+
+    // The phase difference of one round trip through the film
+    let phase = n[1].mul(cos_theta[1]).mul(Complex::new(4.0 * f64::consts::PI * thickness / lambda, 0.0));
+    let shift = phase.exp_i();
+    let airy = |r12: Complex, r23: Complex| {
+        let r23 = r23.mul(shift);
+        r12.add(r23).div(one.add(r12.mul(r23))).norm2()
+    };
+    0.5 * (airy(perpendicular(0, 1), perpendicular(1, 2)) + airy(parallel(0, 1), parallel(1, 2)))
+}
+
+// Evaluates the reflectance of a thin film at every visible wavelength and
+// converts the result to RGB, where the substrate's index of refraction is given
+// for each color channel
+pub fn thin_film_rgb(cos_theta_i: f64, film_ior: f64, thickness: f64, eta: &Vector, k: &Vector) -> Vector {
+    spectrum::reflectance_to_rgb(|lambda| {
+        thin_film(cos_theta_i,
+                  film_ior,
+                  thickness,
+                  spectrum::rgb_at_wavelength(eta, lambda),
+                  spectrum::rgb_at_wavelength(k, lambda),
+                  lambda)
+    })
+}
+
+#[test]
+fn test_thin_film() {
+    // A film without thickness has no effect, while a quarter-wave coating with
+    // an index of refraction of sqrt(eta) cancels reflections at its wavelength
+    for &cos_theta in &[1.0, 0.7, 0.2] {
+        assert!((thin_film(cos_theta, 1.38, 0.0, 1.5, 0.0, 550.0) - dielectric(cos_theta, 1.5)).abs() < 1.0e-9);
+        assert!((thin_film(cos_theta, 1.38, 0.0, 0.2, 3.9, 550.0) - conductor(cos_theta, 0.2, 3.9)).abs() < 1.0e-9);
+    }
+    let ior = 1.5f64.sqrt();
+    assert!(thin_film(1.0, ior, 550.0 / (4.0 * ior), 1.5, 0.0, 550.0) < 1.0e-9);
+}
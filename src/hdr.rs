@@ -0,0 +1,197 @@
+// A decoder for Radiance HDR images, which store RGBE pixels (three 8-bit
+// mantissas that share an 8-bit exponent), either flat or run-length encoded:
+// only the XYZE format and flipped or transposed orientations are not supported
+use vector::Vector;
+use image::{Image, invalid_data};
+
+use std::io;
+
+pub const MAGIC: [u8; 2] = [b'#', b'?'];
+
+// Scanlines of this width and above may use the newer run-length encoding,
+// which stores each component separately
+const MIN_ENCODED_WIDTH: usize = 8;
+const MAX_ENCODED_WIDTH: usize = 0x7fff;
+
+// The most pixels that a single 4-byte run can repeat, which bounds how many
+// pixels a file can plausibly describe: only files that chain runs to repeat a
+// pixel more than this many times per run are rejected
+const MAX_RUN: usize = 255;
+
+fn rgbe_to_rgb(rgbe: &[u8]) -> Vector {
+    if rgbe[3] == 0 {
+        return Vector::zero();
+    }
+    let scale = 2.0f64.powi(i32::from(rgbe[3]) - (128 + 8));
+    Vector::new((f64::from(rgbe[0]) + 0.5) * scale,
+                (f64::from(rgbe[1]) + 0.5) * scale,
+                (f64::from(rgbe[2]) + 0.5) * scale)
+}
+
+// Reads a line of the header, without its terminating newline
+fn next_line<'a>(bytes: &'a [u8], cursor: &mut usize) -> io::Result<&'a str> {
+    let start = *cursor;
+    while *cursor < bytes.len() && bytes[*cursor] != b'\n' {
+        *cursor += 1;
+    }
+    if *cursor == bytes.len() {
+        return Err(invalid_data("unexpected end of hdr header"));
+    }
+    *cursor += 1;
+    ::std::str::from_utf8(&bytes[start..*cursor - 1]).map_err(|_| invalid_data("malformed hdr header"))
+}
+
+// Decodes a single scanline of RGBE pixels, returning the number of bytes read
+fn decode_scanline(bytes: &[u8], scanline: &mut [[u8; 4]]) -> io::Result<usize> {
+    let width = scanline.len();
+    let truncated = || invalid_data("truncated hdr pixel data");
+
+    // Newer run-length encoded scanlines start with two 2s and the width
+    if (MIN_ENCODED_WIDTH..=MAX_ENCODED_WIDTH).contains(&width) && bytes.len() >= 4 && bytes[0] == 2 &&
+       bytes[1] == 2 && bytes[2] & 0x80 == 0 {
+        if ((bytes[2] as usize) << 8 | bytes[3] as usize) != width {
+            return Err(invalid_data("hdr scanline width mismatch"));
+        }
+        let mut cursor = 4;
+        for component in 0..4 {
+            let mut x = 0;
+            while x < width {
+                let count = *bytes.get(cursor).ok_or_else(truncated)? as usize;
+                cursor += 1;
+                if count > 128 {
+                    // A run of the same value
+                    let count = count - 128;
+                    let value = *bytes.get(cursor).ok_or_else(truncated)?;
+                    cursor += 1;
+                    if count > width - x {
+                        return Err(invalid_data("hdr run overflows scanline"));
+                    }
+                    for pixel in &mut scanline[x..x + count] {
+                        pixel[component] = value;
+                    }
+                    x += count;
+                } else {
+                    // A sequence of literal values
+                    if count == 0 || count > width - x {
+                        return Err(invalid_data("invalid hdr literal count"));
+                    }
+                    let values = bytes.get(cursor..cursor + count).ok_or_else(truncated)?;
+                    cursor += count;
+                    for (pixel, &value) in scanline[x..x + count].iter_mut().zip(values) {
+                        pixel[component] = value;
+                    }
+                    x += count;
+                }
+            }
+        }
+        return Ok(cursor);
+    }
+
+    // Otherwise, pixels are stored as they are, except that a pixel of three 1s
+    // repeats the previous one a number of times given by its exponent, with
+    // consecutive repeats forming the higher bits of the count
+    let (mut cursor, mut x, mut shift) = (0, 0, 0);
+    while x < width {
+        let pixel = bytes.get(cursor..cursor + 4).ok_or_else(truncated)?;
+        cursor += 4;
+        if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+            if x == 0 {
+                return Err(invalid_data("hdr run without a preceding pixel"));
+            }
+            let count = (pixel[3] as usize)
+                .checked_shl(shift)
+                .ok_or_else(|| invalid_data("hdr run overflows scanline"))?;
+            if count > width - x {
+                return Err(invalid_data("hdr run overflows scanline"));
+            }
+            let previous = scanline[x - 1];
+            for repeated in &mut scanline[x..x + count] {
+                *repeated = previous;
+            }
+            x += count;
+            shift += 8;
+        } else {
+            scanline[x].copy_from_slice(pixel);
+            x += 1;
+            shift = 0;
+        }
+    }
+    Ok(cursor)
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+    if !bytes.starts_with(&MAGIC) {
+        return Err(invalid_data("missing hdr signature"));
+    }
+
+    // The header is a list of variables that ends with an empty line
+    let mut cursor = 0;
+    next_line(bytes, &mut cursor)?;
+    loop {
+        let line = next_line(bytes, &mut cursor)?;
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with("FORMAT=") && line != "FORMAT=32-bit_rle_rgbe" {
+            return Err(invalid_data("unsupported hdr pixel format"));
+        }
+    }
+
+    // The resolution string gives the order in which scanlines are stored
+    let resolution = next_line(bytes, &mut cursor)?;
+    let tokens: Vec<&str> = resolution.split_whitespace().collect();
+    if tokens.len() != 4 || tokens[2] != "+X" || (tokens[0] != "-Y" && tokens[0] != "+Y") {
+        return Err(invalid_data("unsupported hdr orientation"));
+    }
+    let parse = |token: &str| token.parse::<usize>().map_err(|_| invalid_data("malformed hdr resolution"));
+    let (height, width) = (parse(tokens[1])?, parse(tokens[3])?);
+    let bottom_up = tokens[0] == "+Y";
+
+    // Check that the remaining bytes could hold that many pixels before allocating
+    // them: every scanline takes at least one 4-byte pixel, and every other 4
+    // bytes add at most one run of pixels
+    let remaining = bytes.len() - cursor;
+    let pixels = width.checked_mul(height).ok_or_else(|| invalid_data("invalid hdr resolution"))?;
+    if height > remaining / 4 || pixels / MAX_RUN > remaining / 4 {
+        return Err(invalid_data("hdr resolution doesn't match its pixel data"));
+    }
+
+    let mut image = Image::new(width, height);
+    let mut scanline = vec![[0u8; 4]; width];
+    for row in 0..height {
+        cursor += decode_scanline(&bytes[cursor..], &mut scanline)?;
+        let y = if bottom_up { height - 1 - row } else { row };
+        for (x, rgbe) in scanline.iter().enumerate() {
+            image.set(x, y, &rgbe_to_rgb(rgbe));
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_decode_hdr() {
+    // A 9 x 2 image: the first scanline is run-length encoded, with a run of
+    // eight pixels followed by a literal, and the second is stored flat with a
+    // repeated pixel
+    let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 9\n".to_vec();
+    bytes.extend_from_slice(&[2, 2, 0, 9]);
+    for &(run, last) in &[(128, 64), (0, 0), (255, 0), (129, 130)] {
+        bytes.extend_from_slice(&[128 + 8, run, 1, last]);
+    }
+    bytes.extend_from_slice(&[128, 64, 32, 129]);
+    bytes.extend_from_slice(&[1, 1, 1, 8]);
+    let image = Image::decode(&bytes).unwrap();
+    assert_eq!((image.width, image.height), (9, 2));
+
+    let close = |a: Vector, b: Vector| (a - b).length() < 1.0e-12;
+    assert!(close(image.get(0, 0), Vector::new(128.5, 0.5, 255.5) * 2.0 / 256.0));
+    assert!(close(image.get(8, 0), Vector::new(64.5, 0.5, 0.5) * 4.0 / 256.0));
+    assert!(close(image.get(8, 1), Vector::new(128.5, 64.5, 32.5) * 2.0 / 256.0));
+
+    // Resolutions that the pixel data can't hold are rejected before allocating
+    for resolution in &["-Y 99999999999 +X 99999999999", "-Y 1000000 +X 1000000", "-Y 10 +X 100000"] {
+        let mut bad = format!("#?RADIANCE\n\n{}\n", resolution).into_bytes();
+        bad.extend_from_slice(&bytes[bytes.len() - 40..]);
+        assert!(Image::decode(&bad).is_err());
+    }
+}
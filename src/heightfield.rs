@@ -0,0 +1,192 @@
+use vector::Vector;
+use ray::Ray;
+use bounds::BoundingBox;
+use image::Image;
+use shape::{Shape, DifferentialGeometry, EPSILON, intersect_triangle};
+
+use std::f64;
+use std::io;
+use std::path::Path;
+
+// A regular grid of height samples spanning the xz-extents of `bounds`: each
+// sample is in the range 0..1 and is mapped to the y-extents of `bounds`
+pub struct Heightfield {
+    pub bounds: BoundingBox,
+    // The number of samples along the x- and z-axes, respectively
+    pub resolution: (usize, usize),
+    pub heights: Vec<f64>,
+}
+
+impl Heightfield {
+    pub fn new(bounds: &BoundingBox, resolution: (usize, usize), heights: Vec<f64>) -> Heightfield {
+        assert!(resolution.0 >= 2 && resolution.1 >= 2,
+                "heightfields require at least 2 x 2 samples");
+        assert_eq!(heights.len(), resolution.0 * resolution.1);
+        Heightfield {
+            bounds: *bounds,
+            resolution,
+            heights,
+        }
+    }
+
+    // Builds a heightfield from the luminance of a grayscale image, where the
+    // top row of the image lies along the minimum z-extent of `bounds`
+    pub fn from_image(path: &Path, bounds: &BoundingBox) -> io::Result<Heightfield> {
+        let image = Image::load(path)?;
+        if image.width < 2 || image.height < 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "heightfield images must be at least 2 x 2 pixels"));
+        }
+        let heights = image.pixels.iter().map(|p| p.luminance()).collect();
+        Ok(Heightfield::new(bounds, (image.width, image.height), heights))
+    }
+
+    // The size of a single grid cell along the x- and z-axes
+    fn cell_size(&self) -> (f64, f64) {
+        let diagonal = self.bounds.diagonal();
+        (diagonal.x / (self.resolution.0 - 1) as f64, diagonal.z / (self.resolution.1 - 1) as f64)
+    }
+
+    // The world-space position of the sample at grid coordinates (i, j)
+    fn vertex(&self, i: usize, j: usize) -> Vector {
+        let (dx, dz) = self.cell_size();
+        Vector::new(self.bounds.min.x + i as f64 * dx,
+                    self.bounds.min.y + self.height(i, j),
+                    self.bounds.min.z + j as f64 * dz)
+    }
+
+    fn height(&self, i: usize, j: usize) -> f64 {
+        self.heights[j * self.resolution.0 + i] * self.bounds.diagonal().y
+    }
+
+    // The smooth normal at grid coordinates (i, j), estimated from the slope of
+    // the neighboring samples
+    fn vertex_normal(&self, i: usize, j: usize) -> Vector {
+        let (dx, dz) = self.cell_size();
+        let (i0, i1) = (i.saturating_sub(1), (i + 1).min(self.resolution.0 - 1));
+        let (j0, j1) = (j.saturating_sub(1), (j + 1).min(self.resolution.1 - 1));
+        let dhdx = (self.height(i1, j) - self.height(i0, j)) / ((i1 - i0) as f64 * dx);
+        let dhdz = (self.height(i, j1) - self.height(i, j0)) / ((j1 - j0) as f64 * dz);
+        Vector::new(-dhdx, 1.0, -dhdz).normalize()
+    }
+
+    // Intersects the two triangles that make up the cell with corner (i, j),
+    // returning the distance along the ray, the face normal and the interpolated
+    // vertex normal
+    fn intersect_cell(&self, r: &Ray, i: usize, j: usize) -> Option<(f64, Vector, Vector)> {
+        let corners = [(i, j), (i + 1, j), (i, j + 1), (i + 1, j + 1)];
+
+        let mut closest: Option<(f64, Vector, Vector)> = None;
+        for &(a, b, c) in &[(0, 2, 3), (0, 3, 1)] {
+            let (p0, p1, p2) = (self.vertex(corners[a].0, corners[a].1),
+                                self.vertex(corners[b].0, corners[b].1),
+                                self.vertex(corners[c].0, corners[c].1));
+            if let Some((t, b1, b2)) = intersect_triangle(r, &p0, &p1, &p2) {
+                if t > EPSILON && closest.is_none_or(|(closest_t, _, _)| t < closest_t) {
+                    // The winding order is chosen such that the normal faces up
+                    let normal = (p1 - p0).cross(&(p2 - p0)).normalize();
+                    let shading_normal = self.vertex_normal(corners[a].0, corners[a].1) *
+                                         (1.0 - b1 - b2) +
+                                         self.vertex_normal(corners[b].0, corners[b].1) * b1 +
+                                         self.vertex_normal(corners[c].0, corners[c].1) * b2;
+                    closest = Some((t, normal, shading_normal.normalize()));
+                }
+            }
+        }
+        closest
+    }
+
+    fn geometry_at(&self, r: &Ray, t: f64, n: &Vector, ns: &Vector) -> DifferentialGeometry<'_> {
+        // The uv-coordinates span the grid, with v = 1 along the minimum z-extent
+        // so that images are not flipped
+        let position = r.point_at(t);
+        let diagonal = self.bounds.diagonal();
+        let uv = ((position.x - self.bounds.min.x) / diagonal.x,
+                  1.0 - (position.z - self.bounds.min.z) / diagonal.z);
+
+        // Moving along u or v changes the height according to the slope of the
+        // triangle, which follows from the plane equation dot(n, dp) = 0
+        let dpdu = Vector::new(diagonal.x, -n.x * diagonal.x / n.y, 0.0);
+        let dpdv = Vector::new(0.0, n.z * diagonal.z / n.y, -diagonal.z);
+
+        let mut dg = DifferentialGeometry::new(r, t, n, uv, &dpdu, &dpdv, self);
+        dg.set_shading_normal(ns);
+        dg
+    }
+}
+
+impl Shape for Heightfield {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let (t_near, t_far) = self.bounds.intersect(r)?;
+        if t_far < EPSILON {
+            return None;
+        }
+
+        // Find the cell where the ray enters the grid
+        let t_start = t_near.max(0.0);
+        let start = r.point_at(t_start);
+        let (dx, dz) = self.cell_size();
+        let (nx, nz) = (self.resolution.0 - 1, self.resolution.1 - 1);
+        let cell_of = |offset: f64, size: f64, count: usize| {
+            ((offset / size).floor().max(0.0) as usize).min(count - 1)
+        };
+        let mut i = cell_of(start.x - self.bounds.min.x, dx, nx);
+        let mut j = cell_of(start.z - self.bounds.min.z, dz, nz);
+
+        // Set up a 2D digital differential analyzer (DDA) that walks through the
+        // cells of the grid in the order that the ray passes through them: for each
+        // axis, we track the distance along the ray at which the next cell boundary
+        // is crossed and the distance between successive boundaries
+        let setup = |origin: f64, direction: f64, min: f64, size: f64, cell: usize| {
+            if direction > 0.0 {
+                let boundary = min + (cell + 1) as f64 * size;
+                (t_start + (boundary - origin) / direction, size / direction, 1isize)
+            } else if direction < 0.0 {
+                let boundary = min + cell as f64 * size;
+                (t_start + (boundary - origin) / direction, -size / direction, -1isize)
+            } else {
+                (f64::INFINITY, f64::INFINITY, 0isize)
+            }
+        };
+        let (mut next_x, delta_x, step_x) = setup(start.x, r.direction.x, self.bounds.min.x, dx, i);
+        let (mut next_z, delta_z, step_z) = setup(start.z, r.direction.z, self.bounds.min.z, dz, j);
+
+        loop {
+            // The triangles of each cell lie entirely within its footprint, so the
+            // first cell that reports a hit contains the closest hit
+            if let Some((t, normal, shading_normal)) = self.intersect_cell(r, i, j) {
+                return Some(self.geometry_at(r, t, &normal, &shading_normal));
+            }
+
+            // Advance to the next cell along whichever axis has the nearest boundary
+            if next_x < next_z {
+                if next_x > t_far || (step_x < 0 && i == 0) || (step_x > 0 && i + 1 == nx) {
+                    return None;
+                }
+                i = (i as isize + step_x) as usize;
+                next_x += delta_x;
+            } else {
+                if next_z > t_far || (step_z < 0 && j == 0) || (step_z > 0 && j + 1 == nz) {
+                    return None;
+                }
+                j = (j as isize + step_z) as usize;
+                next_z += delta_z;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_heightfield_traversal() {
+    // A ramp that rises from y = 0 at x = 0 to y = 1 at x = 1
+    let bounds = BoundingBox::new(&Vector::zero(), &Vector::one());
+    let heights = vec![0.0, 0.5, 1.0, 0.0, 0.5, 1.0, 0.0, 0.5, 1.0];
+    let heightfield = Heightfield::new(&bounds, (3, 3), heights);
+
+    // A horizontal ray travelling along the x-axis should pass through the first
+    // cell before hitting the ramp at x = 0.75
+    let r = Ray::new(&Vector::new(-1.0, 0.75, 0.3), &Vector::new(1.0, 0.0, 0.0), 0.0, f64::MAX);
+    let dg = heightfield.intersect(&r).unwrap();
+    assert!((dg.position.x - 0.75).abs() < 1.0e-9);
+    assert!(dg.normal.y > 0.0 && dg.normal.x < 0.0);
+}
@@ -0,0 +1,230 @@
+// A parser for IES LM-63 photometric data, which describes how the luminous
+// intensity of a real light fixture varies with direction: only type C
+// photometry is supported, which is what nearly all architectural fixtures use
+use image::invalid_data;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+// Luminous intensity in candela, tabulated over vertical angles measured from the
+// nadir (the direction in which the fixture points) and horizontal angles around
+// it, both in degrees
+pub struct PhotometricProfile {
+    pub vertical_angles: Vec<f64>,
+    pub horizontal_angles: Vec<f64>,
+    // One row of values per horizontal angle, each with one value per vertical
+    // angle, including the candela multiplier and ballast factors
+    pub candela: Vec<Vec<f64>>,
+}
+
+// The numbers that follow the header of an ies file
+struct Numbers {
+    values: Vec<f64>,
+    cursor: usize,
+}
+
+impl Numbers {
+    fn next(&mut self) -> io::Result<f64> {
+        let value = *self.values
+            .get(self.cursor)
+            .ok_or_else(|| invalid_data("unexpected end of ies file"))?;
+        self.cursor += 1;
+        Ok(value)
+    }
+
+    // Reads the length of a list that follows, which can't be longer than the
+    // numbers that are left
+    fn count(&mut self) -> io::Result<usize> {
+        let value = self.next()?;
+        let remaining = self.values.len() - self.cursor;
+        if value < 0.0 || value.fract() != 0.0 || value > remaining as f64 {
+            return Err(invalid_data("invalid count in ies file"));
+        }
+        Ok(value as usize)
+    }
+}
+
+// Finds the segment of a sorted table that contains `x` and the position of `x`
+// within it, clamping values outside of the table to its ends
+fn locate(table: &[f64], x: f64) -> (usize, f64) {
+    if table.len() < 2 || x <= table[0] {
+        return (0, 0.0);
+    }
+    let last = table.len() - 1;
+    if x >= table[last] {
+        return (last - 1, 1.0);
+    }
+    let i = table.iter().position(|&angle| angle > x).unwrap_or(last) - 1;
+    (i, (x - table[i]) / (table[i + 1] - table[i]))
+}
+
+impl PhotometricProfile {
+    pub fn load(path: &Path) -> io::Result<PhotometricProfile> {
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+        PhotometricProfile::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> io::Result<PhotometricProfile> {
+        // The header consists of keywords, and ends with the line that describes
+        // how the lamp is tilted
+        let mut lines = text.lines();
+        let tilt = loop {
+            match lines.next() {
+                Some(line) if line.trim().starts_with("TILT=") => break line.trim()["TILT=".len()..].to_string(),
+                Some(_) => (),
+                None => return Err(invalid_data("missing TILT line in ies file")),
+            }
+        };
+
+        // Everything after it is a list of numbers, regardless of line breaks
+        let mut numbers = Vec::new();
+        for token in lines.flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ',')) {
+            if !token.is_empty() {
+                numbers.push(token.parse::<f64>().map_err(|_| invalid_data("malformed number in ies file"))?);
+            }
+        }
+        let mut numbers = Numbers {
+            values: numbers,
+            cursor: 0,
+        };
+
+        // Tilt data only matters for lamps whose output depends on their
+        // orientation, so it is skipped
+        match tilt.as_str() {
+            "NONE" => (),
+            "INCLUDE" => {
+                numbers.next()?;
+                let values = numbers.count()?
+                    .checked_mul(2)
+                    .ok_or_else(|| invalid_data("invalid count in ies file"))?;
+                for _ in 0..values {
+                    numbers.next()?;
+                }
+            }
+            _ => return Err(invalid_data("external ies tilt files are not supported")),
+        }
+
+        let _lamps = numbers.next()?;
+        let _lumens_per_lamp = numbers.next()?;
+        let multiplier = numbers.next()?;
+        let vertical_count = numbers.count()?;
+        let horizontal_count = numbers.count()?;
+        let photometric_type = numbers.next()?;
+        let _units = numbers.next()?;
+        let (_width, _length, _height) = (numbers.next()?, numbers.next()?, numbers.next()?);
+        let ballast_factor = numbers.next()?;
+        let ballast_lamp_factor = numbers.next()?;
+        let _input_watts = numbers.next()?;
+        if photometric_type != 1.0 {
+            return Err(invalid_data("only type C ies photometry is supported"));
+        }
+        if vertical_count == 0 || horizontal_count == 0 {
+            return Err(invalid_data("empty ies candela table"));
+        }
+
+        let mut list = |count: usize, scale: f64| {
+            (0..count).map(|_| Ok(numbers.next()? * scale)).collect::<io::Result<Vec<f64>>>()
+        };
+        let vertical_angles = list(vertical_count, 1.0)?;
+        let horizontal_angles = list(horizontal_count, 1.0)?;
+        let scale = multiplier * ballast_factor * ballast_lamp_factor;
+        let mut candela = Vec::with_capacity(horizontal_count);
+        for _ in 0..horizontal_count {
+            candela.push(list(vertical_count, scale)?);
+        }
+        let sorted = |angles: &[f64]| angles.windows(2).all(|pair| pair[0] < pair[1]);
+        if !sorted(&vertical_angles) || !sorted(&horizontal_angles) {
+            return Err(invalid_data("ies angles must be increasing"));
+        }
+        Ok(PhotometricProfile {
+            vertical_angles,
+            horizontal_angles,
+            candela,
+        })
+    }
+
+    // The luminous intensity in a direction given by its vertical and horizontal
+    // angles in degrees, which is zero outside of the measured vertical angles
+    pub fn intensity(&self, vertical: f64, horizontal: f64) -> f64 {
+        let first_vertical = self.vertical_angles[0];
+        let last_vertical = self.vertical_angles[self.vertical_angles.len() - 1];
+        if vertical < first_vertical || vertical > last_vertical {
+            return 0.0;
+        }
+
+        // Only part of the distribution is stored for symmetric fixtures: the
+        // last horizontal angle tells which symmetry applies
+        let mut horizontal = horizontal.rem_euclid(360.0);
+        let first_horizontal = self.horizontal_angles[0];
+        let last_horizontal = self.horizontal_angles[self.horizontal_angles.len() - 1];
+        if last_horizontal == 90.0 {
+            // Symmetric in each quadrant
+            if horizontal > 180.0 {
+                horizontal = 360.0 - horizontal;
+            }
+            if horizontal > 90.0 {
+                horizontal = 180.0 - horizontal;
+            }
+        } else if last_horizontal == 180.0 {
+            // Symmetric about the 0-180 degree plane
+            if horizontal > 180.0 {
+                horizontal = 360.0 - horizontal;
+            }
+        } else if first_horizontal == 90.0 && last_horizontal == 270.0 {
+            // Symmetric about the 90-270 degree plane
+            if !(90.0..=270.0).contains(&horizontal) {
+                horizontal = (180.0 - horizontal).rem_euclid(360.0);
+            }
+        }
+
+        let (v, tv) = locate(&self.vertical_angles, vertical);
+        let (h, th) = locate(&self.horizontal_angles, horizontal);
+        let row = |h: usize| {
+            let row = &self.candela[h];
+            if row.len() < 2 {
+                row[0]
+            } else {
+                row[v] * (1.0 - tv) + row[v + 1] * tv
+            }
+        };
+        if self.horizontal_angles.len() < 2 {
+            row(0)
+        } else {
+            row(h) * (1.0 - th) + row(h + 1) * th
+        }
+    }
+}
+
+#[test]
+fn test_parse_ies() {
+    let profile = PhotometricProfile::parse("IESNA:LM-63-2002\n\
+                                             [TEST] a quadrant-symmetric fixture\n\
+                                             TILT=NONE\n\
+                                             1 1000 2.0 3 2 1 2 0.1 0.1 0.0\n\
+                                             0.5 1.0 10\n\
+                                             0 45 90\n\
+                                             0 90\n\
+                                             100 50 0\n\
+                                             200 100 0\n")
+        .unwrap();
+    assert_eq!(profile.vertical_angles, vec![0.0, 45.0, 90.0]);
+
+    // Values are scaled by the candela multiplier and ballast factor, and are
+    // interpolated between the measured angles
+    assert!((profile.intensity(0.0, 0.0) - 100.0).abs() < 1.0e-9);
+    assert!((profile.intensity(22.5, 0.0) - 75.0).abs() < 1.0e-9);
+    assert!((profile.intensity(0.0, 45.0) - 150.0).abs() < 1.0e-9);
+    assert_eq!(profile.intensity(120.0, 0.0), 0.0);
+
+    // The missing quadrants mirror the measured one
+    for &(mirrored, measured) in &[(135.0, 45.0), (270.0, 90.0), (330.0, 30.0), (-60.0, 60.0)] {
+        assert!((profile.intensity(30.0, mirrored) - profile.intensity(30.0, measured)).abs() < 1.0e-9);
+    }
+
+    assert!(PhotometricProfile::parse("TILT=NONE\n1 1000 1 3 2 2 2 0 0 0\n1 1 10\n").is_err());
+    assert!(PhotometricProfile::parse("TILT=INCLUDE\n1 1e30\n").is_err());
+    assert!(PhotometricProfile::parse("TILT=NONE\n1 1000 1 3 1e18 1 2 0 0 0\n1 1 10\n0 45 90\n0\n").is_err());
+}
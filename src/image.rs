@@ -0,0 +1,256 @@
+use vector::Vector;
+use png;
+use exr;
+use hdr;
+
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+// A floating-point RGB image, stored in row-major order starting at the top-left
+// corner
+#[derive(Clone)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<Vector>,
+    // Per-pixel opacity, for formats that store it
+    pub alpha: Option<Vec<f64>>,
+}
+
+// How the values stored in an image file should be interpreted
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ColorSpace {
+    Linear,
+    // Values are gamma-encoded, as is typical of 8-bit images
+    Srgb,
+}
+
+// The error returned by every decoder and parser for malformed input
+pub fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+impl Image {
+    pub fn new(w: usize, h: usize) -> Image {
+        Image {
+            width: w,
+            height: h,
+            pixels: vec![Vector::zero(); w * h],
+            alpha: None,
+        }
+    }
+
+    // Loads an image from disk: the format is determined by the file's contents
+    // rather than its extension
+    pub fn load(path: &Path) -> io::Result<Image> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Image::decode(&bytes)
+    }
+
+    // Decodes an image stored in one of the Netpbm formats (PGM / PPM, either
+    // ASCII or binary), the Portable FloatMap format (PFM), PNG, OpenEXR or
+    // Radiance HDR: integer values are normalized to the range 0..1, and no
+    // color space conversion is applied
+    pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+        if bytes.starts_with(&png::SIGNATURE) {
+            return png::decode(bytes);
+        }
+        if bytes.starts_with(&exr::MAGIC) {
+            return exr::decode(bytes);
+        }
+        if bytes.starts_with(&hdr::MAGIC) {
+            return hdr::decode(bytes);
+        }
+        if bytes.len() < 2 || bytes[0] != b'P' {
+            return Err(invalid_data("unrecognized image format"));
+        }
+        match bytes[1] {
+            b'2' | b'3' | b'5' | b'6' => decode_netpbm(bytes),
+            b'f' | b'F' => decode_pfm(bytes),
+            _ => Err(invalid_data("unsupported netpbm variant")),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vector {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: &Vector) {
+        self.pixels[y * self.width + x] = *color;
+    }
+
+    // Converts sRGB-encoded values (i.e. from 8-bit images) to linear values
+    pub fn srgb_to_linear(&mut self) {
+        let decode = |c: f64| if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        };
+        for pixel in &mut self.pixels {
+            *pixel = Vector::new(decode(pixel.x), decode(pixel.y), decode(pixel.z));
+        }
+    }
+}
+
+// Reads whitespace-separated header tokens, skipping comments
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn next_token(&mut self) -> io::Result<&'a str> {
+        loop {
+            match self.bytes.get(self.cursor) {
+                Some(&b'#') => {
+                    while self.cursor < self.bytes.len() && self.bytes[self.cursor] != b'\n' {
+                        self.cursor += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.cursor += 1,
+                Some(_) => break,
+                None => return Err(invalid_data("unexpected end of image header")),
+            }
+        }
+        let start = self.cursor;
+        while self.cursor < self.bytes.len() && !self.bytes[self.cursor].is_ascii_whitespace() {
+            self.cursor += 1;
+        }
+        ::std::str::from_utf8(&self.bytes[start..self.cursor])
+            .map_err(|_| invalid_data("malformed image header"))
+    }
+
+    fn next_number<T: ::std::str::FromStr>(&mut self) -> io::Result<T> {
+        self.next_token()?
+            .parse()
+            .map_err(|_| invalid_data("malformed number in image"))
+    }
+
+    // The binary payload begins after exactly one whitespace character
+    fn payload(&self) -> &'a [u8] {
+        &self.bytes[(self.cursor + 1).min(self.bytes.len())..]
+    }
+}
+
+fn decode_netpbm(bytes: &[u8]) -> io::Result<Image> {
+    let mut reader = HeaderReader {
+        bytes,
+        cursor: 0,
+    };
+    let magic = reader.next_token()?;
+    let channels = if magic == "P2" || magic == "P5" { 1 } else { 3 };
+    let binary = magic == "P5" || magic == "P6";
+    let width: usize = reader.next_number()?;
+    let height: usize = reader.next_number()?;
+    let max_value: u32 = reader.next_number()?;
+    if max_value == 0 || max_value > 65535 {
+        return Err(invalid_data("invalid maximum value in netpbm header"));
+    }
+
+    // Check that the pixel data can hold the image before allocating it: binary
+    // values take one or two bytes each, and ASCII values at least one digit and
+    // one separator
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid_data("invalid netpbm dimensions"))?;
+    let mut values = Vec::new();
+    if binary {
+        let payload = reader.payload();
+        let bytes_per_value = if max_value > 255 { 2 } else { 1 };
+        if count > payload.len() / bytes_per_value {
+            return Err(invalid_data("truncated netpbm pixel data"));
+        }
+        values.reserve(count);
+        for i in 0..count {
+            values.push(if bytes_per_value == 2 {
+                // 16-bit values are stored most significant byte first
+                (u32::from(payload[2 * i]) << 8) | u32::from(payload[2 * i + 1])
+            } else {
+                u32::from(payload[i])
+            });
+        }
+    } else {
+        if count > (bytes.len() - reader.cursor).div_ceil(2) {
+            return Err(invalid_data("truncated netpbm pixel data"));
+        }
+        values.reserve(count);
+        for _ in 0..count {
+            values.push(reader.next_number()?);
+        }
+    }
+
+    let mut image = Image::new(width, height);
+    let scale = 1.0 / f64::from(max_value);
+    for (pixel, chunk) in image.pixels.iter_mut().zip(values.chunks(channels)) {
+        *pixel = if channels == 1 {
+            Vector::one() * (f64::from(chunk[0]) * scale)
+        } else {
+            Vector::new(f64::from(chunk[0]), f64::from(chunk[1]), f64::from(chunk[2])) * scale
+        };
+    }
+    Ok(image)
+}
+
+fn decode_pfm(bytes: &[u8]) -> io::Result<Image> {
+    let mut reader = HeaderReader {
+        bytes,
+        cursor: 0,
+    };
+    let channels = if reader.next_token()? == "PF" { 3 } else { 1 };
+    let width: usize = reader.next_number()?;
+    let height: usize = reader.next_number()?;
+
+    // The sign of the scale factor encodes the byte order of the data
+    let scale: f64 = reader.next_number()?;
+    let little_endian = scale < 0.0;
+
+    let payload = reader.payload();
+    let count = width
+        .checked_mul(height)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or_else(|| invalid_data("invalid pfm dimensions"))?;
+    if count > payload.len() / 4 {
+        return Err(invalid_data("truncated pfm pixel data"));
+    }
+    let value_at = |i: usize| {
+        let mut raw = [0u8; 4];
+        raw.copy_from_slice(&payload[4 * i..4 * i + 4]);
+        f64::from(if little_endian {
+            f32::from_le_bytes(raw)
+        } else {
+            f32::from_be_bytes(raw)
+        })
+    };
+
+    // Scanlines are stored from the bottom of the image to the top
+    let mut image = Image::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let i = ((height - 1 - y) * width + x) * channels;
+            let color = if channels == 1 {
+                Vector::one() * value_at(i)
+            } else {
+                Vector::new(value_at(i), value_at(i + 1), value_at(i + 2))
+            };
+            image.set(x, y, &color);
+        }
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_decode_pgm() {
+    let image = Image::decode(b"P2\n# a comment\n2 2\n4\n0 1\n2 4\n").unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.get(1, 0), Vector::one() * 0.25);
+    assert_eq!(image.get(1, 1), Vector::one());
+
+    // Dimensions that overflow or that the data can't hold are rejected
+    assert!(Image::decode(b"P5\n99999999999 99999999999\n255\n\0").is_err());
+    assert!(Image::decode(b"P2\n1000 1000\n255\n0 1 2\n").is_err());
+    assert!(Image::decode(b"PF\n4294967296 4294967296\n-1.0\n\0\0\0\0").is_err());
+}
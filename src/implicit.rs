@@ -0,0 +1,149 @@
+use vector::Vector;
+use ray::Ray;
+use bounds::BoundingBox;
+use shape::{Shape, DifferentialGeometry, EPSILON};
+
+use std::sync::Arc;
+
+// A surface defined as the zero set of an arbitrary scalar function, f(p) = 0,
+// restricted to a bounding box: unlike signed distance fields, the function
+// doesn't need to be a distance bound, so roots are found by bracketing sign
+// changes along the ray and then refining them, or, given a Lipschitz bound on
+// the function, by also subdividing every step that might touch zero
+pub struct Implicit {
+    pub function: Arc<dyn Fn(&Vector) -> f64 + Send + Sync>,
+    pub bounds: BoundingBox,
+    // The number of uniform intervals that the ray is divided into (within the
+    // bounding box) when searching for sign changes: without a Lipschitz bound,
+    // features thinner than an interval and roots where the function touches
+    // zero without changing sign (such as at grazing angles) are missed
+    pub steps: u32,
+    // The number of bisection steps used to refine each root
+    pub refinements: u32,
+    // An upper bound on how fast the function can change per unit of distance,
+    // which lets intervals without a sign change be ruled out or subdivided
+    pub lipschitz: Option<f64>,
+}
+
+// Intervals that can't be ruled out by the Lipschitz bound are subdivided until
+// they are this short, at which point they are taken to contain a root
+const TANGENT_TOLERANCE: f64 = 1.0e-6;
+
+impl Implicit {
+    pub fn new(f: Arc<dyn Fn(&Vector) -> f64 + Send + Sync>, bounds: &BoundingBox) -> Implicit {
+        Implicit {
+            function: f,
+            bounds: *bounds,
+            steps: 512,
+            refinements: 32,
+            lipschitz: None,
+        }
+    }
+
+    pub fn with_lipschitz(mut self, bound: f64) -> Implicit {
+        self.lipschitz = Some(bound);
+        self
+    }
+
+    // Finds the first root within [t0, t1], given the values of the function at
+    // both ends
+    fn find_root(&self, r: &Ray, (t0, f0): (f64, f64), (t1, f1): (f64, f64)) -> Option<f64> {
+        let f = &self.function;
+        if f0 * f1 <= 0.0 {
+            // The interval brackets a root: refine it by repeated bisection
+            let (mut lo, mut hi, mut f_lo) = (t0, t1, f0);
+            for _ in 0..self.refinements {
+                let mid = 0.5 * (lo + hi);
+                let f_mid = f(&r.point_at(mid));
+                if f_lo * f_mid <= 0.0 {
+                    hi = mid;
+                } else {
+                    lo = mid;
+                    f_lo = f_mid;
+                }
+            }
+            return Some(0.5 * (lo + hi));
+        }
+
+        // Without a sign change, the function can only reach zero in between if
+        // it is allowed to change by enough over the length of the interval
+        let bound = self.lipschitz?;
+        if f0.abs() + f1.abs() > bound * (t1 - t0) {
+            return None;
+        }
+        let mid = 0.5 * (t0 + t1);
+        if t1 - t0 < TANGENT_TOLERANCE {
+            return Some(mid);
+        }
+        let f_mid = f(&r.point_at(mid));
+        self.find_root(r, (t0, f0), (mid, f_mid)).or_else(|| self.find_root(r, (mid, f_mid), (t1, f1)))
+    }
+
+    // Estimates the gradient of the function with central differences
+    fn gradient(&self, p: &Vector) -> Vector {
+        const H: f64 = 1.0e-6;
+        let f = &self.function;
+        let dx = Vector::new(H, 0.0, 0.0);
+        let dy = Vector::new(0.0, H, 0.0);
+        let dz = Vector::new(0.0, 0.0, H);
+        Vector::new(f(&(*p + dx)) - f(&(*p - dx)),
+                    f(&(*p + dy)) - f(&(*p - dy)),
+                    f(&(*p + dz)) - f(&(*p - dz))) / (2.0 * H)
+    }
+}
+
+impl Shape for Implicit {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let (t_near, t_far) = self.bounds.intersect(r)?;
+        let t_start = t_near.max(EPSILON);
+        if t_start >= t_far {
+            return None;
+        }
+
+        let f = &self.function;
+        let step = (t_far - t_start) / f64::from(self.steps);
+        let mut t_previous = t_start;
+        let mut f_previous = f(&r.point_at(t_start));
+
+        for i in 1..(self.steps + 1) {
+            let t_current = t_start + f64::from(i) * step;
+            let f_current = f(&r.point_at(t_current));
+
+            if let Some(t) = self.find_root(r, (t_previous, f_previous), (t_current, f_current)) {
+                let position = r.point_at(t);
+
+                // The normal points toward increasing values of the function,
+                // i.e. positive values are considered to be "outside"
+                let normal = self.gradient(&position).normalize();
+                return Some(DifferentialGeometry::from_normal(r, t, &normal, self));
+            }
+            t_previous = t_current;
+            f_previous = f_current;
+        }
+        None
+    }
+}
+
+#[test]
+fn test_implicit_sphere() {
+    use std::f64;
+
+    let sphere = |p: &Vector| p.squared_length() - 1.0;
+    let bounds = BoundingBox::new(&Vector::new(-1.5, -1.5, -1.2), &Vector::new(1.5, 1.5, 1.5));
+    let implicit = Implicit::new(Arc::new(sphere), &bounds);
+
+    // An oblique ray, compared against the analytic intersection
+    let origin = Vector::new(0.3, 0.4, 5.0);
+    let r = Ray::new(&origin, &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    let dg = implicit.intersect(&r).unwrap();
+    let t = 5.0 - (1.0f64 - 0.3 * 0.3 - 0.4 * 0.4).sqrt();
+    assert!((dg.t - t).abs() < 1.0e-9);
+    assert!((dg.normal - r.point_at(t)).length() < 1.0e-6);
+
+    // A grazing ray only touches the surface, which a Lipschitz bound finds
+    let r = Ray::new(&Vector::new(0.0, 1.0, 5.0), &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    assert!(implicit.intersect(&r).is_none());
+    let implicit = Implicit::new(Arc::new(sphere), &bounds).with_lipschitz(2.0 * 3.0f64.sqrt() * 1.5);
+    let dg = implicit.intersect(&r).unwrap();
+    assert!((dg.t - 5.0).abs() < 1.0e-2);
+}
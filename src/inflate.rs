@@ -0,0 +1,266 @@
+// A small decoder for the DEFLATE format (RFC 1951) and its zlib wrapper
+// (RFC 1950), as used by PNG and ZIP-compressed OpenEXR images: this favors
+// simplicity over speed, decoding Huffman codes one bit at a time
+use image::invalid_data;
+
+use std::io;
+
+const MAX_BITS: usize = 15;
+
+// Base lengths and extra bits for length symbols 257..285
+const LENGTH_BASE: [usize; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43,
+                                  51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA: [u32; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4,
+                                 4, 4, 5, 5, 5, 5, 0];
+
+// Base distances and extra bits for distance symbols 0..29
+const DISTANCE_BASE: [usize; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257,
+                                    385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193,
+                                    12289, 16385, 24577];
+const DISTANCE_EXTRA: [u32; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9,
+                                   10, 10, 11, 11, 12, 12, 13, 13];
+
+// The order in which code length code lengths are stored in a dynamic block
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14,
+                                        1, 15];
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn bits(&mut self, count: u32) -> io::Result<u32> {
+        while self.bit_count < count {
+            let byte = *self.bytes
+                .get(self.position)
+                .ok_or_else(|| invalid_data("unexpected end of compressed data"))?;
+            self.position += 1;
+            self.bit_buffer |= u32::from(byte) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1u64 << count) - 1) as u32;
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    // Discards any remaining bits in the current byte
+    fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+// A canonical Huffman code, described by the number of codes of each length and
+// the symbols ordered by code
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Huffman> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Check for an over-subscribed code
+        let mut left = 1i32;
+        for &count in counts.iter().skip(1) {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid_data("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 1];
+        for length in 1..MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> io::Result<usize> {
+        // Codes of each length are consecutive, so we can walk through the
+        // lengths one bit at a time
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..(MAX_BITS + 1) {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(self.counts[length]);
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize] as usize);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid huffman code"))
+    }
+}
+
+fn inflate_codes(reader: &mut BitReader,
+                 output: &mut Vec<u8>,
+                 lengths: &Huffman,
+                 distances: &Huffman)
+                 -> io::Result<()> {
+    loop {
+        let symbol = lengths.decode(reader)?;
+        if symbol < 256 {
+            output.push(symbol as u8);
+        } else if symbol == 256 {
+            return Ok(());
+        } else {
+            let symbol = symbol - 257;
+            if symbol >= LENGTH_BASE.len() {
+                return Err(invalid_data("invalid length symbol"));
+            }
+            let length = LENGTH_BASE[symbol] + reader.bits(LENGTH_EXTRA[symbol])? as usize;
+
+            let symbol = distances.decode(reader)?;
+            if symbol >= DISTANCE_BASE.len() {
+                return Err(invalid_data("invalid distance symbol"));
+            }
+            let distance = DISTANCE_BASE[symbol] + reader.bits(DISTANCE_EXTRA[symbol])? as usize;
+            if distance > output.len() {
+                return Err(invalid_data("distance too far back"));
+            }
+
+            // The copied region may overlap the bytes being written
+            let start = output.len() - distance;
+            for i in 0..length {
+                let byte = output[start + i];
+                output.push(byte);
+            }
+        }
+    }
+}
+
+fn fixed_codes() -> io::Result<(Huffman, Huffman)> {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5u8; 30])?))
+}
+
+fn dynamic_codes(reader: &mut BitReader) -> io::Result<(Huffman, Huffman)> {
+    let length_count = reader.bits(5)? as usize + 257;
+    let distance_count = reader.bits(5)? as usize + 1;
+    let code_count = reader.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &index in CODE_LENGTH_ORDER.iter().take(code_count) {
+        code_lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    // The code lengths of both alphabets are run-length encoded as one sequence
+    let mut lengths = vec![0u8; length_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_length_code.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if index == 0 {
+                    return Err(invalid_data("repeated code length without a previous length"));
+                }
+                (lengths[index - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if index + repeat > lengths.len() {
+            return Err(invalid_data("too many code lengths"));
+        }
+        for length in &mut lengths[index..index + repeat] {
+            *length = value;
+        }
+        index += repeat;
+    }
+
+    Ok((Huffman::new(&lengths[..length_count])?, Huffman::new(&lengths[length_count..])?))
+}
+
+// Decompresses a raw DEFLATE stream
+pub fn inflate(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let mut reader = BitReader {
+        bytes,
+        position: 0,
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    let mut output = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => {
+                // Stored (uncompressed) block
+                reader.align();
+                let header = reader.bytes
+                    .get(reader.position..reader.position + 4)
+                    .ok_or_else(|| invalid_data("unexpected end of compressed data"))?;
+                let length = usize::from(header[0]) | (usize::from(header[1]) << 8);
+                let complement = usize::from(header[2]) | (usize::from(header[3]) << 8);
+                if length != !complement & 0xffff {
+                    return Err(invalid_data("corrupt stored block length"));
+                }
+                let start = reader.position + 4;
+                let data = reader.bytes
+                    .get(start..start + length)
+                    .ok_or_else(|| invalid_data("unexpected end of compressed data"))?;
+                output.extend_from_slice(data);
+                reader.position = start + length;
+            }
+            1 => {
+                let (lengths, distances) = fixed_codes()?;
+                inflate_codes(&mut reader, &mut output, &lengths, &distances)?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_codes(&mut reader)?;
+                inflate_codes(&mut reader, &mut output, &lengths, &distances)?;
+            }
+            _ => return Err(invalid_data("invalid deflate block type")),
+        }
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+// Decompresses a zlib stream: the trailing checksum is not verified
+pub fn zlib_decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    if bytes.len() < 2 || bytes[0] & 0x0f != 8 || (u16::from(bytes[0]) << 8 | u16::from(bytes[1])) % 31 != 0 {
+        return Err(invalid_data("invalid zlib header"));
+    }
+    if bytes[1] & 0x20 != 0 {
+        return Err(invalid_data("zlib preset dictionaries are not supported"));
+    }
+    inflate(&bytes[2..])
+}
+
+#[test]
+fn test_zlib_decompress() {
+    // "hello hello hello hello", compressed with zlib at the default level
+    let compressed = [0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01,
+                      0x68, 0x03, 0x08, 0xb1];
+    assert_eq!(zlib_decompress(&compressed).unwrap(), b"hello hello hello hello".to_vec());
+}
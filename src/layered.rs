@@ -0,0 +1,271 @@
+// Layered materials, which place a dielectric coating over another material
+use vector::Vector;
+use shape::DifferentialGeometry;
+use frame::Frame;
+use material::{Material, BsdfSample, Lobes, orient, Lambertian};
+use texture::{Texture, ConstantTexture};
+use microfacet::{MicrofacetDistribution, MicrofacetModel, roughness_to_alpha};
+use fresnel;
+
+use std::sync::Arc;
+
+extern crate rand;
+use rand::Rng;
+
+// A clear coat over a substrate, such as car paint, varnished wood or glossy
+// plastic, following the model of Weidlich and Wilkie (2007): light that is not
+// reflected by the coating refracts through its (macro) surface, is attenuated on
+// the way to and from the substrate and is scattered by it, while reflections
+// inside of the coating are ignored
+//
+// The substrate must be opaque: light that it transmits is lost
+pub struct Coated {
+    pub substrate: Arc<dyn Material>,
+    pub ior: Arc<dyn Texture<f64>>,
+    // Perceptual roughness of the coating's surface, in the range 0..1
+    pub roughness: Arc<dyn Texture<f64>>,
+    // The absorption coefficient of the coating, per unit of thickness
+    pub absorption: Arc<dyn Texture<Vector>>,
+    pub thickness: f64,
+    pub model: MicrofacetModel,
+}
+
+// The pieces of the layered model at a point on a surface, in the local shading
+// frame
+struct Layer {
+    frame: Frame,
+    ior: f64,
+    distribution: MicrofacetDistribution,
+    absorption: Vector,
+}
+
+impl Layer {
+    // The direction inside of the coating that refracts to `w` (where both point
+    // away from the substrate)
+    fn inside(&self, w: &Vector) -> Vector {
+        fresnel::refract(w, &Vector::new(0.0, 0.0, 1.0), self.ior)
+            .map(|t| -t)
+            .unwrap_or(Vector::new(0.0, 0.0, 1.0))
+    }
+
+    // The direction outside of the coating that a direction inside of it refracts
+    // to, or `None` on total internal reflection
+    fn outside(&self, w: &Vector) -> Option<Vector> {
+        fresnel::refract(&-*w, &Vector::new(0.0, 0.0, -1.0), 1.0 / self.ior)
+    }
+
+    // The probability of sampling the coating rather than the substrate
+    fn coating_probability(&self, wo: &Vector) -> f64 {
+        fresnel::dielectric(wo.z, self.ior).clamp(0.1, 0.9)
+    }
+
+    // The fraction of light that passes through the coating to the substrate and
+    // back again
+    fn transmittance(&self, wo: &Vector, wi: &Vector, wo_inside: &Vector, wi_inside: &Vector) -> Vector {
+        let path = 1.0 / wo_inside.z + 1.0 / wi_inside.z;
+        let absorbed = self.absorption * -path;
+        Vector::new(absorbed.x.exp(), absorbed.y.exp(), absorbed.z.exp()) *
+        ((1.0 - fresnel::dielectric(wo.z, self.ior)) * (1.0 - fresnel::dielectric(wi.z, self.ior)))
+    }
+}
+
+impl Coated {
+    fn layer(&self, intersection: &DifferentialGeometry) -> Layer {
+        let alpha = roughness_to_alpha(self.roughness.evaluate(intersection));
+        Layer {
+            frame: intersection.shading_frame(),
+            ior: self.ior.evaluate(intersection),
+            distribution: MicrofacetDistribution::new(self.model, alpha, alpha),
+            absorption: self.absorption.evaluate(intersection) * self.thickness,
+        }
+    }
+
+    // Evaluates the BSDF and pdf of the rough coating's reflection
+    fn evaluate_coating(&self, layer: &Layer, wo: &Vector, wi: &Vector) -> (f64, f64) {
+        if layer.distribution.is_smooth() {
+            return (0.0, 0.0);
+        }
+        let wm = *wo + *wi;
+        if wm.length() == 0.0 {
+            return (0.0, 0.0);
+        }
+        let wm = wm.normalize();
+        let distribution = &layer.distribution;
+        (distribution.d(&wm) * distribution.g(wo, wi) * fresnel::dielectric(wo.dot(&wm), layer.ior) /
+         (4.0 * wo.z * wi.z),
+         distribution.pdf(wo, &wm) / (4.0 * wo.dot(&wm)))
+    }
+
+    // Evaluates the BSDF and pdf of the light that scatters off the substrate,
+    // where the pdf accounts for the compression of solid angle by refraction
+    fn evaluate_substrate(&self,
+                          layer: &Layer,
+                          wo: &Vector,
+                          wi: &Vector,
+                          intersection: &DifferentialGeometry)
+                          -> (Vector, f64) {
+        let wo_inside = layer.inside(wo);
+        let wi_inside = layer.inside(wi);
+        let (wo_world, wi_world) = (layer.frame.to_world(&wo_inside), layer.frame.to_world(&wi_inside));
+        let eta2 = layer.ior * layer.ior;
+        let f = self.substrate.evaluate(&wo_world, &wi_world, intersection) *
+                layer.transmittance(wo, wi, &wo_inside, &wi_inside) / eta2;
+        let pdf = self.substrate.pdf(&wo_world, &wi_world, intersection) * wi.z / (eta2 * wi_inside.z);
+        (f, pdf)
+    }
+}
+
+impl Material for Coated {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        let coating = if self.layer(intersection).distribution.is_smooth() {
+            Lobes::SPECULAR
+        } else {
+            Lobes::GLOSSY
+        };
+        coating | Lobes::REFLECTION | self.substrate.lobes(intersection)
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let layer = self.layer(intersection);
+        let local = layer.frame.to_local(wo);
+        let flipped = local.z < 0.0;
+        let (wo, _) = orient(&local, &local);
+        let probability = layer.coating_probability(&wo);
+        let mut rng = rand::thread_rng();
+
+        let (mut wi, f, pdf, lobe) = if rng.next_f64() < probability {
+            // Sample the coating
+            if layer.distribution.is_smooth() {
+                let wi = Vector::new(-wo.x, -wo.y, wo.z);
+                let reflectance = fresnel::dielectric(wo.z, layer.ior);
+                (wi, Vector::one() * (reflectance / wo.z), probability, Lobes::SPECULAR | Lobes::REFLECTION)
+            } else {
+                let wm = layer.distribution.sample_wm(&wo, (rng.next_f64(), rng.next_f64()));
+                let wi = (-wo).reflect(&wm);
+                if wi.z <= 0.0 {
+                    return None;
+                }
+                (wi, Vector::zero(), 0.0, Lobes::GLOSSY | Lobes::REFLECTION)
+            }
+        } else {
+            // Refract into the coating and sample the substrate
+            let wo_inside = layer.inside(&wo);
+            let sample = self.substrate.sample(&layer.frame.to_world(&wo_inside), intersection)?;
+            let wi_inside = layer.frame.to_local(&sample.wi);
+            if wi_inside.z <= 0.0 {
+                return None;
+            }
+            let wi = layer.outside(&wi_inside)?;
+            if sample.lobe.contains(Lobes::SPECULAR) {
+                // The substrate's delta distribution can't be evaluated, so the
+                // throughput is computed directly
+                let f = sample.f * layer.transmittance(&wo, &wi, &wo_inside, &wi_inside) *
+                        (wi_inside.z / wi.z);
+                (wi, f, (1.0 - probability) * sample.pdf, sample.lobe)
+            } else {
+                (wi, Vector::zero(), 0.0, sample.lobe)
+            }
+        };
+
+        // Non-specular samples are weighted by the combined density of both ways of
+        // generating them
+        if !lobe.contains(Lobes::SPECULAR) {
+            let (f_coating, pdf_coating) = self.evaluate_coating(&layer, &wo, &wi);
+            let (f_substrate, pdf_substrate) = self.evaluate_substrate(&layer, &wo, &wi, intersection);
+            let f = Vector::one() * f_coating + f_substrate;
+            let pdf = probability * pdf_coating + (1.0 - probability) * pdf_substrate;
+            if pdf <= 0.0 {
+                return None;
+            }
+            if flipped {
+                wi.z = -wi.z;
+            }
+            return Some(BsdfSample::new(&layer.frame.to_world(&wi), &f, pdf, lobe));
+        }
+
+        if pdf <= 0.0 {
+            return None;
+        }
+        if flipped {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample::new(&layer.frame.to_world(&wi), &f, pdf, lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let layer = self.layer(intersection);
+        let (wo, wi) = orient(&layer.frame.to_local(wo), &layer.frame.to_local(wi));
+        if wi.z <= 0.0 {
+            return Vector::zero();
+        }
+        let (f_coating, _) = self.evaluate_coating(&layer, &wo, &wi);
+        let (f_substrate, _) = self.evaluate_substrate(&layer, &wo, &wi, intersection);
+        Vector::one() * f_coating + f_substrate
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let layer = self.layer(intersection);
+        let (wo, wi) = orient(&layer.frame.to_local(wo), &layer.frame.to_local(wi));
+        if wi.z <= 0.0 {
+            return 0.0;
+        }
+        let probability = layer.coating_probability(&wo);
+        let (_, pdf_coating) = self.evaluate_coating(&layer, &wo, &wi);
+        let (_, pdf_substrate) = self.evaluate_substrate(&layer, &wo, &wi, intersection);
+        probability * pdf_coating + (1.0 - probability) * pdf_substrate
+    }
+}
+
+impl Coated {
+    pub fn new(substrate: Arc<dyn Material>, ior: f64, roughness: f64) -> Coated {
+        Coated::textured(substrate,
+                         Arc::new(ConstantTexture::new(ior)),
+                         Arc::new(ConstantTexture::new(roughness)),
+                         Arc::new(ConstantTexture::new(Vector::zero())),
+                         0.0)
+    }
+
+    pub fn textured(substrate: Arc<dyn Material>,
+                    ior: Arc<dyn Texture<f64>>,
+                    roughness: Arc<dyn Texture<f64>>,
+                    absorption: Arc<dyn Texture<Vector>>,
+                    thickness: f64)
+                    -> Coated {
+        Coated {
+            substrate,
+            ior,
+            roughness,
+            absorption,
+            thickness,
+            model: MicrofacetModel::TrowbridgeReitz,
+        }
+    }
+
+    // A diffuse base under a clear coating with the index of refraction of typical
+    // polymers
+    pub fn plastic(color: &Vector, roughness: f64) -> Coated {
+        Coated::new(Arc::new(Lambertian::new(color)), 1.5, roughness)
+    }
+}
+
+#[test]
+fn test_coated() {
+    use material::{sphere_intersection, assert_sample_consistent};
+
+    let (dg, wo) = sphere_intersection(0.3, -0.4);
+    for &roughness in &[0.0, 0.4] {
+        let plastic = Coated::plastic(&Vector::one(), roughness);
+        assert_sample_consistent(&plastic, &wo, &dg);
+        let mut total = 0.0;
+        for _ in 0..20000 {
+            if let Some(sample) = plastic.sample(&wo, &dg) {
+                total += sample.weight(&dg).x;
+            }
+        }
+
+        // Without reflections inside of the coating, most of the light that enters
+        // it is lost to total internal reflection
+        let albedo = total / 20000.0;
+        assert!(albedo < 1.0 && albedo > 0.3);
+    }
+}
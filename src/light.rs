@@ -0,0 +1,538 @@
+// Light sources that can be sampled directly from a point in the scene, which is
+// far less noisy than waiting for paths to hit them by chance
+use vector::Vector;
+use shape::Shape;
+use image::Image;
+use sampling::Distribution2D;
+use frame::Frame;
+use ies::PhotometricProfile;
+
+use std::f64;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+extern crate rand;
+use rand::Rng;
+
+// A direction towards a light source, along with the radiance that arrives from
+// it if nothing is in the way
+pub struct LightSample {
+    pub wi: Vector,
+    pub radiance: Vector,
+    // The density of the sample with respect to solid angle, or its probability
+    // for delta lights
+    pub pdf: f64,
+    // How far away the light is along `wi`, within which a shadow ray checks for
+    // occluders
+    pub distance: f64,
+}
+
+pub trait Light: Sync + Send {
+    // Chooses a direction towards the light from `point`
+    fn sample(&self, point: &Vector) -> Option<LightSample>;
+
+    // The density with respect to solid angle with which `sample` chooses the
+    // direction `wi` from `point`
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64;
+
+    // The radiance arriving along rays that leave the scene in `direction`, which
+    // is zero except for lights that surround the scene
+    fn radiance(&self, direction: &Vector) -> Vector {
+        Vector::zero()
+    }
+
+    // Whether the light is infinitely small, such that paths can never hit it and
+    // it can only be sampled directly
+    fn is_delta(&self) -> bool {
+        false
+    }
+}
+
+// A shape that emits a constant radiance from its front, or from both of its
+// sides
+pub struct AreaLight {
+    pub shape: Arc<dyn Shape>,
+    pub emission: Vector,
+    pub two_sided: bool,
+    // Whether light leaves the side opposite the shape's normal
+    pub flip_normals: bool,
+}
+
+impl AreaLight {
+    pub fn new(shape: Arc<dyn Shape>, emission: &Vector) -> AreaLight {
+        AreaLight {
+            shape,
+            emission: *emission,
+            two_sided: false,
+            flip_normals: false,
+        }
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, point: &Vector) -> Option<LightSample> {
+        let (position, normal, pdf) = self.shape.sample(point)?;
+        let offset = position - *point;
+        let distance = offset.length();
+        if distance == 0.0 || pdf <= 0.0 {
+            return None;
+        }
+        let wi = offset / distance;
+
+        // Points behind a one-sided light receive nothing from it
+        let normal = if self.flip_normals { -normal } else { normal };
+        if !self.two_sided && normal.dot(&wi) >= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.emission,
+            pdf,
+            distance,
+        })
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        self.shape.pdf(point, wi)
+    }
+}
+
+// Light arriving from infinitely far away, given by an equirectangular image
+// whose center lies along the -z-axis and whose top row is straight up: it is
+// sampled in proportion to the luminance of each pixel, accounting for the
+// stretching of the image towards the poles
+pub struct EnvironmentLight {
+    pub image: Image,
+    // Turns the image about the y-axis, in degrees
+    pub rotation: f64,
+    // Scales the values of the image
+    pub intensity: f64,
+    distribution: Distribution2D,
+}
+
+// Turns a vector about the y-axis by an angle in radians
+fn rotate_y(v: &Vector, angle: f64) -> Vector {
+    let (sin, cos) = angle.sin_cos();
+    Vector::new(v.x * cos + v.z * sin, v.y, -v.x * sin + v.z * cos)
+}
+
+impl EnvironmentLight {
+    pub fn new(image: Image, rotation: f64, intensity: f64) -> EnvironmentLight {
+        let (width, height) = (image.width, image.height);
+        let mut function = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let sin_theta = (f64::consts::PI * (y as f64 + 0.5) / height as f64).sin();
+            // Pixels that aren't finite, e.g. from corrupt HDR files, would
+            // poison the whole distribution, so they are never sampled
+            for x in 0..width {
+                let luminance = image.get(x, y).luminance();
+                let luminance = if luminance.is_finite() { luminance.max(0.0) } else { 0.0 };
+                function.push(luminance * sin_theta);
+            }
+        }
+        EnvironmentLight {
+            image,
+            rotation,
+            intensity,
+            distribution: Distribution2D::new(&function, width, height),
+        }
+    }
+
+    // Loads the image from any of the formats that `Image` supports, which should
+    // hold linear values
+    pub fn load(path: &Path, rotation: f64, intensity: f64) -> io::Result<EnvironmentLight> {
+        Ok(EnvironmentLight::new(Image::load(path)?, rotation, intensity))
+    }
+
+    fn direction_to_uv(&self, direction: &Vector) -> (f64, f64) {
+        let d = rotate_y(&direction.normalize(), -self.rotation.to_radians());
+        let phi = d.x.atan2(-d.z);
+        let theta = (d.x * d.x + d.z * d.z).sqrt().atan2(d.y);
+        (0.5 + phi / (2.0 * f64::consts::PI), theta / f64::consts::PI)
+    }
+
+    fn uv_to_direction(&self, uv: (f64, f64)) -> Vector {
+        let (sin_theta, cos_theta) = (uv.1 * f64::consts::PI).sin_cos();
+        let (sin_phi, cos_phi) = ((uv.0 - 0.5) * 2.0 * f64::consts::PI).sin_cos();
+        rotate_y(&Vector::new(sin_theta * sin_phi, cos_theta, -sin_theta * cos_phi),
+                 self.rotation.to_radians())
+    }
+
+    // Pixels are looked up without filtering, so that the radiance is constant
+    // where the density of samples is
+    fn lookup(&self, uv: (f64, f64)) -> Vector {
+        let x = ((uv.0 * self.image.width as f64) as usize).min(self.image.width - 1);
+        let y = ((uv.1 * self.image.height as f64) as usize).min(self.image.height - 1);
+        self.image.get(x, y) * self.intensity
+    }
+
+    // Converts a density over the image to one with respect to solid angle
+    fn solid_angle_pdf(pdf: f64, v: f64) -> f64 {
+        let sin_theta = (v * f64::consts::PI).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        pdf / (2.0 * f64::consts::PI * f64::consts::PI * sin_theta)
+    }
+}
+
+impl Light for EnvironmentLight {
+    fn sample(&self, point: &Vector) -> Option<LightSample> {
+        let mut rng = rand::thread_rng();
+        let (uv, pdf) = self.distribution.sample_continuous((rng.next_f64(), rng.next_f64()));
+        let pdf = EnvironmentLight::solid_angle_pdf(pdf, uv.1);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi: self.uv_to_direction(uv),
+            radiance: self.lookup(uv),
+            pdf,
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        let uv = self.direction_to_uv(wi);
+        EnvironmentLight::solid_angle_pdf(self.distribution.pdf(uv), uv.1)
+    }
+
+    fn radiance(&self, direction: &Vector) -> Vector {
+        self.lookup(self.direction_to_uv(direction))
+    }
+}
+
+// Orients a photometric profile: the nadir points in `direction`, and the 0 degree
+// horizontal angle lies along the projection of `reference` onto the plane that
+// is perpendicular to it (if they are parallel, it is arbitrary)
+fn profile_frame(direction: &Vector, reference: &Vector) -> Frame {
+    Frame::new(&direction.normalize(), reference)
+}
+
+// Scales the intensity of a light by a photometric profile, whose nadir points
+// along the normal of `frame` and whose horizontal angles are measured from its
+// first tangent, counterclockwise when looking along the nadir from above
+fn profile_intensity(profile: &Option<Arc<PhotometricProfile>>, frame: &Frame, direction: &Vector) -> f64 {
+    match *profile {
+        Some(ref profile) => {
+            let local = frame.to_local(direction);
+            let vertical = local.z.clamp(-1.0, 1.0).acos().to_degrees();
+            let horizontal = (-local.y).atan2(local.x).to_degrees();
+            profile.intensity(vertical, horizontal)
+        }
+        None => 1.0,
+    }
+}
+
+// An infinitely small light that emits in every direction
+pub struct PointLight {
+    pub position: Vector,
+    // Radiant intensity, in W / sr: with a profile, this is the intensity per
+    // candela, e.g. `Illuminant::radiance(1.0)`
+    pub intensity: Vector,
+    pub profile: Option<Arc<PhotometricProfile>>,
+    // Orients the profile, which points straight down by default like a fixture
+    // hanging from a ceiling, with its 0 degree horizontal angle along the x-axis
+    pub frame: Frame,
+}
+
+impl PointLight {
+    pub fn new(position: &Vector, intensity: &Vector) -> PointLight {
+        PointLight {
+            position: *position,
+            intensity: *intensity,
+            profile: None,
+            frame: profile_frame(&Vector::new(0.0, -1.0, 0.0), &Vector::new(1.0, 0.0, 0.0)),
+        }
+    }
+
+    // Modulates the intensity by the distribution of a real fixture
+    pub fn with_profile(mut self, profile: Arc<PhotometricProfile>) -> PointLight {
+        self.profile = Some(profile);
+        self
+    }
+
+    // Points the nadir of the profile in a direction, turning it about the nadir
+    // so that its 0 degree horizontal angle faces `reference`
+    pub fn aimed(mut self, direction: &Vector, reference: &Vector) -> PointLight {
+        self.frame = profile_frame(direction, reference);
+        self
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: &Vector) -> Option<LightSample> {
+        let offset = self.position - *point;
+        let distance = offset.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = offset / distance;
+        let intensity = self.intensity * profile_intensity(&self.profile, &self.frame, &-wi);
+        Some(LightSample {
+            wi,
+            radiance: intensity / (distance * distance),
+            pdf: 1.0,
+            distance,
+        })
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+// A point light that only emits within a cone, whose intensity falls off smoothly
+// towards its edge
+pub struct SpotLight {
+    pub position: Vector,
+    pub direction: Vector,
+    // Radiant intensity along the axis of the cone, in W / sr: with a profile,
+    // this is the intensity per candela
+    pub intensity: Vector,
+    // The cosines of the angles from the axis where the intensity begins to fall
+    // off and where it reaches zero
+    pub cos_falloff_start: f64,
+    pub cos_total_width: f64,
+    pub profile: Option<Arc<PhotometricProfile>>,
+    frame: Frame,
+}
+
+impl SpotLight {
+    // The angles from the axis are in degrees, and `reference` gives the 0 degree
+    // horizontal angle of a profile
+    pub fn new(position: &Vector,
+               direction: &Vector,
+               reference: &Vector,
+               intensity: &Vector,
+               total_width: f64,
+               falloff_start: f64)
+               -> SpotLight {
+        let direction = direction.normalize();
+        SpotLight {
+            position: *position,
+            direction,
+            intensity: *intensity,
+            cos_falloff_start: falloff_start.min(total_width).to_radians().cos(),
+            cos_total_width: total_width.to_radians().cos(),
+            profile: None,
+            frame: profile_frame(&direction, reference),
+        }
+    }
+
+    // Modulates the intensity by the distribution of a real fixture, whose nadir
+    // lies along the axis of the cone
+    pub fn with_profile(mut self, profile: Arc<PhotometricProfile>) -> SpotLight {
+        self.profile = Some(profile);
+        self
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        if cos_theta >= self.cos_falloff_start {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_total_width {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        t * t * (3.0 - 2.0 * t)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: &Vector) -> Option<LightSample> {
+        let offset = self.position - *point;
+        let distance = offset.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = offset / distance;
+        let scale = self.falloff((-wi).dot(&self.direction)) *
+                    profile_intensity(&self.profile, &self.frame, &-wi);
+        if scale <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.intensity * (scale / (distance * distance)),
+            pdf: 1.0,
+            distance,
+        })
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        0.0
+    }
+
+    fn is_delta(&self) -> bool {
+        true
+    }
+}
+
+#[test]
+fn test_area_light() {
+    use shape::{Sphere, Quad, Disk, Triangle};
+    use std::f64::consts::PI;
+
+    // The sampled densities agree with the ones that are evaluated for the same
+    // directions, and their reciprocals average to the solid angle of the shape
+    let point = Vector::new(0.1, -0.2, 0.3);
+    let sphere = Sphere::new(&Vector::new(0.5, 1.0, -2.0), 0.6);
+    let sphere_solid_angle = 2.0 * PI * (1.0 - (1.0 - 0.36 / (sphere.center - point).squared_length()).sqrt());
+    let disk = Disk::new(&Vector::new(0.1, 1.8, 0.3), &Vector::new(0.0, -1.0, 0.0), 0.5);
+    let disk_solid_angle = 2.0 * PI * (1.0 - 2.0 / 4.25f64.sqrt());
+    let shapes: Vec<(Arc<dyn Shape>, f64)> =
+        vec![(Arc::new(sphere), sphere_solid_angle),
+             (Arc::new(disk), disk_solid_angle),
+             (Arc::new(Quad::new(&Vector::new(-1.0, 1.0, -1.0), &Vector::new(2.0, 0.0, 0.0), &Vector::new(0.0, 0.5, 1.0))),
+              0.0),
+             (Arc::new(Triangle::new(&Vector::new(-1.0, 1.0, 2.0), &Vector::new(1.0, 1.5, 2.0), &Vector::new(0.0, 3.0, 1.0))),
+              0.0)];
+    for &(ref shape, solid_angle) in &shapes {
+        let mut sum = 0.0;
+        const SAMPLES: usize = 20000;
+        for _ in 0..SAMPLES {
+            let (position, _, pdf) = shape.sample(&point).unwrap();
+            let wi = (position - point).normalize();
+            assert!((shape.pdf(&point, &wi) - pdf).abs() <= 1.0e-6 * pdf);
+            sum += 1.0 / pdf;
+        }
+        if solid_angle > 0.0 {
+            assert!((sum / SAMPLES as f64 - solid_angle).abs() < 0.02 * solid_angle);
+        }
+    }
+
+    // A disk light of radius R at a height h above a point facing it produces an
+    // irradiance of L * pi * R^2 / (h^2 + R^2)
+    let light = AreaLight::new(Arc::new(Disk::new(&Vector::new(0.0, 2.0, 0.0), &Vector::new(0.0, -1.0, 0.0), 1.0)),
+                               &Vector::one());
+    let mut irradiance = 0.0;
+    const SAMPLES: usize = 20000;
+    for _ in 0..SAMPLES {
+        let sample = light.sample(&Vector::zero()).unwrap();
+        irradiance += sample.radiance.x * sample.wi.y / sample.pdf;
+    }
+    irradiance /= SAMPLES as f64;
+    let expected = PI / 5.0;
+    assert!((irradiance - expected).abs() < 0.01 * expected);
+
+    // Nothing reaches points behind the light
+    assert!(light.sample(&Vector::new(0.0, 3.0, 0.0)).is_none());
+}
+
+#[test]
+fn test_environment_light() {
+    // A uniform environment produces an irradiance of pi times its radiance
+    let mut image = Image::new(16, 8);
+    for pixel in &mut image.pixels {
+        *pixel = Vector::one() * 0.5;
+    }
+    let uniform = EnvironmentLight::new(image, 30.0, 2.0);
+    let mut irradiance = 0.0;
+    const SAMPLES: usize = 100000;
+    for _ in 0..SAMPLES {
+        let sample = uniform.sample(&Vector::zero()).unwrap();
+        assert!((uniform.pdf(&Vector::zero(), &sample.wi) - sample.pdf).abs() <= 1.0e-9 * sample.pdf);
+        irradiance += sample.radiance.x * sample.wi.y.max(0.0) / sample.pdf;
+    }
+    irradiance /= SAMPLES as f64;
+    assert!((irradiance - f64::consts::PI).abs() < 0.02 * f64::consts::PI);
+
+    // Every sample of an environment with a single bright pixel lies within it,
+    // and the pixel's direction turns with the environment
+    let mut image = Image::new(16, 8);
+    image.set(12, 2, &(Vector::one() * 100.0));
+    for &rotation in &[0.0, 90.0] {
+        let bright = EnvironmentLight::new(image.clone(), rotation, 1.0);
+        for _ in 0..100 {
+            let sample = bright.sample(&Vector::zero()).unwrap();
+            assert!(sample.radiance.x > 99.0);
+            assert!(bright.radiance(&sample.wi).x > 99.0);
+        }
+
+        // Pixels that aren't finite are skipped when sampling
+        let mut broken = image.clone();
+        broken.set(3, 5, &(Vector::one() * f64::NAN));
+        broken.set(7, 1, &(Vector::one() * f64::INFINITY));
+        let broken = EnvironmentLight::new(broken, rotation, 1.0);
+        for _ in 0..100 {
+            let sample = broken.sample(&Vector::zero()).unwrap();
+            assert!(sample.radiance.x > 99.0 && sample.pdf.is_finite());
+        }
+        let pixel_center = bright.uv_to_direction((12.5 / 16.0, 2.5 / 8.0));
+        assert!(pixel_center.y > 0.0);
+        if rotation == 0.0 {
+            assert!(pixel_center.x > 0.0 && pixel_center.z.abs() < 0.5);
+        } else {
+            assert!(pixel_center.z < 0.0 && pixel_center.x.abs() < 0.5);
+        }
+    }
+}
+
+#[test]
+fn test_point_and_spot_lights() {
+    // Intensity falls off with the square of the distance
+    let point = PointLight::new(&Vector::new(0.0, 2.0, 0.0), &(Vector::one() * 8.0));
+    let sample = point.sample(&Vector::zero()).unwrap();
+    assert!((sample.radiance - Vector::one() * 2.0).length() < 1.0e-12);
+    assert!((sample.wi - Vector::new(0.0, 1.0, 0.0)).length() < 1.0e-12 && sample.distance == 2.0);
+    assert!(point.is_delta() && point.pdf(&Vector::zero(), &sample.wi) == 0.0);
+
+    // Spot lights are full strength inside of their falloff, and dark outside of
+    // their cone
+    let down = Vector::new(0.0, -1.0, 0.0);
+    let above = Vector::new(0.0, 1.0, 0.0);
+    let (x_axis, z_axis) = (Vector::new(1.0, 0.0, 0.0), Vector::new(0.0, 0.0, 1.0));
+    let spot = SpotLight::new(&above, &down, &x_axis, &Vector::one(), 30.0, 20.0);
+    assert!((spot.sample(&Vector::new(0.1, 0.0, 0.0)).unwrap().radiance.x - 1.0 / 1.01).abs() < 1.0e-12);
+    let edge = spot.sample(&Vector::new(25.0f64.to_radians().tan(), 0.0, 0.0)).unwrap();
+    assert!(edge.radiance.x > 0.0 && edge.radiance.x < 1.0);
+    assert!(spot.sample(&Vector::new(1.0, 0.0, 0.0)).is_none());
+
+    // A profile that is brightest straight down and dark sideways
+    let profile = Arc::new(PhotometricProfile::parse("TILT=NONE\n\
+                                                     1 1000 1 3 1 1 2 0 0 0\n\
+                                                     1 1 0\n\
+                                                     0 45 90\n\
+                                                     0\n\
+                                                     1000 500 0\n")
+        .unwrap());
+    let fixture = PointLight::new(&Vector::new(0.0, 1.0, 0.0), &Vector::one()).with_profile(profile.clone());
+    assert!((fixture.sample(&Vector::zero()).unwrap().radiance.x - 1000.0).abs() < 1.0e-9);
+    let aside = fixture.sample(&Vector::new(1.0, 1.0, 0.0)).unwrap();
+    assert!(aside.radiance.x < 1.0e-9);
+    let aimed = PointLight::new(&Vector::new(0.0, 1.0, 0.0), &Vector::one())
+        .with_profile(profile)
+        .aimed(&Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0));
+    assert!((aimed.sample(&Vector::new(1.0, 1.0, 0.0)).unwrap().radiance.x - 1000.0).abs() < 1.0e-9);
+
+    // An asymmetric profile that throws light towards 0 degrees, half as much
+    // towards 90 degrees and none backwards, oriented by its reference direction:
+    // seen from above, 90 degrees lies counterclockwise from 0 degrees
+    let profile = Arc::new(PhotometricProfile::parse("TILT=NONE\n\
+                                                     1 1000 1 3 5 1 2 0 0 0\n\
+                                                     1 1 0\n\
+                                                     0 45 90\n\
+                                                     0 90 180 270 360\n\
+                                                     1000 1000 1000\n\
+                                                     500 500 500\n\
+                                                     0 0 0\n\
+                                                     0 0 0\n\
+                                                     1000 1000 1000\n")
+        .unwrap());
+    let washer = PointLight::new(&above, &Vector::one()).with_profile(profile.clone()).aimed(&down, &z_axis);
+    let spot = SpotLight::new(&above, &down, &z_axis, &Vector::one(), 90.0, 90.0).with_profile(profile);
+    for light in &[&washer as &dyn Light, &spot] {
+        let radiance = |x: f64, z: f64| {
+            light.sample(&Vector::new(x, 0.0, z)).map_or(0.0, |sample| sample.radiance.x)
+        };
+        assert!((radiance(0.0, 1.0) - 500.0).abs() < 1.0e-9);
+        assert!((radiance(1.0, 0.0) - 250.0).abs() < 1.0e-9);
+        assert!(radiance(0.0, -1.0) < 1.0e-9 && radiance(-1.0, 0.0) < 1.0e-9);
+    }
+}
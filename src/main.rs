@@ -6,7 +6,6 @@ extern crate rand;
 use rand::Rng;
 
 // Standard library
-use std::error::Error;
 use std::io::prelude::*;
 use std::fs::File;
 use std::path::Path;
@@ -22,21 +21,47 @@ mod material;
 mod primitive;
 mod scene;
 mod camera;
+mod bounds;
+mod csg;
+mod sdf;
+mod image;
+mod heightfield;
+mod implicit;
+mod texture;
+mod inflate;
+mod png;
+mod exr;
+mod hdr;
+mod mipmap;
+mod noise;
+mod bump;
+mod frame;
+mod fresnel;
+mod microfacet;
+mod sampling;
+mod spectrum;
+mod layered;
+mod principled;
+mod measured;
+mod mix;
+mod light;
+mod sky;
+mod ies;
 
 // Custom modules
 use vector::Vector;
 use ray::Ray;
-use shape::Shape;
-use shape::DifferentialGeometry;
 use shape::Sphere;
 use shape::Plane;
-use material::Material;
-use material::Lambertian;
-use material::Metallic;
+use shape::{DifferentialGeometry, EPSILON};
+use material::{Material, Lambertian, Lobes};
+use material::Conductor;
 use material::Dielectric;
 use primitive::Primitive;
 use scene::Scene;
 use camera::Camera;
+use sky::Sky;
+use sampling::power_heuristic;
 
 // Output resolution
 const RES_X: u32 = 800;
@@ -45,27 +70,86 @@ const SAMPLES: u32 = 1;
 const MAX_DEPTH: u32 = 5;
 const NUMBER_OF_THREADS: u32 = 10;
 const GAMMA: f64 = 1.0 / 2.2;
+const SKY_EXPOSURE: f64 = 0.02;
 
-fn trace(r: &Ray, scene: &Scene, depth: u32) -> Vector {
-    let surface_interaction = scene.intersect(&r);
+// Samples one of the scene's lights at random and returns the light that it
+// scatters towards the viewer, weighted against the chance that sampling the
+// material would have found the same light
+fn sample_light(wo: &Vector, dg: &DifferentialGeometry, material: &dyn Material, scene: &Scene) -> Vector {
+    if scene.lights.is_empty() {
+        return Vector::zero();
+    }
+    let count = scene.lights.len();
+    let index = ((rand::thread_rng().next_f64() * count as f64) as usize).min(count - 1);
+    let light_source = &scene.lights[index];
+    let light = match light_source.sample(&dg.position) {
+        Some(light) => light,
+        None => return Vector::zero(),
+    };
+
+    let f = material.evaluate(wo, &light.wi, dg);
+    if f.max_component() <= 0.0 {
+        return Vector::zero();
+    }
+    let shadow_ray = Ray::new(&dg.position, &light.wi, 0.0, light.distance - EPSILON);
+    if scene.is_occluded(&shadow_ray) {
+        return Vector::zero();
+    }
+    // Materials can't sample delta lights, so there is nothing to weigh them against
+    let light_pdf = light.pdf / count as f64;
+    let weight = if light_source.is_delta() {
+        1.0
+    } else {
+        power_heuristic(light_pdf, material.pdf(wo, &light.wi, dg))
+    };
+    f * light.radiance * (light.wi.abs_dot(&dg.shading_normal) * weight / light_pdf)
+}
+
+// Weighs light that a path found by chance, given the density with which the
+// light would have sampled the same direction, against sampling it directly
+fn emission_weight<F: Fn() -> f64>(bounce_pdf: Option<f64>, scene: &Scene, light_pdf: F) -> f64 {
+    match bounce_pdf {
+        Some(pdf) if !scene.lights.is_empty() => power_heuristic(pdf, light_pdf() / scene.lights.len() as f64),
+        _ => 1.0,
+    }
+}
+
+// The density of the last bounce is used to weigh light that paths find by
+// chance against light that was sampled directly: it is missing for camera rays
+// and specular bounces, which can't sample lights
+fn trace(r: &Ray, scene: &Scene, depth: u32, bounce_pdf: Option<f64>) -> Vector {
+    let surface_interaction = scene.intersect(r);
     match surface_interaction {
         // Hit
-        Some((dg, mtl)) => {
-            let mut attenuation = Vector::one();
-            if depth < MAX_DEPTH {
-                let bounce_ray = mtl.scatter(&r, &dg, &mut attenuation);
-                return attenuation * trace(&bounce_ray, &scene, depth + 1);
-            } else {
-                Vector::zero()
+        Some((dg, item)) => {
+            let mut radiance = item.emitted(&dg);
+            if radiance.max_component() > 0.0 {
+                radiance *= emission_weight(bounce_pdf, scene, || item.shape.pdf(&r.origin, &r.direction));
+            }
+
+            if depth < MAX_DEPTH && !item.absorbs(&dg) {
+                let wo = -r.direction;
+                if item.material.lobes(&dg).is_non_specular() {
+                    radiance += sample_light(&wo, &dg, item.material.as_ref(), scene);
+                }
+                match item.material.sample(&wo, &dg) {
+                    Some(sample) if sample.pdf > 0.0 => {
+                        let bounce_ray = Ray::new(&dg.position, &sample.wi, r.t_min, r.t_max);
+                        let pdf = if sample.lobe.contains(Lobes::SPECULAR) { None } else { Some(sample.pdf) };
+                        radiance += sample.weight(&dg) * trace(&bounce_ray, scene, depth + 1, pdf);
+                    }
+                    _ => (),
+                }
             }
+            radiance
         }
         // Miss
         None => {
-            let unit_direction = r.direction.normalize();
-            let t = 0.5 * (unit_direction.y + 1.0);
-            let white = Vector::one();
-            let blue = Vector::new(0.5, 0.7, 1.0);
-            white.lerp(&blue, t)
+            if let Some(environment) = scene.environment() {
+                let radiance = environment.radiance(&r.direction);
+                return radiance * emission_weight(bounce_pdf, scene, || environment.pdf(&r.origin, &r.direction));
+            }
+            Vector::zero()
         }
     }
 }
@@ -91,8 +175,14 @@ fn threaded_color(start: (u32, u32),
                 // (note that we flip the y-axis)
                 let u = (x as f64 + rng.next_f64()) / RES_X as f64;
                 let v = ((RES_Y - y) as f64 + rng.next_f64()) / RES_Y as f64;
-                let r = camera.generate_ray(u, v);
-                col += trace(&r, &scene, 0);
+                // Differentials are scaled to account for the footprint of
+                // each individual sample
+                let spacing = 1.0 / (SAMPLES as f64).sqrt();
+                let r = camera.generate_ray_differential(u,
+                                                         v,
+                                                         spacing / RES_X as f64,
+                                                         spacing / RES_Y as f64);
+                col += trace(&r, &scene, 0, None);
             }
 
             col /= SAMPLES as f64;
@@ -116,7 +206,7 @@ fn map(v: f64, fmin: f64, fmax: f64, tmin: f64, tmax: f64) -> f64 {
 fn main() {
     let path = Path::new("output/render.ppm");
     let display = path.display();
-    let mut file = File::create(&path).expect("couldn't create file");
+    let mut file = File::create(path).expect("couldn't create file");
 
     // Use the time module to record how long it takes to render the entire scene
     let start = Instant::now();
@@ -134,10 +224,10 @@ fn main() {
     let left = Arc::new(Plane::new(&Vector::new(1.0, 0.0, 0.0), &Vector::new(1.0, 0.0, 0.0)));
     let right = Arc::new(Plane::new(&Vector::new(-1.0, 0.0, 0.0), &Vector::new(-1.0, 0.0, 0.0)));
     let back = Arc::new(Plane::new(&Vector::new(0.0, 0.0, -2.0), &Vector::new(0.0, 0.0, -1.0)));
-    scene.items.push(Primitive::new(floor, mtl_diff_white.clone()));
-    scene.items.push(Primitive::new(left, mtl_diff_red.clone()));
-    scene.items.push(Primitive::new(right, mtl_diff_green.clone()));
-    scene.items.push(Primitive::new(back, mtl_diff_white.clone()));
+    scene.add(Primitive::new(floor, mtl_diff_white.clone()));
+    scene.add(Primitive::new(left, mtl_diff_red.clone()));
+    scene.add(Primitive::new(right, mtl_diff_green.clone()));
+    scene.add(Primitive::new(back, mtl_diff_white.clone()));
 
     // Spheres
     const NUMBER_OF_SPHERES: u32 = 7;
@@ -145,12 +235,16 @@ fn main() {
     for i in 0..NUMBER_OF_SPHERES {
         let pct = (i as f64) / (NUMBER_OF_SPHERES as f64);
         let x = pct * 2.0 - 1.0;
-        let mtl = Arc::new(Metallic::new(&Vector::one(), x));
+        let presets = [Conductor::gold, Conductor::copper, Conductor::aluminium, Conductor::silver];
+        let mtl = Arc::new(presets[i as usize % presets.len()](pct));
         let sph = Arc::new(Sphere::new(&Vector::new(x + 0.05, 0.0, -1.0),
                                        (pct * 0.5 + MINIMUM_RADIUS) * 0.25));
-        scene.items.push(Primitive::new(sph, mtl));
+        scene.add(Primitive::new(sph, mtl));
     }
 
+    // A summer afternoon sky, scaled down to the range of the display
+    scene.set_environment(Arc::new(Sky::at_time(40.0, 172.0, 15.0, 3.0, SKY_EXPOSURE)));
+
     // Set up camera and scene atomic reference counted pointers
     let shared_camera = Arc::new(Camera::new(60.0, RES_X as f64 / RES_Y as f64));
     let shared_scene = Arc::new(scene);
@@ -182,7 +276,7 @@ fn main() {
 
     // Write to the file
     match file.write_all(file_contents.as_bytes()) {
-        Err(why) => panic!("couldn't write to {}: {}", display, why.description()),
+        Err(why) => panic!("couldn't write to {}: {}", display, why),
         Ok(_) => {
             println!("successfully wrote to {}, finished in {:?} seconds",
                      display,
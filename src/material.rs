@@ -1,90 +1,511 @@
 use vector::Vector;
-use ray::Ray;
 use shape::DifferentialGeometry;
+use texture::{Texture, ConstantTexture};
+use microfacet::{MicrofacetDistribution, MicrofacetModel, roughness_to_alpha};
+use fresnel;
+use sampling;
+
+use std::f64;
+use std::ops::BitOr;
+use std::sync::Arc;
 
 extern crate rand;
 use rand::Rng;
 
+// Flags describing the kinds of scattering (lobes) that a material exhibits,
+// which can be combined with `|`
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Lobes(u8);
+
+impl Lobes {
+    pub const NONE: Lobes = Lobes(0);
+    pub const REFLECTION: Lobes = Lobes(1);
+    pub const TRANSMISSION: Lobes = Lobes(2);
+    pub const DIFFUSE: Lobes = Lobes(4);
+    pub const GLOSSY: Lobes = Lobes(8);
+    // Lobes described by a Dirac delta, which can only be sampled
+    pub const SPECULAR: Lobes = Lobes(16);
+
+    // Whether all of the given flags are set
+    pub fn contains(&self, other: Lobes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    // Whether any of the given flags are set
+    pub fn intersects(&self, other: Lobes) -> bool {
+        self.0 & other.0 != 0
+    }
+
+    // Whether the lobes can be evaluated for an arbitrary pair of directions, i.e.
+    // whether light sampling is useful
+    pub fn is_non_specular(&self) -> bool {
+        self.intersects(Lobes::DIFFUSE | Lobes::GLOSSY)
+    }
+}
+
+impl BitOr for Lobes {
+    type Output = Lobes;
+
+    fn bitor(self, rhs: Lobes) -> Lobes {
+        Lobes(self.0 | rhs.0)
+    }
+}
+
+// The result of sampling a material: the incident direction (in world-space),
+// the value of the BSDF and the probability density of choosing the direction
+// with respect to solid angle, along with the lobe that was sampled
+//
+// For specular lobes, the pdf is the probability of choosing the lobe and `f`
+// includes the delta distribution's division by the cosine of the incident angle,
+// so that `f * |cos(theta_i)| / pdf` is always the path throughput weight
+pub struct BsdfSample {
+    pub wi: Vector,
+    pub f: Vector,
+    pub pdf: f64,
+    pub lobe: Lobes,
+}
+
+impl BsdfSample {
+    pub fn new(wi: &Vector, f: &Vector, pdf: f64, lobe: Lobes) -> BsdfSample {
+        BsdfSample {
+            wi: *wi,
+            f: *f,
+            pdf,
+            lobe,
+        }
+    }
+
+    // The factor by which the sample scales the light arriving along `wi`
+    pub fn weight(&self, intersection: &DifferentialGeometry) -> Vector {
+        self.f * (self.wi.dot(&intersection.shading_normal).abs() / self.pdf)
+    }
+}
+
+// Orients a pair of local directions so that `wo` lies above the surface, which
+// lets materials treat hits on either side of the surface alike
+pub fn orient(wo: &Vector, wi: &Vector) -> (Vector, Vector) {
+    if wo.z < 0.0 {
+        (Vector::new(wo.x, wo.y, -wo.z), Vector::new(wi.x, wi.y, -wi.z))
+    } else {
+        (*wo, *wi)
+    }
+}
+
+// Materials describe how light scatters at a surface: all directions point away
+// from the point of intersection, where `wo` is the direction towards the viewer
+// and `wi` the direction from which light arrives
 pub trait Material: Sync + Send {
-    // Produce a scattered ray
-    fn scatter(&self,
-               incident: &Ray,
-               intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
-               -> Ray;
+    // The lobes that make up the material at a point on a surface
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes;
+
+    // Samples an incident direction, returning `None` if the sample carries no
+    // energy
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample>;
+
+    // Evaluates the BSDF for a pair of directions, which is zero for specular
+    // lobes
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector;
+
+    // The probability density of `sample` returning `wi`, with respect to solid
+    // angle, which is zero for specular lobes
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64;
+}
+
+fn same_hemisphere(wo: &Vector, wi: &Vector) -> bool {
+    wo.z * wi.z > 0.0
 }
 
 pub struct Lambertian {
-    pub albedo: Vector,
+    pub albedo: Arc<dyn Texture<Vector>>,
 }
 
 impl Material for Lambertian {
-    fn scatter(&self,
-               incident: &Ray,
-               intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
-               -> Ray {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION
+    }
 
-        let target = intersection.position + intersection.normal + Vector::random_in_unit_sphere();
-        let scattered = Ray::new(&intersection.position,
-                                 &mut (target - intersection.position),
-                                 incident.t_min,
-                                 incident.t_max);
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let mut rng = rand::thread_rng();
+        let wi = intersection.shading_frame()
+            .to_world(&sampling::cosine_sample_hemisphere((rng.next_f64(), rng.next_f64())));
+        Some(BsdfSample::new(&wi,
+                             &self.evaluate(wo, &wi, intersection),
+                             self.pdf(wo, &wi, intersection),
+                             Lobes::DIFFUSE | Lobes::REFLECTION))
+    }
+
+    // Light is always scattered into the hemisphere of the shading normal, even
+    // when the surface is seen from behind
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        if wi.dot(&intersection.shading_normal) <= 0.0 {
+            return Vector::zero();
+        }
+        self.albedo.evaluate(intersection) / f64::consts::PI
+    }
 
-        *attenuation = self.albedo;
-        scattered
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        sampling::cosine_hemisphere_pdf(wi.dot(&intersection.shading_normal))
     }
 }
 
 impl Lambertian {
     pub fn new(a: &Vector) -> Lambertian {
-        Lambertian { albedo: *a }
+        Lambertian::textured(Arc::new(ConstantTexture::new(*a)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture<Vector>>) -> Lambertian {
+        Lambertian { albedo }
+    }
+}
+
+// A rough diffuse surface, such as clay or concrete, modeled as a collection of
+// Lambertian V-shaped grooves (Oren and Nayar 1994): unlike Lambertian surfaces,
+// these appear flatter and brighter towards the viewer at grazing angles
+//
+// Like `Lambertian`, light is always scattered into the hemisphere of the shading
+// normal
+pub struct OrenNayar {
+    pub albedo: Arc<dyn Texture<Vector>>,
+    // The standard deviation of the angle of the grooves, in degrees, where zero
+    // is equivalent to a Lambertian surface
+    pub sigma: Arc<dyn Texture<f64>>,
+}
+
+impl Material for OrenNayar {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        Lobes::DIFFUSE | Lobes::REFLECTION
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let mut rng = rand::thread_rng();
+        let wi = intersection.shading_frame()
+            .to_world(&sampling::cosine_sample_hemisphere((rng.next_f64(), rng.next_f64())));
+        Some(BsdfSample::new(&wi,
+                             &self.evaluate(wo, &wi, intersection),
+                             self.pdf(wo, &wi, intersection),
+                             Lobes::DIFFUSE | Lobes::REFLECTION))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let frame = intersection.shading_frame();
+        let (mut wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        if wi.z <= 0.0 {
+            return Vector::zero();
+        }
+        wo.z = wo.z.abs();
+
+        // The qualitative model's fit to the full expression
+        let sigma = self.sigma.evaluate(intersection).max(0.0).to_radians();
+        let sigma2 = sigma * sigma;
+        let a = 1.0 - sigma2 / (2.0 * (sigma2 + 0.33));
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // The cosine of the difference between the azimuths of the two directions
+        let (sin_theta_o, sin_theta_i) = ((1.0 - wo.z * wo.z).max(0.0).sqrt(), (1.0 - wi.z * wi.z).max(0.0).sqrt());
+        let cos_phi = if sin_theta_o > 1.0e-4 && sin_theta_i > 1.0e-4 {
+            ((wo.x * wi.x + wo.y * wi.y) / (sin_theta_o * sin_theta_i)).max(0.0)
+        } else {
+            0.0
+        };
+
+        // Alpha is the larger of the two polar angles and beta the smaller
+        let (sin_alpha, tan_beta) = if wi.z > wo.z {
+            (sin_theta_o, sin_theta_i / wi.z)
+        } else {
+            (sin_theta_i, sin_theta_o / wo.z.max(1.0e-8))
+        };
+        self.albedo.evaluate(intersection) * ((a + b * cos_phi * sin_alpha * tan_beta) / f64::consts::PI)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        sampling::cosine_hemisphere_pdf(wi.dot(&intersection.shading_normal))
     }
 }
 
+impl OrenNayar {
+    pub fn new(a: &Vector, sigma: f64) -> OrenNayar {
+        OrenNayar::textured(Arc::new(ConstantTexture::new(*a)), Arc::new(ConstantTexture::new(sigma)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture<Vector>>, sigma: Arc<dyn Texture<f64>>) -> OrenNayar {
+        OrenNayar { albedo, sigma }
+    }
+}
+
+// A cheap approximation of glossy metals, which jitters the mirror direction: this
+// is neither energy-conserving nor physically based, see `Conductor`
+//
+// The jittered directions have no closed-form density, so the material is
+// treated as specular and can only be sampled
 pub struct Metallic {
-    pub albedo: Vector,
-    pub glossiness: f64,
+    pub albedo: Arc<dyn Texture<Vector>>,
+    // Values outside of the range 0..1 are clamped
+    pub glossiness: Arc<dyn Texture<f64>>,
 }
 
 impl Material for Metallic {
-    fn scatter(&self,
-               incident: &Ray,
-               intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
-               -> Ray {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        Lobes::SPECULAR | Lobes::REFLECTION
+    }
 
-        let reflected = incident.direction.normalize().reflect(&intersection.normal);
-        let scattered = Ray::new(&intersection.position,
-                                 &(reflected + Vector::random_in_unit_sphere() * self.glossiness),
-                                 incident.t_min,
-                                 incident.t_max);
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let reflected = (-*wo).reflect(&intersection.shading_normal);
+        let glossiness = self.glossiness.evaluate(intersection).clamp(0.0, 1.0);
+        let wi = (reflected + Vector::random_in_unit_sphere() * glossiness).normalize();
+        let cos_theta_i = wi.dot(&intersection.shading_normal).abs();
+        if cos_theta_i == 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(&wi,
+                             &(self.albedo.evaluate(intersection) / cos_theta_i),
+                             1.0,
+                             Lobes::SPECULAR | Lobes::REFLECTION))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        Vector::zero()
+    }
 
-        *attenuation = self.albedo;
-        scattered
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        0.0
     }
 }
 
 impl Metallic {
     pub fn new(a: &Vector, g: f64) -> Metallic {
-        Metallic {
-            albedo: *a,
-            glossiness: g.min(1.0).max(0.0),
+        Metallic::textured(Arc::new(ConstantTexture::new(*a)),
+                           Arc::new(ConstantTexture::new(g.clamp(0.0, 1.0))))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture<Vector>>, glossiness: Arc<dyn Texture<f64>>) -> Metallic {
+        Metallic { albedo, glossiness }
+    }
+}
+
+// A thin transparent coating, such as a soap film, a layer of oil or the oxide on
+// anodized metal, whose thickness is comparable to the wavelength of light: waves
+// reflected from its top and bottom interfere, which produces iridescent colors
+pub struct ThinFilm {
+    pub ior: f64,
+    // In nanometers, where visible colors appear for films up to about a micron
+    pub thickness: Arc<dyn Texture<f64>>,
+}
+
+impl ThinFilm {
+    // The RGB reflectance of the film over a substrate, evaluated per wavelength
+    pub fn reflectance(&self,
+                       cos_theta_i: f64,
+                       eta: &Vector,
+                       k: &Vector,
+                       intersection: &DifferentialGeometry)
+                       -> Vector {
+        let thickness = self.thickness.evaluate(intersection).max(0.0);
+        fresnel::thin_film_rgb(cos_theta_i, self.ior, thickness, eta, k)
+    }
+
+    pub fn new(ior: f64, thickness: f64) -> ThinFilm {
+        ThinFilm::textured(ior, Arc::new(ConstantTexture::new(thickness)))
+    }
+
+    pub fn textured(ior: f64, thickness: Arc<dyn Texture<f64>>) -> ThinFilm {
+        ThinFilm { ior, thickness }
+    }
+}
+
+// A metal, described by its complex index of refraction, with microfacet
+// roughness that may differ along the surface's two tangents
+pub struct Conductor {
+    pub eta: Arc<dyn Texture<Vector>>,
+    // The extinction coefficient, i.e. the imaginary part of the index of refraction
+    pub k: Arc<dyn Texture<Vector>>,
+    // Perceptual roughness along the directions of dpdu and dpdv, in the range 0..1
+    pub roughness_u: Arc<dyn Texture<f64>>,
+    pub roughness_v: Arc<dyn Texture<f64>>,
+    pub model: MicrofacetModel,
+    pub film: Option<ThinFilm>,
+}
+
+impl Conductor {
+    fn distribution(&self, intersection: &DifferentialGeometry) -> MicrofacetDistribution {
+        MicrofacetDistribution::new(self.model,
+                                    roughness_to_alpha(self.roughness_u.evaluate(intersection)),
+                                    roughness_to_alpha(self.roughness_v.evaluate(intersection)))
+    }
+
+    fn fresnel(&self, cos_theta: f64, intersection: &DifferentialGeometry) -> Vector {
+        let (eta, k) = (self.eta.evaluate(intersection), self.k.evaluate(intersection));
+        match self.film {
+            Some(ref film) => film.reflectance(cos_theta, &eta, &k, intersection),
+            None => fresnel::conductor_rgb(cos_theta, &eta, &k),
+        }
+    }
+}
+
+impl Material for Conductor {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        if self.distribution(intersection).is_smooth() {
+            Lobes::SPECULAR | Lobes::REFLECTION
+        } else {
+            Lobes::GLOSSY | Lobes::REFLECTION
+        }
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let frame = intersection.shading_frame();
+        let mut wo = frame.to_local(wo);
+
+        // Metals are opaque, so treat both sides of the surface alike
+        let flipped = wo.z < 0.0;
+        if flipped {
+            wo.z = -wo.z;
+        }
+
+        let distribution = self.distribution(intersection);
+        let (mut wi, f, pdf, lobe) = if distribution.is_smooth() {
+            // Perfect specular reflection
+            let wi = Vector::new(-wo.x, -wo.y, wo.z);
+            (wi, self.fresnel(wo.z, intersection) / wo.z, 1.0, Lobes::SPECULAR | Lobes::REFLECTION)
+        } else {
+            let mut rng = rand::thread_rng();
+            let wm = distribution.sample_wm(&wo, (rng.next_f64(), rng.next_f64()));
+            let wi = (-wo).reflect(&wm);
+            if wi.z <= 0.0 {
+                return None;
+            }
+            (wi,
+             self.evaluate_local(&wo, &wi, &distribution, intersection),
+             distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm)),
+             Lobes::GLOSSY | Lobes::REFLECTION)
+        };
+        if pdf <= 0.0 {
+            return None;
+        }
+
+        if flipped {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample::new(&frame.to_world(&wi), &f, pdf, lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let distribution = self.distribution(intersection);
+        let frame = intersection.shading_frame();
+        let (mut wo, mut wi) = (frame.to_local(wo), frame.to_local(wi));
+        if distribution.is_smooth() || !same_hemisphere(&wo, &wi) {
+            return Vector::zero();
+        }
+        if wo.z < 0.0 {
+            wo.z = -wo.z;
+            wi.z = -wi.z;
+        }
+        self.evaluate_local(&wo, &wi, &distribution, intersection)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let distribution = self.distribution(intersection);
+        let frame = intersection.shading_frame();
+        let (mut wo, mut wi) = (frame.to_local(wo), frame.to_local(wi));
+        if distribution.is_smooth() || !same_hemisphere(&wo, &wi) {
+            return 0.0;
+        }
+        if wo.z < 0.0 {
+            wo.z = -wo.z;
+            wi.z = -wi.z;
         }
+        let wm = (wo + wi).normalize();
+        distribution.pdf(&wo, &wm) / (4.0 * wo.dot(&wm).abs())
+    }
+}
+
+impl Conductor {
+    // Evaluates the Torrance-Sparrow model for directions in the upper hemisphere
+    // of the shading frame
+    fn evaluate_local(&self,
+                      wo: &Vector,
+                      wi: &Vector,
+                      distribution: &MicrofacetDistribution,
+                      intersection: &DifferentialGeometry)
+                      -> Vector {
+        let wm = *wo + *wi;
+        if wo.z == 0.0 || wi.z == 0.0 || wm.length() == 0.0 {
+            return Vector::zero();
+        }
+        let wm = wm.normalize();
+        self.fresnel(wo.dot(&wm), intersection) *
+        (distribution.d(&wm) * distribution.g(wo, wi) / (4.0 * wo.z * wi.z))
+    }
+
+    pub fn new(eta: &Vector, k: &Vector, roughness: f64) -> Conductor {
+        Conductor::anisotropic(eta, k, roughness, roughness)
+    }
+
+    pub fn anisotropic(eta: &Vector, k: &Vector, roughness_u: f64, roughness_v: f64) -> Conductor {
+        Conductor::textured(Arc::new(ConstantTexture::new(*eta)),
+                            Arc::new(ConstantTexture::new(*k)),
+                            Arc::new(ConstantTexture::new(roughness_u)),
+                            Arc::new(ConstantTexture::new(roughness_v)))
+    }
+
+    pub fn textured(eta: Arc<dyn Texture<Vector>>,
+                    k: Arc<dyn Texture<Vector>>,
+                    roughness_u: Arc<dyn Texture<f64>>,
+                    roughness_v: Arc<dyn Texture<f64>>)
+                    -> Conductor {
+        Conductor {
+            eta,
+            k,
+            roughness_u,
+            roughness_v,
+            model: MicrofacetModel::TrowbridgeReitz,
+            film: None,
+        }
+    }
+
+    // Coats the metal with a thin film, such as an oxide layer
+    pub fn with_thin_film(self, film: ThinFilm) -> Conductor {
+        Conductor { film: Some(film), ..self }
+    }
+
+    // Measured optical constants, averaged over the wavelengths that contribute
+    // to each of the red, green and blue channels
+    pub fn gold(roughness: f64) -> Conductor {
+        Conductor::new(&Vector::new(0.143119, 0.374957, 1.44248),
+                       &Vector::new(3.98316, 2.38572, 1.60322),
+                       roughness)
+    }
+
+    pub fn copper(roughness: f64) -> Conductor {
+        Conductor::new(&Vector::new(0.200438, 0.924033, 1.10221),
+                       &Vector::new(3.91295, 2.45285, 2.14219),
+                       roughness)
+    }
+
+    pub fn aluminium(roughness: f64) -> Conductor {
+        Conductor::new(&Vector::new(1.65746, 0.880369, 0.521229),
+                       &Vector::new(9.22387, 6.26952, 4.837),
+                       roughness)
+    }
+
+    pub fn silver(roughness: f64) -> Conductor {
+        Conductor::new(&Vector::new(0.155265, 0.116723, 0.138342),
+                       &Vector::new(4.82835, 3.12225, 2.14696),
+                       roughness)
     }
 }
 
 pub struct Dielectric {
-    pub ior: f64,
+    pub ior: Arc<dyn Texture<f64>>,
+    // Replaces Schlick's approximation of the reflectance with that of a thin film
+    // on the surface, which is assumed to look the same from both sides
+    pub film: Option<ThinFilm>,
 }
 
 impl Material for Dielectric {
-    fn scatter(&self,
-               incident: &Ray,
-               intersection: &DifferentialGeometry,
-               attenuation: &mut Vector)
-               -> Ray {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        Lobes::SPECULAR | Lobes::REFLECTION | Lobes::TRANSMISSION
+    }
 
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
         // The index of refraction (IOR) of a particular medium is defined
         // as the speed of light in a vacuum divided by the speed of light
         // in the medium:
@@ -94,7 +515,8 @@ impl Material for Dielectric {
         //              n_i * sin(theta_i) = n_t * sin(theta_t)
         //
         // So, sin(theta_t) = (n_i / n_t) * sin(theta_i)
-        let mut ior = self.ior;
+        let mut ior = self.ior.evaluate(intersection);
+        let substrate = ior;
 
         // R0 is the probability of reflection at normal incidence, which
         // is given by the equation:
@@ -106,43 +528,301 @@ impl Material for Dielectric {
 
         // Check if the incident ray is inside of the medium, in which case
         // flip the normal
-        let mut outward_normal = intersection.normal;
-        if incident.direction.dot(&outward_normal) > 0.0 {
+        let mut outward_normal = intersection.shading_normal;
+        if !intersection.front_face {
             outward_normal *= -1.0;
             ior = 1.0 / ior;
         }
         ior = 1.0 / ior;
 
         // Calculate angles
-        let cos_theta_i = incident.direction.dot(&outward_normal) * -1.0;
+        let direction = -*wo;
+        let cos_theta_i = -direction.dot(&outward_normal);
         let cos_theta_t = 1.0 - ior * ior * (1.0 - cos_theta_i * cos_theta_i);
 
-        // Schlick's approximation
-        let probability_of_reflection = r0 + (1.0 - r0) * (1.0 - cos_theta_i).powf(5.0);
+        // Schlick's approximation, unless the surface has a thin film: for light
+        // arriving from inside, its reflectance is evaluated for the angle on the
+        // outside, which is the same for films that don't absorb light
+        let reflectance = match self.film {
+            Some(ref film) if cos_theta_t > 0.0 => {
+                let cos_theta_outside = if intersection.front_face { cos_theta_i } else { cos_theta_t.sqrt() };
+                film.reflectance(cos_theta_outside, &(Vector::one() * substrate), &Vector::zero(), intersection)
+            }
+            _ => Vector::one() * (r0 + (1.0 - r0) * (1.0 - cos_theta_i).powf(5.0)),
+        };
+        let probability_of_reflection = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
         let mut rng = rand::thread_rng();
-        let mut scattered: Vector;
 
         // Check for total internal reflection (when cos_theta_t is negative)
-        if cos_theta_t > 0.0 && rng.next_f64() > probability_of_reflection {
+        let (wi, fraction, probability, lobe) = if cos_theta_t > 0.0 && rng.next_f64() > probability_of_reflection {
             // Refract
-            scattered = (incident.direction * ior) +
-                        (outward_normal * (ior * cos_theta_i - cos_theta_t.sqrt()));
-        } else {
+            ((direction * ior) + (outward_normal * (ior * cos_theta_i - cos_theta_t.sqrt())),
+             Vector::one() - reflectance,
+             1.0 - probability_of_reflection,
+             Lobes::SPECULAR | Lobes::TRANSMISSION)
+        } else if cos_theta_t > 0.0 {
             // Reflect
-            scattered = incident.direction.reflect(&outward_normal);
+            (direction.reflect(&outward_normal),
+             reflectance,
+             probability_of_reflection,
+             Lobes::SPECULAR | Lobes::REFLECTION)
+        } else {
+            // Total internal reflection
+            (direction.reflect(&outward_normal), Vector::one(), 1.0, Lobes::SPECULAR | Lobes::REFLECTION)
+        };
+
+        // Without a film, the reflectance and transmittance cancel with the
+        // probabilities of choosing each lobe
+        let wi = wi.normalize();
+        let cos_theta = wi.dot(&intersection.shading_normal).abs();
+        if cos_theta == 0.0 || probability <= 0.0 {
+            return None;
         }
+        Some(BsdfSample::new(&wi, &(fraction / cos_theta), probability, lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        Vector::zero()
+    }
 
-        *attenuation = Vector::one();
-        let refracted = incident.direction.refract(&intersection.normal);
-        Ray::new(&intersection.position,
-                 &scattered,
-                 incident.t_min,
-                 incident.t_max)
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        0.0
     }
 }
 
 impl Dielectric {
     pub fn new(i: f64) -> Dielectric {
-        Dielectric { ior: i }
+        Dielectric::textured(Arc::new(ConstantTexture::new(i)))
+    }
+
+    pub fn textured(ior: Arc<dyn Texture<f64>>) -> Dielectric {
+        Dielectric { ior, film: None }
+    }
+
+    // A soap bubble: a thin film of water with air on either side
+    pub fn bubble(thickness: Arc<dyn Texture<f64>>) -> Dielectric {
+        Dielectric::new(1.0).with_thin_film(ThinFilm::textured(1.33, thickness))
+    }
+
+    pub fn with_thin_film(self, film: ThinFilm) -> Dielectric {
+        Dielectric { film: Some(film), ..self }
+    }
+}
+
+// A dielectric with a microfacet surface (Walter et al. 2007), such as frosted
+// glass, which both reflects and transmits light over a range of directions
+//
+// Like `Dielectric`, this doesn't scale radiance by the squared ratio of the
+// indices of refraction, which cancels out for rays that leave the medium again
+pub struct RoughDielectric {
+    pub ior: Arc<dyn Texture<f64>>,
+    // Perceptual roughness, in the range 0..1
+    pub roughness: Arc<dyn Texture<f64>>,
+    pub model: MicrofacetModel,
+}
+
+impl RoughDielectric {
+    fn distribution(&self, intersection: &DifferentialGeometry) -> MicrofacetDistribution {
+        let alpha = roughness_to_alpha(self.roughness.evaluate(intersection));
+        MicrofacetDistribution::new(self.model, alpha, alpha)
+    }
+
+    // The relative index of refraction for light leaving the surface along the
+    // local direction `wo`
+    fn eta(&self, wo: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let ior = self.ior.evaluate(intersection);
+        if wo.z < 0.0 { 1.0 / ior } else { ior }
+    }
+
+    // Returns the microfacet normal that scatters `wo` into `wi`, or `None` if the
+    // configuration is impossible
+    fn half_vector(wo: &Vector, wi: &Vector, eta: f64) -> Option<Vector> {
+        let reflected = wi.z > 0.0;
+        let wm = if reflected { *wo + *wi } else { *wo + *wi * eta };
+        if wm.length() == 0.0 || wi.z == 0.0 {
+            return None;
+        }
+        let wm = wm.normalize();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+
+        // Discard back-facing microfacets
+        if wo.dot(&wm) <= 0.0 || (wi.dot(&wm) > 0.0) != reflected {
+            return None;
+        }
+        Some(wm)
+    }
+
+    // Evaluates the BSDF and pdf for directions where `wo` is on the outside
+    fn evaluate_local(&self,
+                      wo: &Vector,
+                      wi: &Vector,
+                      eta: f64,
+                      distribution: &MicrofacetDistribution)
+                      -> (f64, f64) {
+        let wm = match RoughDielectric::half_vector(wo, wi, eta) {
+            Some(wm) => wm,
+            None => return (0.0, 0.0),
+        };
+        let reflectance = fresnel::dielectric(wo.dot(&wm), eta);
+        let d = distribution.d(&wm);
+        let g = distribution.g(wo, wi);
+        let pdf_wm = distribution.pdf(wo, &wm);
+        if wi.z > 0.0 {
+            (d * g * reflectance / (4.0 * wo.z * wi.z),
+             pdf_wm / (4.0 * wo.dot(&wm)) * reflectance)
+        } else {
+            let denominator = (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+            let dwm_dwi = wi.dot(&wm).abs() / denominator;
+            (d * g * (1.0 - reflectance) * (wi.dot(&wm) * wo.dot(&wm) / (wi.z * wo.z * denominator)).abs(),
+             pdf_wm * dwm_dwi * (1.0 - reflectance))
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        if self.distribution(intersection).is_smooth() {
+            Lobes::SPECULAR | Lobes::REFLECTION | Lobes::TRANSMISSION
+        } else {
+            Lobes::GLOSSY | Lobes::REFLECTION | Lobes::TRANSMISSION
+        }
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let frame = intersection.shading_frame();
+        let local = frame.to_local(wo);
+        let eta = self.eta(&local, intersection);
+        let (wo, _) = orient(&local, &local);
+        let inside = local.z < 0.0;
+
+        let distribution = self.distribution(intersection);
+        let mut rng = rand::thread_rng();
+        let smooth = distribution.is_smooth();
+        let wm = if smooth {
+            Vector::new(0.0, 0.0, 1.0)
+        } else {
+            distribution.sample_wm(&wo, (rng.next_f64(), rng.next_f64()))
+        };
+
+        // Choose between reflection and transmission based on the Fresnel
+        // reflectance of the sampled microfacet
+        let reflectance = fresnel::dielectric(wo.dot(&wm), eta);
+        let (mut wi, lobe) = match fresnel::refract(&wo, &wm, eta) {
+            Some(transmitted) if rng.next_f64() >= reflectance => (transmitted, Lobes::TRANSMISSION),
+            _ => ((-wo).reflect(&wm), Lobes::REFLECTION),
+        };
+
+        let (f, pdf, lobe) = if smooth {
+            let probability = if lobe == Lobes::REFLECTION { reflectance } else { 1.0 - reflectance };
+            (probability / wi.z.abs(), probability, Lobes::SPECULAR | lobe)
+        } else {
+            let (f, pdf) = self.evaluate_local(&wo, &wi, eta, &distribution);
+            (f, pdf, Lobes::GLOSSY | lobe)
+        };
+        if pdf <= 0.0 || wi.z == 0.0 {
+            return None;
+        }
+
+        if inside {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample::new(&frame.to_world(&wi), &(Vector::one() * f), pdf, lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let distribution = self.distribution(intersection);
+        if distribution.is_smooth() {
+            return Vector::zero();
+        }
+        let frame = intersection.shading_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let eta = self.eta(&wo, intersection);
+        let (wo, wi) = orient(&wo, &wi);
+        Vector::one() * self.evaluate_local(&wo, &wi, eta, &distribution).0
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let distribution = self.distribution(intersection);
+        if distribution.is_smooth() {
+            return 0.0;
+        }
+        let frame = intersection.shading_frame();
+        let (wo, wi) = (frame.to_local(wo), frame.to_local(wi));
+        let eta = self.eta(&wo, intersection);
+        let (wo, wi) = orient(&wo, &wi);
+        self.evaluate_local(&wo, &wi, eta, &distribution).1
+    }
+}
+
+impl RoughDielectric {
+    pub fn new(ior: f64, roughness: f64) -> RoughDielectric {
+        RoughDielectric::textured(Arc::new(ConstantTexture::new(ior)),
+                                  Arc::new(ConstantTexture::new(roughness)))
+    }
+
+    pub fn textured(ior: Arc<dyn Texture<f64>>, roughness: Arc<dyn Texture<f64>>) -> RoughDielectric {
+        RoughDielectric {
+            ior,
+            roughness,
+            model: MicrofacetModel::TrowbridgeReitz,
+        }
+    }
+}
+
+// The intersection of a ray travelling down the z-axis from (x, y, 3) with the
+// unit sphere, along with the direction towards the viewer
+#[cfg(test)]
+pub fn sphere_intersection(x: f64, y: f64) -> (DifferentialGeometry<'static>, Vector) {
+    use ray::Ray;
+    use shape::{Shape, Sphere};
+
+    let sphere: &'static Sphere = Box::leak(Box::new(Sphere::new(&Vector::zero(), 1.0)));
+    let incident = Ray::new(&Vector::new(x, y, 3.0), &Vector::new(0.0, 0.0, -1.0), 0.0, 10.0);
+    (sphere.intersect(&incident).unwrap(), -incident.direction)
+}
+
+// Checks that the values returned by sampling a material agree with evaluating
+// it for the sampled direction, which doesn't apply to specular lobes
+#[cfg(test)]
+pub fn assert_sample_consistent(material: &dyn Material, wo: &Vector, dg: &DifferentialGeometry) {
+    for _ in 0..1000 {
+        if let Some(sample) = material.sample(wo, dg) {
+            if !sample.lobe.contains(Lobes::SPECULAR) {
+                let f = material.evaluate(wo, &sample.wi, dg);
+                let pdf = material.pdf(wo, &sample.wi, dg);
+                assert!((f - sample.f).length() <= 1.0e-6 * (1.0 + f.length()));
+                assert!((pdf - sample.pdf).abs() <= 1.0e-6 * (1.0 + pdf));
+            }
+        }
+    }
+}
+
+#[test]
+fn test_rough_dielectric_energy() {
+    // Without absorption, a rough dielectric only loses the energy of paths that
+    // would scatter between microfacets
+    let (dg, wo) = sphere_intersection(0.0, 0.0);
+    for &roughness in &[0.0, 0.3] {
+        let material = RoughDielectric::new(1.5, roughness);
+        let mut total = 0.0;
+        for _ in 0..10000 {
+            if let Some(sample) = material.sample(&wo, &dg) {
+                total += sample.weight(&dg).x;
+            }
+        }
+        let albedo = total / 10000.0;
+        assert!(albedo <= 1.0 + 1.0e-9 && albedo > 0.95);
+    }
+}
+
+#[test]
+fn test_sample_matches_evaluate() {
+    let (dg, wo) = sphere_intersection(0.5, 0.2);
+    let materials: Vec<Box<dyn Material>> = vec![Box::new(Lambertian::new(&Vector::one())),
+                                                 Box::new(OrenNayar::new(&Vector::one(), 30.0)),
+                                                 Box::new(Conductor::anisotropic(&Vector::one(), &Vector::one(), 0.3, 0.6)),
+                                                 Box::new(RoughDielectric::new(1.5, 0.4))];
+    for material in &materials {
+        assert_sample_consistent(material.as_ref(), &wo, &dg);
     }
 }
@@ -0,0 +1,304 @@
+// Measured, isotropic BRDFs, tabulated over Rusinkiewicz's half-angle / difference-
+// angle parameterization: this is the layout of the MERL database (Matusik et al.
+// 2003), which measured 100 real-world materials
+use vector::Vector;
+use shape::DifferentialGeometry;
+use material::{Material, BsdfSample, Lobes, orient};
+use sampling::Distribution2D;
+use image::invalid_data;
+
+use std::f64;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+extern crate rand;
+use rand::Rng;
+
+// The resolution of the tables that are used for importance sampling: one table
+// over the elevation and relative azimuth of `wi` for each range of elevations of
+// `wo`
+const SAMPLING_RESOLUTION: (usize, usize, usize) = (16, 32, 64);
+
+pub struct Measured {
+    // The number of entries along the half angle's elevation, the difference
+    // angle's elevation and the difference angle's azimuth (which, by reciprocity,
+    // only spans half a circle)
+    pub resolution: (usize, usize, usize),
+    // The values of the BRDF, with the azimuth varying the fastest
+    pub values: Vec<Vector>,
+    sampling: Vec<Distribution2D>,
+}
+
+// Converts a pair of directions in the upper hemisphere to the elevation of their
+// half vector and the elevation and azimuth of `wi` relative to it
+fn half_difference(wo: &Vector, wi: &Vector) -> (f64, f64, f64) {
+    let h = (*wo + *wi).normalize();
+    let theta_h = h.z.clamp(-1.0, 1.0).acos();
+    let phi_h = h.y.atan2(h.x);
+
+    // Rotate `wi` so that the half vector becomes the z-axis
+    let (sin_phi, cos_phi) = phi_h.sin_cos();
+    let (sin_theta, cos_theta) = theta_h.sin_cos();
+    let x = wi.x * cos_phi + wi.y * sin_phi;
+    let y = -wi.x * sin_phi + wi.y * cos_phi;
+    let d = Vector::new(x * cos_theta - wi.z * sin_theta, y, x * sin_theta + wi.z * cos_theta);
+
+    let mut phi_d = d.y.atan2(d.x);
+    if phi_d < 0.0 {
+        phi_d += f64::consts::PI;
+    }
+    (theta_h, d.z.clamp(-1.0, 1.0).acos(), phi_d)
+}
+
+impl Measured {
+    // Looks up the BRDF for directions in the upper hemisphere of the shading
+    // frame, using the nearest entry of the table
+    fn evaluate_local(&self, wo: &Vector, wi: &Vector) -> Vector {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return Vector::zero();
+        }
+        let (theta_h, theta_d, phi_d) = half_difference(wo, wi);
+        let (n_theta_h, n_theta_d, n_phi_d) = self.resolution;
+
+        // The half angle's elevation is sampled more densely near the normal, where
+        // specular peaks are found
+        let index = |t: f64, n: usize| ((t * n as f64) as usize).min(n - 1);
+        let i = index((theta_h / f64::consts::FRAC_PI_2).max(0.0).sqrt(), n_theta_h);
+        let j = index(theta_d / f64::consts::FRAC_PI_2, n_theta_d);
+        let k = index(phi_d / f64::consts::PI, n_phi_d);
+        self.values[(i * n_theta_d + j) * n_phi_d + k]
+    }
+
+    // The sampling table for the elevation of `wo`, along with the position of
+    // `wi` within it
+    fn sampling_coordinates(&self, wo: &Vector, wi: &Vector) -> (&Distribution2D, (f64, f64)) {
+        let theta_o = wo.z.clamp(0.0, 1.0).acos();
+        let index = ((theta_o / f64::consts::FRAC_PI_2 * self.sampling.len() as f64) as usize)
+            .min(self.sampling.len() - 1);
+        let theta_i = wi.z.clamp(0.0, 1.0).acos();
+        let mut phi = wi.y.atan2(wi.x) - wo.y.atan2(wo.x);
+        if phi < 0.0 {
+            phi += 2.0 * f64::consts::PI;
+        }
+        (&self.sampling[index],
+         (theta_i / f64::consts::FRAC_PI_2, (phi / (2.0 * f64::consts::PI)).min(1.0 - 1.0e-9)))
+    }
+
+    // Converts a density over the sampling table's coordinates to one over solid
+    // angle
+    fn solid_angle_pdf(pdf: f64, theta_i: f64) -> f64 {
+        let sin_theta = theta_i.sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        pdf / (f64::consts::FRAC_PI_2 * 2.0 * f64::consts::PI * sin_theta)
+    }
+
+    fn pdf_local(&self, wo: &Vector, wi: &Vector) -> f64 {
+        if wo.z <= 0.0 || wi.z <= 0.0 {
+            return 0.0;
+        }
+        let (distribution, p) = self.sampling_coordinates(wo, wi);
+        Measured::solid_angle_pdf(distribution.pdf(p), p.0 * f64::consts::FRAC_PI_2)
+    }
+
+    // Builds the sampling tables, which are proportional to the luminance of the
+    // BRDF times the cosine of `wi`: a small constant is added so that directions
+    // whose entries were missed by the table's cells can still be sampled
+    fn build_sampling(&mut self) {
+        let (n_o, n_theta, n_phi) = SAMPLING_RESOLUTION;
+        self.sampling = (0..n_o)
+            .map(|o| {
+                let theta_o = (o as f64 + 0.5) / n_o as f64 * f64::consts::FRAC_PI_2;
+                let wo = Vector::new(theta_o.sin(), 0.0, theta_o.cos());
+                let mut function = Vec::with_capacity(n_theta * n_phi);
+                for j in 0..n_phi {
+                    let phi = (j as f64 + 0.5) / n_phi as f64 * 2.0 * f64::consts::PI;
+                    for i in 0..n_theta {
+                        let theta = (i as f64 + 0.5) / n_theta as f64 * f64::consts::FRAC_PI_2;
+                        let wi = Vector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                        function.push(self.evaluate_local(&wo, &wi).luminance().max(0.0) * wi.z * theta.sin());
+                    }
+                }
+                let mean = function.iter().sum::<f64>() / function.len() as f64;
+                let floor = 0.01 * mean + 1.0e-8;
+                let function: Vec<f64> = function.iter().map(|f| f + floor).collect();
+                Distribution2D::new(&function, n_theta, n_phi)
+            })
+            .collect();
+    }
+}
+
+impl Material for Measured {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        Lobes::DIFFUSE | Lobes::GLOSSY | Lobes::REFLECTION
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let frame = intersection.shading_frame();
+        let local = frame.to_local(wo);
+        let (wo_local, _) = orient(&local, &local);
+        let theta_o = wo_local.z.clamp(0.0, 1.0).acos();
+        let index = ((theta_o / f64::consts::FRAC_PI_2 * self.sampling.len() as f64) as usize)
+            .min(self.sampling.len() - 1);
+
+        let mut rng = rand::thread_rng();
+        let (p, _) = self.sampling[index].sample_continuous((rng.next_f64(), rng.next_f64()));
+        let theta_i = p.0 * f64::consts::FRAC_PI_2;
+        let phi_i = wo_local.y.atan2(wo_local.x) + p.1 * 2.0 * f64::consts::PI;
+        let mut wi = Vector::new(theta_i.sin() * phi_i.cos(), theta_i.sin() * phi_i.sin(), theta_i.cos());
+
+        let pdf = self.pdf_local(&wo_local, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let f = self.evaluate_local(&wo_local, &wi);
+        if local.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample::new(&frame.to_world(&wi), &f, pdf, Lobes::GLOSSY | Lobes::REFLECTION))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let frame = intersection.shading_frame();
+        let (wo, wi) = orient(&frame.to_local(wo), &frame.to_local(wi));
+        self.evaluate_local(&wo, &wi)
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let frame = intersection.shading_frame();
+        let (wo, wi) = orient(&frame.to_local(wo), &frame.to_local(wi));
+        self.pdf_local(&wo, &wi)
+    }
+}
+
+impl Measured {
+    // Builds a BRDF from a table of values, see `resolution` and `values` for the
+    // layout: negative values mark missing measurements and are treated as zero
+    pub fn new(resolution: (usize, usize, usize), values: Vec<Vector>) -> Measured {
+        assert!(resolution.0 > 0 && resolution.1 > 0 && resolution.2 > 0);
+        assert_eq!(values.len(), resolution.0 * resolution.1 * resolution.2);
+        let mut measured = Measured {
+            resolution,
+            values: values.iter().map(|v| v.max(&Vector::zero())).collect(),
+            sampling: vec![],
+        };
+        measured.build_sampling();
+        measured
+    }
+
+    // Tabulates a BRDF given as a function of the half angle's elevation and the
+    // difference angle's elevation and azimuth, in radians
+    pub fn tabulate<F: Fn(f64, f64, f64) -> Vector>(resolution: (usize, usize, usize), brdf: F) -> Measured {
+        let (n_theta_h, n_theta_d, n_phi_d) = resolution;
+        let mut values = Vec::with_capacity(n_theta_h * n_theta_d * n_phi_d);
+        for i in 0..n_theta_h {
+            let t = (i as f64 + 0.5) / n_theta_h as f64;
+            let theta_h = t * t * f64::consts::FRAC_PI_2;
+            for j in 0..n_theta_d {
+                let theta_d = (j as f64 + 0.5) / n_theta_d as f64 * f64::consts::FRAC_PI_2;
+                for k in 0..n_phi_d {
+                    values.push(brdf(theta_h, theta_d, (k as f64 + 0.5) / n_phi_d as f64 * f64::consts::PI));
+                }
+            }
+        }
+        Measured::new(resolution, values)
+    }
+
+    // Decodes a BRDF in the MERL binary format: the dimensions of the table as
+    // three 32-bit integers, followed by the red, green and blue tables as 64-bit
+    // floats (all little-endian)
+    pub fn decode_merl(bytes: &[u8]) -> io::Result<Measured> {
+        if bytes.len() < 12 {
+            return Err(invalid_data("truncated MERL header"));
+        }
+        let dimension = |i: usize| {
+            let mut word = [0u8; 4];
+            word.copy_from_slice(&bytes[i * 4..i * 4 + 4]);
+            i32::from_le_bytes(word)
+        };
+        let (n_theta_h, n_theta_d, n_phi_d) = (dimension(0), dimension(1), dimension(2));
+        if n_theta_h <= 0 || n_theta_d <= 0 || n_phi_d <= 0 {
+            return Err(invalid_data("invalid MERL dimensions"));
+        }
+        let resolution = (n_theta_h as usize, n_theta_d as usize, n_phi_d as usize);
+        // The dimensions come straight from the file, so they may not describe a
+        // table that fits in memory
+        let n = resolution.0
+            .checked_mul(resolution.1)
+            .and_then(|n| n.checked_mul(resolution.2))
+            .ok_or_else(|| invalid_data("invalid MERL dimensions"))?;
+        let size = n.checked_mul(3 * 8)
+            .and_then(|size| size.checked_add(12))
+            .ok_or_else(|| invalid_data("invalid MERL dimensions"))?;
+        if bytes.len() != size {
+            return Err(invalid_data("MERL data doesn't match its dimensions"));
+        }
+
+        // The scale factors of each channel, from the database's reference code
+        const SCALE: [f64; 3] = [1.0 / 1500.0, 1.15 / 1500.0, 1.66 / 1500.0];
+        let value = |channel: usize, i: usize| {
+            let offset = 12 + (channel * n + i) * 8;
+            let mut word = [0u8; 8];
+            word.copy_from_slice(&bytes[offset..offset + 8]);
+            f64::from_le_bytes(word) * SCALE[channel]
+        };
+        let values = (0..n).map(|i| Vector::new(value(0, i), value(1, i), value(2, i))).collect();
+        Ok(Measured::new(resolution, values))
+    }
+
+    pub fn load(path: &Path) -> io::Result<Measured> {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        Measured::decode_merl(&bytes)
+    }
+}
+
+#[test]
+fn test_measured() {
+    use material::{sphere_intersection, assert_sample_consistent};
+
+    let (dg, wo) = sphere_intersection(0.4, 0.3);
+
+    // A diffuse table reflects its albedo, and samples agree with evaluating it
+    let albedo = 0.7;
+    let mut bytes = Vec::new();
+    for &d in &[4i32, 4, 8] {
+        bytes.extend_from_slice(&d.to_le_bytes());
+    }
+    for channel in 0..3 {
+        for _ in 0..4 * 4 * 8 {
+            bytes.extend_from_slice(&(albedo / f64::consts::PI / [1.0, 1.15, 1.66][channel] * 1500.0).to_le_bytes());
+        }
+    }
+    let diffuse = Measured::decode_merl(&bytes).unwrap();
+    assert_sample_consistent(&diffuse, &wo, &dg);
+    let mut total = 0.0;
+    for _ in 0..20000 {
+        if let Some(sample) = diffuse.sample(&wo, &dg) {
+            total += sample.weight(&dg).y;
+        }
+    }
+    assert!((total / 20000.0 - albedo).abs() < 0.02);
+    assert!(Measured::decode_merl(&bytes[..bytes.len() - 1]).is_err());
+    let huge: Vec<u8> = (0..3).flat_map(|_| i32::MAX.to_le_bytes().to_vec()).collect();
+    assert!(Measured::decode_merl(&huge).is_err());
+
+    // A glossy table concentrates its samples near the mirror direction
+    let glossy = Measured::tabulate((90, 90, 180), |theta_h, _, _| {
+        Vector::one() * (-(theta_h / 0.1).powi(2)).exp() * 20.0
+    });
+    let frame = dg.shading_frame();
+    let mirror = (-wo).reflect(&dg.shading_normal);
+    let mut close = 0;
+    for _ in 0..1000 {
+        if let Some(sample) = glossy.sample(&wo, &dg) {
+            if sample.wi.dot(&mirror) > 0.9 && frame.to_local(&sample.wi).z > 0.0 {
+                close += 1;
+            }
+        }
+    }
+    assert!(close > 800);
+}
@@ -0,0 +1,196 @@
+// Microfacet distributions, which describe rough surfaces as a collection of tiny
+// mirrors: all directions are expressed in a local shading frame, where the
+// macro-surface normal is the z-axis
+use vector::Vector;
+
+use std::f64;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MicrofacetModel {
+    // Also known as GGX, which has longer tails than Beckmann
+    TrowbridgeReitz,
+    Beckmann,
+}
+
+// Below this, surfaces are treated as perfectly smooth
+pub const SMOOTH_ALPHA: f64 = 1.0e-3;
+
+// Converts a perceptually linear roughness in the range 0..1 to the distribution's
+// width parameter
+pub fn roughness_to_alpha(roughness: f64) -> f64 {
+    let roughness = roughness.clamp(0.0, 1.0);
+    roughness * roughness
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MicrofacetDistribution {
+    pub model: MicrofacetModel,
+    // The roughness along the frame's first and second tangents
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl MicrofacetDistribution {
+    pub fn new(model: MicrofacetModel, alpha_x: f64, alpha_y: f64) -> MicrofacetDistribution {
+        MicrofacetDistribution {
+            model,
+            alpha_x: alpha_x.max(1.0e-4),
+            alpha_y: alpha_y.max(1.0e-4),
+        }
+    }
+
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH_ALPHA
+    }
+
+    // The density of microfacets with the given normal, per unit of projected area
+    pub fn d(&self, wm: &Vector) -> f64 {
+        if wm.z <= 0.0 {
+            return 0.0;
+        }
+        let x = wm.x / self.alpha_x;
+        let y = wm.y / self.alpha_y;
+        let z2 = wm.z * wm.z;
+        match self.model {
+            MicrofacetModel::TrowbridgeReitz => {
+                let denominator = x * x + y * y + z2;
+                1.0 / (f64::consts::PI * self.alpha_x * self.alpha_y * denominator * denominator)
+            }
+            MicrofacetModel::Beckmann => {
+                (-(x * x + y * y) / z2).exp() /
+                (f64::consts::PI * self.alpha_x * self.alpha_y * z2 * z2)
+            }
+        }
+    }
+
+    // Smith's auxiliary function, which measures the fraction of microfacets that
+    // are masked when viewed from the given direction
+    pub fn lambda(&self, w: &Vector) -> f64 {
+        let tan2_theta = (w.x * w.x + w.y * w.y) / (w.z * w.z);
+        if !tan2_theta.is_finite() {
+            return 0.0;
+        }
+
+        // The roughness in the azimuthal direction of `w`
+        let projected = (w.x * w.x * self.alpha_x * self.alpha_x + w.y * w.y * self.alpha_y * self.alpha_y) /
+                        (w.x * w.x + w.y * w.y).max(1.0e-16);
+        let alpha2_tan2_theta = projected * tan2_theta;
+        match self.model {
+            MicrofacetModel::TrowbridgeReitz => 0.5 * ((1.0 + alpha2_tan2_theta).sqrt() - 1.0),
+            MicrofacetModel::Beckmann => {
+                // A rational approximation of the exact expression, which involves
+                // the error function
+                let a = 1.0 / alpha2_tan2_theta.sqrt();
+                if a >= 1.6 {
+                    0.0
+                } else {
+                    (1.0 - 1.259 * a + 0.396 * a * a) / (3.535 * a + 2.181 * a * a)
+                }
+            }
+        }
+    }
+
+    // The fraction of microfacets visible from a single direction
+    pub fn g1(&self, w: &Vector) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // The fraction of microfacets visible from both directions, accounting for the
+    // correlation between masking and shadowing
+    pub fn g(&self, wo: &Vector, wi: &Vector) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // Samples a microfacet normal as seen from `wo`: for Trowbridge-Reitz only the
+    // visible normals are sampled (Heitz 2018), while for Beckmann the full
+    // distribution is sampled
+    pub fn sample_wm(&self, wo: &Vector, u: (f64, f64)) -> Vector {
+        match self.model {
+            MicrofacetModel::TrowbridgeReitz => {
+                // Transform the view direction to the hemisphere configuration
+                let wo = if wo.z < 0.0 { -*wo } else { *wo };
+                let vh = Vector::new(self.alpha_x * wo.x, self.alpha_y * wo.y, wo.z).normalize();
+
+                // Build an orthonormal basis around it
+                let length2 = vh.x * vh.x + vh.y * vh.y;
+                let t1 = if length2 > 0.0 {
+                    Vector::new(-vh.y, vh.x, 0.0) / length2.sqrt()
+                } else {
+                    Vector::new(1.0, 0.0, 0.0)
+                };
+                let t2 = vh.cross(&t1);
+
+                // Sample a point on the projected hemisphere, which is a disk
+                // that is partially occluded from grazing angles
+                let r = u.0.sqrt();
+                let phi = 2.0 * f64::consts::PI * u.1;
+                let p1 = r * phi.cos();
+                let p2 = r * phi.sin();
+                let s = 0.5 * (1.0 + vh.z);
+                let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * p2;
+
+                // Reproject onto the hemisphere and transform back to the ellipsoid
+                let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+                Vector::new(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1.0e-6)).normalize()
+            }
+            MicrofacetModel::Beckmann => {
+                let phi = if self.alpha_x == self.alpha_y {
+                    2.0 * f64::consts::PI * u.1
+                } else {
+                    let phi = (self.alpha_y / self.alpha_x *
+                               (2.0 * f64::consts::PI * u.1 + 0.5 * f64::consts::PI).tan())
+                        .atan();
+                    if u.1 > 0.5 { phi + f64::consts::PI } else { phi }
+                };
+                let (sin_phi, cos_phi) = phi.sin_cos();
+                let alpha2 = 1.0 /
+                             (cos_phi * cos_phi / (self.alpha_x * self.alpha_x) +
+                              sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+                let tan2_theta = -alpha2 * (1.0 - u.0).ln();
+                let cos_theta = 1.0 / (1.0 + tan2_theta).sqrt();
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                Vector::new(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta)
+            }
+        }
+    }
+
+    // The probability density of `sample_wm` returning `wm`, with respect to solid
+    // angle
+    pub fn pdf(&self, wo: &Vector, wm: &Vector) -> f64 {
+        match self.model {
+            MicrofacetModel::TrowbridgeReitz => {
+                self.g1(wo) * wo.dot(wm).abs() * self.d(wm) / wo.z.abs()
+            }
+            MicrofacetModel::Beckmann => self.d(wm) * wm.z.abs(),
+        }
+    }
+}
+
+#[test]
+fn test_microfacet_normalization() {
+    // The projected area of the microfacets must equal that of the macro-surface,
+    // and the visible normals seen from any direction must form a distribution
+    let wo = Vector::new(0.6, -0.3, 0.5).normalize();
+    for &model in &[MicrofacetModel::TrowbridgeReitz, MicrofacetModel::Beckmann] {
+        let distribution = MicrofacetDistribution::new(model, 0.3, 0.6);
+        let (mut projected_area, mut visible) = (0.0, 0.0);
+        let (n_theta, n_phi) = (800, 800);
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) / n_theta as f64 * 0.5 * f64::consts::PI;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) / n_phi as f64 * 2.0 * f64::consts::PI;
+                let wm = Vector::new(theta.sin() * phi.cos(), theta.sin() * phi.sin(), theta.cos());
+                let area = theta.sin() * (0.5 * f64::consts::PI / n_theta as f64) *
+                           (2.0 * f64::consts::PI / n_phi as f64);
+                projected_area += distribution.d(&wm) * wm.z * area;
+                if model == MicrofacetModel::TrowbridgeReitz && wo.dot(&wm) > 0.0 {
+                    visible += distribution.pdf(&wo, &wm) * area;
+                }
+            }
+        }
+        assert!((projected_area - 1.0).abs() < 1.0e-2);
+        if model == MicrofacetModel::TrowbridgeReitz {
+            assert!((visible - 1.0).abs() < 1.0e-2);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+use vector::Vector;
+use image::Image;
+
+use std::f64;
+
+// How texture lookups are filtered
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FilterMode {
+    // Bilinear interpolation of the full-resolution image, ignoring the footprint
+    Bilinear,
+    // Bilinear interpolation between the two mip-map levels closest to the
+    // footprint's (isotropic) size
+    Trilinear,
+    // Elliptically weighted averaging, which accounts for anisotropic footprints
+    Ewa,
+}
+
+// How texture coordinates outside of the range 0..1 are handled
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum WrapMode {
+    Repeat,
+    Clamp,
+    Mirror,
+    // Lookups outside of the image return black
+    Black,
+}
+
+// Which values of the image are filtered: opacity is returned in every component
+// of a vector, and is 1 for images without it
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Plane {
+    Color,
+    Alpha,
+}
+
+// The longest axis of an EWA footprint is at most this many times the shortest,
+// which bounds the number of texels that a single lookup touches
+const MAX_ANISOTROPY: f64 = 8.0;
+
+// An image pyramid, where each level is half the resolution of the previous one:
+// like image textures, the top row of each level corresponds to t = 1
+pub struct MipMap {
+    pub levels: Vec<Image>,
+    pub wrap: WrapMode,
+    pub filter: FilterMode,
+}
+
+// Halves the resolution of an image by averaging blocks of 2 x 2 texels, along
+// with their opacity: odd sizes are rounded up, clamping the blocks at the edge
+// of the image
+fn downsample(image: &Image) -> Image {
+    let width = image.width.div_ceil(2);
+    let height = image.height.div_ceil(2);
+    let mut result = Image::new(width, height);
+    let mut alpha = image.alpha.as_ref().map(|_| vec![0.0; width * height]);
+    for y in 0..height {
+        for x in 0..width {
+            let (x0, x1) = (2 * x, (2 * x + 1).min(image.width - 1));
+            let (y0, y1) = (2 * y, (2 * y + 1).min(image.height - 1));
+            let sum = image.get(x0, y0) + image.get(x1, y0) + image.get(x0, y1) + image.get(x1, y1);
+            result.set(x, y, &(sum * 0.25));
+            if let (Some(source), Some(averaged)) = (image.alpha.as_ref(), alpha.as_mut()) {
+                let texel = |x: usize, y: usize| source[y * image.width + x];
+                averaged[y * width + x] = (texel(x0, y0) + texel(x1, y0) + texel(x0, y1) + texel(x1, y1)) * 0.25;
+            }
+        }
+    }
+    result.alpha = alpha;
+    result
+}
+
+impl MipMap {
+    pub fn new(image: Image, wrap: WrapMode, filter: FilterMode) -> MipMap {
+        let mut levels = vec![image];
+        loop {
+            let next = match levels.last() {
+                Some(last) if last.width > 1 || last.height > 1 => downsample(last),
+                _ => break,
+            };
+            levels.push(next);
+        }
+        MipMap {
+            levels,
+            wrap,
+            filter,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    // Returns a texel from the given level, applying the wrap mode
+    fn texel(&self, plane: Plane, level: usize, x: i64, y: i64) -> Vector {
+        let image = &self.levels[level];
+        let (w, h) = (image.width as i64, image.height as i64);
+        let wrap = |i: i64, size: i64| match self.wrap {
+            WrapMode::Repeat => Some(i.rem_euclid(size)),
+            WrapMode::Clamp => Some(i.clamp(0, size - 1)),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                Some(if i >= size { 2 * size - 1 - i } else { i })
+            }
+            WrapMode::Black => if i >= 0 && i < size { Some(i) } else { None },
+        };
+        match (wrap(x, w), wrap(y, h)) {
+            (Some(x), Some(y)) => {
+                let (x, y) = (x as usize, y as usize);
+                match (plane, image.alpha.as_ref()) {
+                    (Plane::Color, _) => image.get(x, y),
+                    (Plane::Alpha, Some(alpha)) => Vector::one() * alpha[y * image.width + x],
+                    (Plane::Alpha, None) => Vector::one(),
+                }
+            }
+            _ => Vector::zero(),
+        }
+    }
+
+    // Interpolates between the four texels surrounding a point, where `st` is in
+    // image space (i.e. t = 0 is the top row)
+    fn bilinear(&self, plane: Plane, level: usize, st: (f64, f64)) -> Vector {
+        let image = &self.levels[level];
+
+        // Pixel centers lie at half-integer coordinates
+        let x = st.0 * image.width as f64 - 0.5;
+        let y = st.1 * image.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (dx, dy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        self.texel(plane, level, x0, y0) * ((1.0 - dx) * (1.0 - dy)) +
+        self.texel(plane, level, x0 + 1, y0) * (dx * (1.0 - dy)) +
+        self.texel(plane, level, x0, y0 + 1) * ((1.0 - dx) * dy) +
+        self.texel(plane, level, x0 + 1, y0 + 1) * (dx * dy)
+    }
+
+    // Converts a filter width in texture space to a (fractional) level
+    fn level_of_detail(&self, width: f64) -> f64 {
+        let resolution = self.width().max(self.height()) as f64;
+        (width.max(1.0e-8) * resolution).log2().clamp(0.0, (self.levels.len() - 1) as f64)
+    }
+
+    fn trilinear(&self, plane: Plane, st: (f64, f64), width: f64) -> Vector {
+        let lod = self.level_of_detail(width);
+        let level = lod.floor() as usize;
+        if level + 1 >= self.levels.len() {
+            return self.bilinear(plane, level, st);
+        }
+        let delta = lod - level as f64;
+        self.bilinear(plane, level, st) * (1.0 - delta) + self.bilinear(plane, level + 1, st) * delta
+    }
+
+    // Filters the texels of a single level that fall within the ellipse spanned by
+    // the two axes, using a truncated Gaussian
+    fn ewa_level(&self, plane: Plane, level: usize, st: (f64, f64), axis0: (f64, f64), axis1: (f64, f64)) -> Vector {
+        let image = &self.levels[level];
+        let (w, h) = (image.width as f64, image.height as f64);
+        let (s, t) = (st.0 * w - 0.5, st.1 * h - 0.5);
+        let axis0 = (axis0.0 * w, axis0.1 * h);
+        let axis1 = (axis1.0 * w, axis1.1 * h);
+
+        // Compute the coefficients of the implicit ellipse A s^2 + B s t + C t^2 = F,
+        // enlarged slightly so that it always covers at least one texel
+        let a = axis0.1 * axis0.1 + axis1.1 * axis1.1 + 1.0;
+        let b = -2.0 * (axis0.0 * axis0.1 + axis1.0 * axis1.1);
+        let c = axis0.0 * axis0.0 + axis1.0 * axis1.0 + 1.0;
+        let inv_f = 1.0 / (a * c - b * b * 0.25);
+        let (a, b, c) = (a * inv_f, b * inv_f, c * inv_f);
+
+        // Compute the ellipse's bounding box in texel space
+        let determinant = -b * b + 4.0 * a * c;
+        let inv_determinant = 1.0 / determinant;
+        let u_extent = 2.0 * inv_determinant * (determinant * c).sqrt();
+        let v_extent = 2.0 * inv_determinant * (determinant * a).sqrt();
+        let (s0, s1) = ((s - u_extent).ceil() as i64, (s + u_extent).floor() as i64);
+        let (t0, t1) = ((t - v_extent).ceil() as i64, (t + v_extent).floor() as i64);
+
+        const ALPHA: f64 = 2.0;
+        let mut sum = Vector::zero();
+        let mut total_weight = 0.0;
+        for y in t0..(t1 + 1) {
+            let dt = y as f64 - t;
+            for x in s0..(s1 + 1) {
+                let ds = x as f64 - s;
+                let r2 = a * ds * ds + b * ds * dt + c * dt * dt;
+                if r2 < 1.0 {
+                    let weight = (-ALPHA * r2).exp() - (-ALPHA).exp();
+                    sum += self.texel(plane, level, x, y) * weight;
+                    total_weight += weight;
+                }
+            }
+        }
+        if total_weight > 0.0 {
+            sum / total_weight
+        } else {
+            self.bilinear(plane, level, st)
+        }
+    }
+
+    fn ewa(&self, plane: Plane, st: (f64, f64), dst0: (f64, f64), dst1: (f64, f64)) -> Vector {
+        let length = |d: (f64, f64)| (d.0 * d.0 + d.1 * d.1).sqrt();
+
+        // Make the first axis the major one
+        let (major, mut minor) = if length(dst0) < length(dst1) {
+            (dst1, dst0)
+        } else {
+            (dst0, dst1)
+        };
+        let major_length = length(major);
+        let mut minor_length = length(minor);
+
+        // Clamp the eccentricity of the ellipse by growing the minor axis, which
+        // blurs the result rather than tracing arbitrarily many texels
+        if minor_length * MAX_ANISOTROPY < major_length && minor_length > 0.0 {
+            let scale = major_length / (minor_length * MAX_ANISOTROPY);
+            minor = (minor.0 * scale, minor.1 * scale);
+            minor_length *= scale;
+        }
+        if minor_length == 0.0 {
+            return self.bilinear(plane, 0, st);
+        }
+
+        // Choose the levels based on the minor axis, so that the ellipse covers a
+        // bounded number of texels
+        let lod = self.level_of_detail(minor_length);
+        let level = lod.floor() as usize;
+        if level + 1 >= self.levels.len() {
+            // The footprint covers the whole texture: its axes are no longer
+            // bounded relative to the coarsest level, so filtering the ellipse
+            // could visit any number of (wrapped) texels
+            return self.bilinear(plane, level, st);
+        }
+        let delta = lod - level as f64;
+        self.ewa_level(plane, level, st, major, minor) * (1.0 - delta) +
+        self.ewa_level(plane, level + 1, st, major, minor) * delta
+    }
+
+    // Filters the texture over the footprint described by the derivatives of the
+    // texture coordinates with respect to the image plane
+    pub fn lookup(&self, st: (f64, f64), dstdx: (f64, f64), dstdy: (f64, f64)) -> Vector {
+        self.filter_plane(Plane::Color, st, dstdx, dstdy)
+    }
+
+    // Filters the opacity of the texture in the same way
+    pub fn lookup_alpha(&self, st: (f64, f64), dstdx: (f64, f64), dstdy: (f64, f64)) -> f64 {
+        self.filter_plane(Plane::Alpha, st, dstdx, dstdy).x
+    }
+
+    fn filter_plane(&self, plane: Plane, st: (f64, f64), dstdx: (f64, f64), dstdy: (f64, f64)) -> Vector {
+        // Flip the t-axis so that it runs down the image
+        let st = (st.0, 1.0 - st.1);
+        let dstdx = (dstdx.0, -dstdx.1);
+        let dstdy = (dstdy.0, -dstdy.1);
+        match self.filter {
+            FilterMode::Bilinear => self.bilinear(plane, 0, st),
+            FilterMode::Trilinear => {
+                let width = 2.0 * dstdx.0.abs().max(dstdx.1.abs()).max(dstdy.0.abs()).max(dstdy.1.abs());
+                self.trilinear(plane, st, width)
+            }
+            FilterMode::Ewa => self.ewa(plane, st, dstdx, dstdy),
+        }
+    }
+}
+
+#[test]
+fn test_mipmap() {
+    // A 4 x 3 checkerboard of black and white texels
+    let mut image = Image::new(4, 3);
+    for y in 0..3 {
+        for x in 0..4 {
+            if (x + y) % 2 == 0 {
+                image.set(x, y, &Vector::one());
+            }
+        }
+    }
+    let mipmap = MipMap::new(image, WrapMode::Repeat, FilterMode::Trilinear);
+    let sizes: Vec<_> = mipmap.levels.iter().map(|level| (level.width, level.height)).collect();
+    assert_eq!(sizes, vec![(4, 3), (2, 2), (1, 1)]);
+
+    // A tiny footprint returns the texel itself, while a large one averages
+    let texel = mipmap.lookup((0.125, 5.0 / 6.0), (1.0e-6, 0.0), (0.0, 1.0e-6));
+    assert!((texel - Vector::one()).length() < 1.0e-6);
+    let average = mipmap.lookup((0.5, 0.5), (1.0, 0.0), (0.0, 1.0));
+    assert!((average - Vector::one() * 0.5).length() < 0.1);
+
+    // Huge footprints fall back to the coarsest level rather than scanning every
+    // texel that the ellipse covers
+    let mut image = Image::new(2, 2);
+    image.set(0, 0, &Vector::one());
+    let mipmap = MipMap::new(image, WrapMode::Repeat, FilterMode::Ewa);
+    let coarsest = mipmap.lookup((0.3, 0.7), (1.0e9, 0.0), (0.0, 1.0e8));
+    assert!((coarsest - Vector::one() * 0.25).length() < 1.0e-12);
+
+    // Opacity is averaged along with the color
+    let mut image = Image::new(2, 2);
+    image.alpha = Some(vec![1.0, 0.0, 0.5, 0.5]);
+    let mipmap = MipMap::new(image, WrapMode::Clamp, FilterMode::Trilinear);
+    assert_eq!(mipmap.levels[1].alpha, Some(vec![0.5]));
+}
@@ -0,0 +1,123 @@
+// Blends between two materials, such as a dirt mask over paint or a glaze whose
+// reflections strengthen at grazing angles
+use vector::Vector;
+use shape::DifferentialGeometry;
+use material::{Material, BsdfSample, Lobes};
+use texture::{Texture, ConstantTexture};
+use fresnel;
+
+use std::sync::Arc;
+
+extern crate rand;
+use rand::Rng;
+
+// How much of the second material is used
+pub enum MixWeight {
+    // Values outside of the range 0..1 are clamped
+    Amount(Arc<dyn Texture<f64>>),
+    // The Fresnel reflectance of a dielectric with the given index of refraction,
+    // for the angle of the viewer
+    Fresnel(f64),
+}
+
+// The weighted sum of two materials: sampling stochastically selects one of them
+// based on the weight, but the value and density of the sample account for both
+pub struct MixMaterial {
+    pub first: Arc<dyn Material>,
+    pub second: Arc<dyn Material>,
+    pub weight: MixWeight,
+}
+
+impl MixMaterial {
+    fn weight(&self, wo: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        match self.weight {
+            MixWeight::Amount(ref amount) => amount.evaluate(intersection).clamp(0.0, 1.0),
+            MixWeight::Fresnel(ior) => fresnel::dielectric(wo.dot(&intersection.shading_normal).abs(), ior),
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        self.first.lobes(intersection) | self.second.lobes(intersection)
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let weight = self.weight(wo, intersection);
+        let (chosen, probability) = if rand::thread_rng().next_f64() < weight {
+            (&self.second, weight)
+        } else {
+            (&self.first, 1.0 - weight)
+        };
+        let sample = chosen.sample(wo, intersection)?;
+
+        // The other material can't scatter light into a specular direction
+        if sample.lobe.contains(Lobes::SPECULAR) {
+            return Some(BsdfSample::new(&sample.wi,
+                                        &(sample.f * probability),
+                                        sample.pdf * probability,
+                                        sample.lobe));
+        }
+        let pdf = self.pdf(wo, &sample.wi, intersection);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample::new(&sample.wi, &self.evaluate(wo, &sample.wi, intersection), pdf, sample.lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let weight = self.weight(wo, intersection);
+        self.first.evaluate(wo, wi, intersection) * (1.0 - weight) + self.second.evaluate(wo, wi, intersection) * weight
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let weight = self.weight(wo, intersection);
+        self.first.pdf(wo, wi, intersection) * (1.0 - weight) + self.second.pdf(wo, wi, intersection) * weight
+    }
+}
+
+impl MixMaterial {
+    pub fn new(first: Arc<dyn Material>, second: Arc<dyn Material>, amount: f64) -> MixMaterial {
+        MixMaterial::textured(first, second, Arc::new(ConstantTexture::new(amount)))
+    }
+
+    pub fn textured(first: Arc<dyn Material>,
+                    second: Arc<dyn Material>,
+                    amount: Arc<dyn Texture<f64>>)
+                    -> MixMaterial {
+        MixMaterial {
+            first,
+            second,
+            weight: MixWeight::Amount(amount),
+        }
+    }
+
+    // Uses the second material where a dielectric with the given index of
+    // refraction would reflect light, e.g. a mirror over a diffuse base
+    pub fn fresnel(first: Arc<dyn Material>, second: Arc<dyn Material>, ior: f64) -> MixMaterial {
+        MixMaterial {
+            first,
+            second,
+            weight: MixWeight::Fresnel(ior),
+        }
+    }
+}
+
+#[test]
+fn test_mix_material() {
+    use material::{Lambertian, Conductor, sphere_intersection, assert_sample_consistent};
+
+    let (dg, wo) = sphere_intersection(0.6, 0.1);
+
+    let diffuse = Arc::new(Lambertian::new(&Vector::new(0.2, 0.5, 0.8)));
+    let materials = [MixMaterial::new(diffuse.clone(), Arc::new(Conductor::gold(0.3)), 0.4),
+                     MixMaterial::fresnel(diffuse.clone(), Arc::new(Conductor::silver(0.0)), 1.5)];
+    for material in &materials {
+        assert_sample_consistent(material, &wo, &dg);
+    }
+
+    // Mixing a material with itself has no effect
+    let same = MixMaterial::new(diffuse.clone(), diffuse.clone(), 0.7);
+    let wi = Vector::new(0.3, 0.5, 1.0).normalize();
+    assert!((same.evaluate(&wo, &wi, &dg) - diffuse.evaluate(&wo, &wi, &dg)).length() < 1.0e-12);
+}
@@ -0,0 +1,210 @@
+// Solid noise functions, which are used to build procedural textures: all of them
+// are deterministic and tile with a period of 256 units
+use vector::Vector;
+
+// Ken Perlin's reference permutation of the integers 0..255
+const PERMUTATION: [u8; 256] =
+    [151, 160, 137, 91, 90, 15, 131, 13, 201, 95, 96, 53, 194, 233, 7, 225, 140, 36, 103, 30,
+     69, 142, 8, 99, 37, 240, 21, 10, 23, 190, 6, 148, 247, 120, 234, 75, 0, 26, 197, 62, 94,
+     252, 219, 203, 117, 35, 11, 32, 57, 177, 33, 88, 237, 149, 56, 87, 174, 20, 125, 136, 171,
+     168, 68, 175, 74, 165, 71, 134, 139, 48, 27, 166, 77, 146, 158, 231, 83, 111, 229, 122,
+     60, 211, 133, 230, 220, 105, 92, 41, 55, 46, 245, 40, 244, 102, 143, 54, 65, 25, 63, 161,
+     1, 216, 80, 73, 209, 76, 132, 187, 208, 89, 18, 169, 200, 196, 135, 130, 116, 188, 159,
+     86, 164, 100, 109, 198, 173, 186, 3, 64, 52, 217, 226, 250, 124, 123, 5, 202, 38, 147,
+     118, 126, 255, 82, 85, 212, 207, 206, 59, 227, 47, 16, 58, 17, 182, 189, 28, 42, 223, 183,
+     170, 213, 119, 248, 152, 2, 44, 154, 163, 70, 221, 153, 101, 155, 167, 43, 172, 9, 129,
+     22, 39, 253, 19, 98, 108, 110, 79, 113, 224, 232, 178, 185, 112, 104, 218, 246, 97, 228,
+     251, 34, 242, 193, 238, 210, 144, 12, 191, 179, 162, 241, 81, 51, 145, 235, 249, 14, 239,
+     107, 49, 192, 214, 31, 181, 199, 106, 157, 184, 84, 204, 176, 115, 121, 50, 45, 127, 4,
+     150, 254, 138, 236, 205, 93, 222, 114, 67, 29, 24, 72, 243, 141, 128, 195, 78, 66, 215,
+     61, 156, 180];
+
+// Hashes a lattice point to a value in the range 0..255
+fn hash(x: i64, y: i64, z: i64) -> usize {
+    let p = |i: i64| PERMUTATION[(i & 255) as usize] as i64;
+    p(p(p(x) + y) + z) as usize
+}
+
+// The quintic interpolant used by improved Perlin noise, which has continuous
+// first and second derivatives
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+// The dot product of an offset with one of twelve gradients, which point towards
+// the midpoints of the edges of a cube
+fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 {
+        y
+    } else if h == 12 || h == 14 {
+        x
+    } else {
+        z
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Improved Perlin noise (Perlin 2002), in the range -1..1: this is zero at every
+// lattice point
+pub fn perlin(p: &Vector) -> f64 {
+    let (xi, yi, zi) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - xi, p.y - yi, p.z - zi);
+    let (xi, yi, zi) = (xi as i64, yi as i64, zi as i64);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    // Blend the contributions of the eight corners of the surrounding cell
+    let corner = |i: i64, j: i64, k: i64| {
+        gradient(hash(xi + i, yi + j, zi + k),
+                 x - i as f64,
+                 y - j as f64,
+                 z - k as f64)
+    };
+    lerp(w,
+         lerp(v,
+              lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+              lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+         lerp(v,
+              lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+              lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+}
+
+// Simplex noise (Perlin 2001), in the range -1..1: this interpolates between the
+// four corners of a tetrahedron rather than the eight corners of a cube, which is
+// cheaper and has fewer axis-aligned artifacts
+pub fn simplex(p: &Vector) -> f64 {
+    const F3: f64 = 1.0 / 3.0;
+    const G3: f64 = 1.0 / 6.0;
+
+    // Skew the input space to determine which simplex cell we're in
+    let s = (p.x + p.y + p.z) * F3;
+    let (i, j, k) = ((p.x + s).floor(), (p.y + s).floor(), (p.z + s).floor());
+    let t = (i + j + k) * G3;
+    let first = Vector::new(p.x - (i - t), p.y - (j - t), p.z - (k - t));
+
+    // Find the offsets of the second and third corners, based on the order of the
+    // coordinates within the cell
+    let (second, third) = if first.x >= first.y {
+        if first.y >= first.z {
+            ((1, 0, 0), (1, 1, 0))
+        } else if first.x >= first.z {
+            ((1, 0, 0), (1, 0, 1))
+        } else {
+            ((0, 0, 1), (1, 0, 1))
+        }
+    } else if first.y < first.z {
+        ((0, 0, 1), (0, 1, 1))
+    } else if first.x < first.z {
+        ((0, 1, 0), (0, 1, 1))
+    } else {
+        ((0, 1, 0), (1, 1, 0))
+    };
+
+    let (i, j, k) = (i as i64, j as i64, k as i64);
+    let corners = [((0, 0, 0), 0.0), (second, G3), (third, 2.0 * G3), ((1, 1, 1), 3.0 * G3)];
+    let mut sum = 0.0;
+    for &((di, dj, dk), offset) in &corners {
+        let x = first.x - di as f64 + offset;
+        let y = first.y - dj as f64 + offset;
+        let z = first.z - dk as f64 + offset;
+        let falloff = 0.6 - x * x - y * y - z * z;
+        if falloff > 0.0 {
+            sum += falloff.powi(4) * gradient(hash(i + di, j + dj, k + dk), x, y, z);
+        }
+    }
+    32.0 * sum
+}
+
+// Returns a pseudo-random point within the given cell
+fn feature_point(x: i64, y: i64, z: i64) -> Vector {
+    let h = hash(x, y, z);
+    let offset = |salt: usize| {
+        let value = PERMUTATION[(h + salt) & 255] as usize * 256 +
+                    PERMUTATION[(h * 3 + salt * 7) & 255] as usize;
+        (value as f64 + 0.5) / 65536.0
+    };
+    Vector::new(x as f64 + offset(0), y as f64 + offset(85), z as f64 + offset(170))
+}
+
+// Worley (cellular) noise: returns the distances to the closest and second closest
+// of a set of feature points, one of which lies in each unit cell
+pub fn worley(p: &Vector) -> (f64, f64) {
+    let (xi, yi, zi) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let (mut f1, mut f2) = (f64::MAX, f64::MAX);
+    for i in -1..2 {
+        for j in -1..2 {
+            for k in -1..2 {
+                let distance = (feature_point(xi + i, yi + j, zi + k) - *p).length();
+                if distance < f1 {
+                    f2 = f1;
+                    f1 = distance;
+                } else if distance < f2 {
+                    f2 = distance;
+                }
+            }
+        }
+    }
+    (f1, f2)
+}
+
+// The noise functions that can be combined into fractal noise
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum NoiseBasis {
+    Perlin,
+    Simplex,
+    // Uses the distance to the closest feature point, remapped to roughly -1..1
+    Worley,
+}
+
+impl NoiseBasis {
+    pub fn evaluate(&self, p: &Vector) -> f64 {
+        match *self {
+            NoiseBasis::Perlin => perlin(p),
+            NoiseBasis::Simplex => simplex(p),
+            NoiseBasis::Worley => 2.0 * worley(p).0 - 1.0,
+        }
+    }
+}
+
+// Fractional Brownian motion: sums octaves of noise, where each octave's frequency
+// is `lacunarity` times the previous one and its amplitude `gain` times
+pub fn fbm(basis: NoiseBasis, p: &Vector, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+    let mut sum = 0.0;
+    let (mut frequency, mut amplitude) = (1.0, 1.0);
+    for _ in 0..octaves {
+        sum += basis.evaluate(&(*p * frequency)) * amplitude;
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    sum
+}
+
+// Like fractional Brownian motion, but sums the absolute value of each octave,
+// which produces creases where the noise crosses zero
+pub fn turbulence(basis: NoiseBasis, p: &Vector, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+    let mut sum = 0.0;
+    let (mut frequency, mut amplitude) = (1.0, 1.0);
+    for _ in 0..octaves {
+        sum += basis.evaluate(&(*p * frequency)).abs() * amplitude;
+        frequency *= lacunarity;
+        amplitude *= gain;
+    }
+    sum
+}
+
+#[test]
+fn test_noise() {
+    assert_eq!(perlin(&Vector::new(3.0, -2.0, 7.0)), 0.0);
+    for i in 0..1000 {
+        let p = Vector::new(i as f64 * 0.37, i as f64 * -0.23, i as f64 * 0.11);
+        assert!(perlin(&p).abs() <= 1.0);
+        assert!(simplex(&p).abs() <= 1.0);
+        let (f1, f2) = worley(&p);
+        assert!(f1 <= f2 && f1 < 3.0f64.sqrt());
+    }
+}
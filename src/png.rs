@@ -0,0 +1,264 @@
+// A decoder for PNG images, supporting all standard color types and bit depths
+// as well as Adam7 interlacing: ancillary chunks (including gamma and color
+// profiles) are ignored
+use vector::Vector;
+use image::{Image, invalid_data};
+use inflate::zlib_decompress;
+
+use std::io;
+
+pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+// The starting offsets and spacing of each of the seven Adam7 passes
+const ADAM7: [(usize, usize, usize, usize); 7] = [(0, 0, 8, 8),
+                                                  (4, 0, 8, 8),
+                                                  (0, 4, 4, 8),
+                                                  (2, 0, 4, 4),
+                                                  (0, 2, 2, 4),
+                                                  (1, 0, 2, 2),
+                                                  (0, 1, 1, 2)];
+
+struct Header {
+    width: usize,
+    height: usize,
+    bit_depth: u8,
+    color_type: u8,
+    interlaced: bool,
+}
+
+impl Header {
+    fn channels(&self) -> usize {
+        match self.color_type {
+            0 | 3 => 1,
+            2 => 3,
+            4 => 2,
+            _ => 4,
+        }
+    }
+
+    // The number of bytes used by each row of a (sub-)image of the given width
+    fn row_bytes(&self, width: usize) -> usize {
+        (width * self.channels() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (u32::from(bytes[0]) << 24) | (u32::from(bytes[1]) << 16) | (u32::from(bytes[2]) << 8) |
+    u32::from(bytes[3])
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = i16::from(a) + i16::from(b) - i16::from(c);
+    let (pa, pb, pc) = ((p - i16::from(a)).abs(), (p - i16::from(b)).abs(), (p - i16::from(c)).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+// Reverses the per-scanline filters of a (sub-)image, returning the raw data
+// without the leading filter type bytes
+fn unfilter(data: &[u8], row_bytes: usize, rows: usize, bytes_per_pixel: usize) -> io::Result<Vec<u8>> {
+    let mut output = vec![0u8; row_bytes * rows];
+    for y in 0..rows {
+        let start = y * (row_bytes + 1);
+        let filter = data[start];
+        let source = &data[start + 1..start + 1 + row_bytes];
+        let (previous_rows, current_rows) = output.split_at_mut(y * row_bytes);
+        let previous = if y > 0 {
+            &previous_rows[(y - 1) * row_bytes..]
+        } else {
+            &[][..]
+        };
+        let current = &mut current_rows[..row_bytes];
+
+        for x in 0..row_bytes {
+            let a = if x >= bytes_per_pixel { current[x - bytes_per_pixel] } else { 0 };
+            let b = if y > 0 { previous[x] } else { 0 };
+            let c = if y > 0 && x >= bytes_per_pixel { previous[x - bytes_per_pixel] } else { 0 };
+            current[x] = match filter {
+                0 => source[x],
+                1 => source[x].wrapping_add(a),
+                2 => source[x].wrapping_add(b),
+                3 => source[x].wrapping_add(((u16::from(a) + u16::from(b)) / 2) as u8),
+                4 => source[x].wrapping_add(paeth(a, b, c)),
+                _ => return Err(invalid_data("invalid png filter type")),
+            };
+        }
+    }
+    Ok(output)
+}
+
+// Extracts the (unnormalized) samples of a row of unfiltered data
+fn samples(row: &[u8], count: usize, bit_depth: u8) -> Vec<u16> {
+    (0..count)
+        .map(|i| match bit_depth {
+            16 => (u16::from(row[2 * i]) << 8) | u16::from(row[2 * i + 1]),
+            8 => u16::from(row[i]),
+            _ => {
+                // Sub-byte samples are packed starting with the most significant bits
+                let bit = i * bit_depth as usize;
+                let shift = 8 - bit_depth as usize - bit % 8;
+                u16::from((row[bit / 8] >> shift) & ((1u16 << bit_depth) - 1) as u8)
+            }
+        })
+        .collect()
+}
+
+pub fn decode(bytes: &[u8]) -> io::Result<Image> {
+    if bytes.len() < SIGNATURE.len() || bytes[..SIGNATURE.len()] != SIGNATURE {
+        return Err(invalid_data("missing png signature"));
+    }
+
+    // Walk through the chunks, collecting the header, palette and image data
+    let mut header = None;
+    let mut palette = Vec::new();
+    let mut compressed = Vec::new();
+    let mut cursor = SIGNATURE.len();
+    while cursor + 8 <= bytes.len() {
+        let length = read_u32(&bytes[cursor..]) as usize;
+        let kind = &bytes[cursor + 4..cursor + 8];
+        let data = bytes.get(cursor + 8..cursor + 8 + length)
+            .ok_or_else(|| invalid_data("truncated png chunk"))?;
+        match kind {
+            b"IHDR" => {
+                if length < 13 {
+                    return Err(invalid_data("truncated png header"));
+                }
+                header = Some(Header {
+                    width: read_u32(data) as usize,
+                    height: read_u32(&data[4..]) as usize,
+                    bit_depth: data[8],
+                    color_type: data[9],
+                    interlaced: data[12] == 1,
+                });
+            }
+            b"PLTE" => palette = data.to_vec(),
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+
+        // Skip over the chunk's data and CRC
+        cursor += 12 + length;
+    }
+
+    let header = header.ok_or_else(|| invalid_data("missing png header"))?;
+    let valid_depths: &[u8] = match header.color_type {
+        0 => &[1, 2, 4, 8, 16],
+        3 => &[1, 2, 4, 8],
+        2 | 4 | 6 => &[8, 16],
+        _ => return Err(invalid_data("invalid png color type")),
+    };
+    if !valid_depths.contains(&header.bit_depth) {
+        return Err(invalid_data("invalid png bit depth"));
+    }
+    if header.color_type == 3 && palette.is_empty() {
+        return Err(invalid_data("missing png palette"));
+    }
+
+    let data = zlib_decompress(&compressed)?;
+    let channels = header.channels();
+    let bytes_per_pixel = ((channels * header.bit_depth as usize) / 8).max(1);
+    let max_value = f64::from((1u32 << header.bit_depth) - 1);
+    let has_alpha = header.color_type == 4 || header.color_type == 6;
+
+    // Non-interlaced images are treated as a single pass that covers every pixel
+    let passes: &[(usize, usize, usize, usize)] = if header.interlaced {
+        &ADAM7
+    } else {
+        &[(0, 0, 1, 1)]
+    };
+
+    // Work out the size of each pass, and check that the image data holds exactly
+    // that many bytes before allocating the image, since the header is untrusted
+    let mut layout = Vec::with_capacity(passes.len());
+    let mut expected_size = 0usize;
+    for &(x0, y0, dx, dy) in passes {
+        let pass_width = (header.width + dx - 1 - x0) / dx;
+        let pass_height = (header.height + dy - 1 - y0) / dy;
+        if pass_width == 0 || pass_height == 0 {
+            continue;
+        }
+        let row_bytes = header.row_bytes(pass_width);
+        let size = (row_bytes + 1)
+            .checked_mul(pass_height)
+            .ok_or_else(|| invalid_data("invalid png dimensions"))?;
+        expected_size = expected_size.checked_add(size)
+            .ok_or_else(|| invalid_data("invalid png dimensions"))?;
+        layout.push((x0, y0, dx, dy, pass_width, pass_height, row_bytes, size));
+    }
+    if data.len() != expected_size {
+        return Err(invalid_data("png image data doesn't match its dimensions"));
+    }
+
+    let mut image = Image::new(header.width, header.height);
+    let mut alpha = vec![1.0; header.width * header.height];
+    let mut offset = 0;
+    for &(x0, y0, dx, dy, pass_width, pass_height, row_bytes, size) in &layout {
+        let filtered = &data[offset..offset + size];
+        let unfiltered = unfilter(filtered, row_bytes, pass_height, bytes_per_pixel)?;
+        offset += size;
+
+        for row in 0..pass_height {
+            let values = samples(&unfiltered[row * row_bytes..],
+                                 pass_width * channels,
+                                 header.bit_depth);
+            for column in 0..pass_width {
+                let pixel = &values[column * channels..(column + 1) * channels];
+                let color = match header.color_type {
+                    0 | 4 => Vector::one() * (f64::from(pixel[0]) / max_value),
+                    3 => {
+                        let entry = pixel[0] as usize * 3;
+                        if entry + 2 >= palette.len() {
+                            return Err(invalid_data("png palette index out of range"));
+                        }
+                        Vector::new(f64::from(palette[entry]),
+                                    f64::from(palette[entry + 1]),
+                                    f64::from(palette[entry + 2])) / 255.0
+                    }
+                    _ => {
+                        Vector::new(f64::from(pixel[0]), f64::from(pixel[1]), f64::from(pixel[2])) /
+                        max_value
+                    }
+                };
+
+                let (x, y) = (x0 + column * dx, y0 + row * dy);
+                image.set(x, y, &color);
+                if has_alpha {
+                    alpha[y * header.width + x] = f64::from(pixel[channels - 1]) / max_value;
+                }
+            }
+        }
+    }
+
+    if has_alpha {
+        image.alpha = Some(alpha);
+    }
+    Ok(image)
+}
+
+#[test]
+fn test_decode_png() {
+    // A 2 x 2 RGB image with red, green, blue and white pixels
+    let bytes = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49,
+                 0x48, 0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02,
+                 0x00, 0x00, 0x00, 0xfd, 0xd4, 0x9a, 0x73, 0x00, 0x00, 0x00, 0x12, 0x49, 0x44,
+                 0x41, 0x54, 0x78, 0x9c, 0x63, 0xf8, 0xcf, 0xc0, 0xc0, 0x00, 0xc2, 0x0c, 0xff,
+                 0x81, 0x00, 0x00, 0x1f, 0xee, 0x05, 0xfb, 0x0b, 0xd9, 0x68, 0x8b, 0x00, 0x00,
+                 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae, 0x42, 0x60, 0x82];
+    let image = decode(&bytes).unwrap();
+    assert_eq!((image.width, image.height), (2, 2));
+    assert_eq!(image.get(0, 0), Vector::new(1.0, 0.0, 0.0));
+    assert_eq!(image.get(1, 0), Vector::new(0.0, 1.0, 0.0));
+    assert_eq!(image.get(0, 1), Vector::new(0.0, 0.0, 1.0));
+    assert_eq!(image.get(1, 1), Vector::one());
+
+    // A header that claims far more pixels than the image data holds
+    let mut huge = bytes;
+    huge[16..24].copy_from_slice(&[0x7f, 0xff, 0xff, 0xff, 0x7f, 0xff, 0xff, 0xff]);
+    assert!(decode(&huge).is_err());
+}
@@ -2,27 +2,197 @@ use shape::Shape;
 use shape::DifferentialGeometry;
 use ray::Ray;
 use material::Material;
+use bump::NormalMapping;
+use texture::Texture;
+use vector::Vector;
 
 use std::sync::Arc;
 
+extern crate rand;
+use rand::Rng;
+
+// The most surfaces of a single shape that a ray can pass through because of its
+// alpha mask, which bounds the cost of intersecting highly cut-out shapes
+const MAX_ALPHA_SKIPS: usize = 16;
+
+// How the back of a surface, i.e. the side opposite its normal, is shaded
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Sidedness {
+    // The geometry is passed to the material as it is, which decides how to treat
+    // hits on the back
+    Outward,
+    // Both sides are shaded like the front, by turning the surface towards the
+    // ray, which suits open meshes and single planes
+    TwoSided,
+    // Hits on the back absorb all light
+    OneSided,
+}
+
 // Primitives are instances of renderable geometry
 pub struct Primitive {
-    pub shape: Arc<Shape>,
-    pub material: Arc<Material>,
+    pub shape: Arc<dyn Shape>,
+    pub material: Arc<dyn Material>,
+    // Perturbs the shading normal at points of intersection
+    pub normal_mapping: Option<Arc<dyn NormalMapping>>,
+    // The opacity of the surface, in the range 0..1: hits where it is below
+    // `alpha_threshold` are cut out, while fractional values above the threshold
+    // are treated as partial coverage by passing through the surface at random
+    pub alpha: Option<Arc<dyn Texture<f64>>>,
+    pub alpha_threshold: f64,
+    pub sidedness: Sidedness,
+    // Whether the shape's normals point inward rather than outward, e.g. for the
+    // walls of a room
+    pub flip_normals: bool,
+    // The radiance leaving the front of the surface, which makes the primitive
+    // an area light once it is added to a scene
+    pub emission: Option<Vector>,
 }
 
 impl Primitive {
-    pub fn new(s: Arc<Shape>, m: Arc<Material>) -> Primitive {
+    pub fn new(s: Arc<dyn Shape>, m: Arc<dyn Material>) -> Primitive {
         Primitive {
             shape: s,
             material: m,
+            normal_mapping: None,
+            alpha: None,
+            alpha_threshold: 0.0,
+            sidedness: Sidedness::Outward,
+            flip_normals: false,
+            emission: None,
+        }
+    }
+
+    pub fn with_normal_mapping(self, nm: Arc<dyn NormalMapping>) -> Primitive {
+        Primitive { normal_mapping: Some(nm), ..self }
+    }
+
+    pub fn with_alpha(self, alpha: Arc<dyn Texture<f64>>, threshold: f64) -> Primitive {
+        Primitive {
+            alpha: Some(alpha),
+            alpha_threshold: threshold,
+            ..self
+        }
+    }
+
+    pub fn with_emission(self, emission: &Vector) -> Primitive {
+        Primitive { emission: Some(*emission), ..self }
+    }
+
+    // The radiance leaving a point of intersection towards the ray that hit it:
+    // only the front of the surface emits light, unless it is two-sided
+    pub fn emitted(&self, dg: &DifferentialGeometry) -> Vector {
+        match self.emission {
+            Some(emission) if dg.front_face => emission,
+            _ => Vector::zero(),
+        }
+    }
+
+    // Whether the ray stops at a point of intersection or passes through it
+    fn is_opaque(&self, dg: &DifferentialGeometry) -> bool {
+        match self.alpha {
+            Some(ref alpha) => {
+                let alpha = alpha.evaluate(dg);
+                if alpha >= 1.0 {
+                    true
+                } else if alpha < self.alpha_threshold {
+                    false
+                } else {
+                    rand::thread_rng().next_f64() < alpha
+                }
+            }
+            None => true,
         }
     }
 
-    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry, Arc<Material>)> {
-        if let Some(dg) = self.shape.intersect(incident) {
-            return Some((dg, self.material.clone()));
-        };
+    pub fn intersect(&self, incident: &Ray) -> Option<DifferentialGeometry<'_>> {
+        // Shapes only report their closest point of intersection, so the ray is
+        // restarted from points that are skipped to find the ones behind them
+        let mut r = Ray::new(&incident.origin, &incident.direction, incident.t_min, incident.t_max);
+        let mut offset = 0.0;
+        for _ in 0..MAX_ALPHA_SKIPS {
+            let mut dg = self.shape.intersect(&r)?;
+            dg.t += offset;
+            if self.is_opaque(&dg) {
+                return Some(dg);
+            }
+            offset = dg.t;
+            r = Ray::new(&dg.position, &incident.direction, incident.t_min, incident.t_max - offset);
+        }
         None
     }
+
+    // Whether light arriving at a point of intersection is absorbed rather than
+    // scattered by the material
+    pub fn absorbs(&self, dg: &DifferentialGeometry) -> bool {
+        self.sidedness == Sidedness::OneSided && !dg.front_face
+    }
+
+    // Finishes the geometry at the closest point of intersection, which is more
+    // expensive than finding it
+    pub fn prepare_for_shading(&self, dg: &mut DifferentialGeometry, incident: &Ray) {
+        if self.flip_normals {
+            dg.flip();
+        }
+        if self.sidedness == Sidedness::TwoSided && !dg.front_face {
+            dg.flip();
+        }
+        dg.compute_differentials(incident);
+        if let Some(ref normal_mapping) = self.normal_mapping {
+            normal_mapping.apply(dg);
+        }
+    }
+}
+
+#[test]
+fn test_alpha() {
+    use vector::Vector;
+    use shape::Sphere;
+    use material::Lambertian;
+    use shape::Quad;
+    use image::Image;
+    use mipmap::{MipMap, FilterMode, WrapMode};
+    use texture::{ConstantTexture, GradientTexture, PlanarMapping, ImageTexture, ImageChannel, UvMapping};
+
+    let sphere = Arc::new(Sphere::new(&Vector::zero(), 1.0));
+    let material = Arc::new(Lambertian::new(&Vector::one()));
+    let r = Ray::new(&Vector::new(0.0, 0.0, 3.0), &Vector::new(0.0, 0.0, -1.0), 0.0, 10.0);
+    let constant = |alpha: f64, threshold: f64| {
+        Primitive::new(sphere.clone(), material.clone())
+            .with_alpha(Arc::new(ConstantTexture::new(alpha)), threshold)
+    };
+
+    // Cut out below the threshold, and partially covered above it, where rays
+    // that pass through the front of the sphere may still hit its back
+    assert!(constant(0.4, 0.5).intersect(&r).is_none());
+    assert!(constant(1.0, 0.5).intersect(&r).is_some());
+    let partial = constant(0.5, 0.0);
+    let hits = (0..1000).filter(|_| partial.intersect(&r).is_some()).count();
+    assert!(hits > 680 && hits < 820);
+
+    // Rays pass through the transparent front of the sphere to hit its back
+    let gradient = GradientTexture::new(Arc::new(ConstantTexture::new(0.0)),
+                                        Arc::new(ConstantTexture::new(1.0)),
+                                        Arc::new(PlanarMapping::new(&Vector::new(0.0, 0.0, -10.0),
+                                                                    &Vector::zero(),
+                                                                    (0.5, 0.0))));
+    let back = Primitive::new(sphere.clone(), material.clone()).with_alpha(Arc::new(gradient), 0.5);
+    let dg = back.intersect(&r).unwrap();
+    assert!((dg.t - 4.0).abs() < 1.0e-9 && (dg.position.z + 1.0).abs() < 1.0e-9);
+
+    // A quad masked by the alpha channel of a 2 x 1 RGBA image, whose left pixel
+    // is transparent and whose right pixel is opaque
+    let png = [0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+               0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x01, 0x08, 0x06, 0x00, 0x00,
+               0x00, 0xf4, 0x22, 0x7f, 0x8a, 0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54, 0x78,
+               0xda, 0x63, 0xf8, 0xff, 0xff, 0x3f, 0x03, 0x10, 0xff, 0x07, 0x00, 0x1e, 0xea, 0x06,
+               0xfa, 0xd1, 0x2a, 0xca, 0xe5, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+               0x42, 0x60, 0x82];
+    let mipmap = MipMap::new(Image::decode(&png).unwrap(), WrapMode::Clamp, FilterMode::Bilinear);
+    let mask = ImageTexture::new(Arc::new(mipmap), Arc::new(UvMapping::default()))
+        .with_channel(ImageChannel::Alpha);
+    let quad = Arc::new(Quad::new(&Vector::zero(), &Vector::new(1.0, 0.0, 0.0), &Vector::new(0.0, 1.0, 0.0)));
+    let cutout = Primitive::new(quad, material.clone()).with_alpha(Arc::new(mask), 0.5);
+    let towards_quad = |u: f64| Ray::new(&Vector::new(u, 0.5, 1.0), &Vector::new(0.0, 0.0, -1.0), 0.0, 10.0);
+    assert!(cutout.intersect(&towards_quad(0.25)).is_none());
+    assert!(cutout.intersect(&towards_quad(0.75)).is_some());
 }
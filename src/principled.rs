@@ -0,0 +1,437 @@
+// A "principled" uber-material, after the BRDF that Disney presented at SIGGRAPH
+// 2012 and extended with transmission in 2015: a handful of intuitive parameters,
+// most of which are in the range 0..1, blend between diffuse, metallic, glassy and
+// clear-coated appearances
+use vector::Vector;
+use shape::DifferentialGeometry;
+use frame::Frame;
+use material::{Material, BsdfSample, Lobes, orient, RoughDielectric};
+use texture::{Texture, ConstantTexture};
+use microfacet::{MicrofacetDistribution, MicrofacetModel, SMOOTH_ALPHA, roughness_to_alpha};
+use sampling;
+use image::invalid_data;
+
+use std::f64;
+use std::io;
+use std::sync::Arc;
+
+extern crate rand;
+use rand::Rng;
+
+// The parameters of a principled material that aren't textured, along with
+// constant values for the ones that are
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct PrincipledParameters {
+    pub base_color: Vector,
+    pub metallic: f64,
+    pub roughness: f64,
+    // Scales the reflectance of dielectrics at normal incidence, where the default
+    // of 0.5 corresponds to 4%
+    pub specular: f64,
+    // Tints dielectric reflections towards the hue of the base color
+    pub specular_tint: f64,
+    // A retro-reflective rim for cloth
+    pub sheen: f64,
+    pub sheen_tint: f64,
+    // A second, colorless specular layer
+    pub clearcoat: f64,
+    pub clearcoat_gloss: f64,
+    // The fraction of the dielectric base that is transmissive rather than diffuse
+    pub transmission: f64,
+    pub ior: f64,
+    // Flattens the diffuse response towards that of a subsurface scattering
+    // medium (Hanrahan and Krueger 1993), without transporting light under the
+    // surface
+    pub subsurface: f64,
+}
+
+impl Default for PrincipledParameters {
+    fn default() -> PrincipledParameters {
+        PrincipledParameters {
+            base_color: Vector::new(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            sheen_tint: 0.5,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            subsurface: 0.0,
+        }
+    }
+}
+
+impl PrincipledParameters {
+    // Parses a whitespace-separated list of `key=value` pairs, such as
+    // `base_color=0.9,0.1,0.1 metallic=0 roughness=0.3`, where colors are either a
+    // single value or three comma-separated values: parameters that aren't listed
+    // keep their defaults
+    pub fn parse(description: &str) -> io::Result<PrincipledParameters> {
+        let mut parameters = PrincipledParameters::default();
+        for pair in description.split_whitespace() {
+            let mut parts = pair.splitn(2, '=');
+            let key = parts.next().unwrap_or("");
+            let value = parts.next()
+                .ok_or_else(|| invalid_data(&format!("expected `key=value`, found `{}`", pair)))?;
+            let numbers = value.split(',')
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| invalid_data(&format!("invalid value for `{}`: `{}`", key, value)))?;
+            let scalar = || if numbers.len() == 1 {
+                Ok(numbers[0])
+            } else {
+                Err(invalid_data(&format!("expected a single value for `{}`", key)))
+            };
+            match key {
+                "base_color" => {
+                    parameters.base_color = match numbers.len() {
+                        1 => Vector::one() * numbers[0],
+                        3 => Vector::new(numbers[0], numbers[1], numbers[2]),
+                        _ => return Err(invalid_data("expected one or three values for `base_color`")),
+                    }
+                }
+                "metallic" => parameters.metallic = scalar()?,
+                "roughness" => parameters.roughness = scalar()?,
+                "specular" => parameters.specular = scalar()?,
+                "specular_tint" => parameters.specular_tint = scalar()?,
+                "sheen" => parameters.sheen = scalar()?,
+                "sheen_tint" => parameters.sheen_tint = scalar()?,
+                "clearcoat" => parameters.clearcoat = scalar()?,
+                "clearcoat_gloss" => parameters.clearcoat_gloss = scalar()?,
+                "transmission" => parameters.transmission = scalar()?,
+                "ior" => parameters.ior = scalar()?,
+                "subsurface" => parameters.subsurface = scalar()?,
+                _ => return Err(invalid_data(&format!("unknown parameter `{}`", key))),
+            }
+        }
+        let p = &parameters;
+        let unit = [("base_color", p.base_color.x),
+                    ("base_color", p.base_color.y),
+                    ("base_color", p.base_color.z),
+                    ("metallic", p.metallic),
+                    ("roughness", p.roughness),
+                    ("specular", p.specular),
+                    ("specular_tint", p.specular_tint),
+                    ("sheen", p.sheen),
+                    ("sheen_tint", p.sheen_tint),
+                    ("clearcoat", p.clearcoat),
+                    ("clearcoat_gloss", p.clearcoat_gloss),
+                    ("transmission", p.transmission),
+                    ("subsurface", p.subsurface)];
+        for &(key, value) in &unit {
+            if !(0.0..=1.0).contains(&value) {
+                return Err(invalid_data(&format!("`{}` must be in the range 0..1", key)));
+            }
+        }
+        if !(p.ior > 0.0 && p.ior.is_finite()) {
+            return Err(invalid_data("the index of refraction must be positive"));
+        }
+        Ok(parameters)
+    }
+
+    // Clamps the parameters that are weights to the range 0..1, which keeps the
+    // material from creating energy or sampling lobes with negative probabilities
+    fn clamped(&self) -> PrincipledParameters {
+        let unit = |x: f64| x.clamp(0.0, 1.0);
+        PrincipledParameters {
+            specular: unit(self.specular),
+            specular_tint: unit(self.specular_tint),
+            sheen: unit(self.sheen),
+            sheen_tint: unit(self.sheen_tint),
+            clearcoat: unit(self.clearcoat),
+            clearcoat_gloss: unit(self.clearcoat_gloss),
+            transmission: unit(self.transmission),
+            subsurface: unit(self.subsurface),
+            ..*self
+        }
+    }
+}
+
+pub struct Principled {
+    pub base_color: Arc<dyn Texture<Vector>>,
+    pub metallic: Arc<dyn Texture<f64>>,
+    pub roughness: Arc<dyn Texture<f64>>,
+    // The remaining parameters: its base color, metallic and roughness are unused
+    pub parameters: PrincipledParameters,
+    // The transmissive part of the material, which shares its roughness
+    glass: RoughDielectric,
+}
+
+// The weights of the material's lobes at a point on a surface
+struct Blend {
+    frame: Frame,
+    base_color: Vector,
+    // The hue of the base color, independent of its luminance
+    tint: Vector,
+    // The reflectance of the specular lobe at normal incidence
+    specular: Vector,
+    roughness: f64,
+    distribution: MicrofacetDistribution,
+    diffuse_weight: f64,
+    glass_weight: f64,
+    // The probabilities of sampling the diffuse, specular, glass and clearcoat
+    // lobes
+    probabilities: [f64; 4],
+}
+
+// The Fresnel weight of Schlick's approximation
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn mix(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t
+}
+
+// The generalized Trowbridge-Reitz distribution with an exponent of one (Burley
+// 2012), whose long tails give clear coats a haze around highlights
+fn gtr1(cos_theta_h: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (f64::consts::PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta_h * cos_theta_h))
+}
+
+// The widths of the clear coat's distribution of normals when it is fully glossy
+// and when it is not glossy at all
+const CLEARCOAT_ALPHA: (f64, f64) = (0.001, 0.1);
+
+impl Principled {
+    fn blend(&self, intersection: &DifferentialGeometry) -> Blend {
+        let p = &self.parameters;
+        let base_color = self.base_color.evaluate(intersection);
+        let metallic = self.metallic.evaluate(intersection).clamp(0.0, 1.0);
+        let roughness = self.roughness.evaluate(intersection).clamp(0.0, 1.0);
+        let luminance = base_color.luminance();
+        let tint = if luminance > 0.0 { base_color / luminance } else { Vector::one() };
+
+        let dielectric = (Vector::one() * (1.0 - p.specular_tint) + tint * p.specular_tint) * (0.08 * p.specular);
+        let specular = dielectric.lerp(&base_color, metallic);
+
+        // Very smooth surfaces are kept glossy, so that every lobe besides the
+        // glass can be evaluated
+        let alpha = roughness_to_alpha(roughness).max(SMOOTH_ALPHA);
+        let diffuse_weight = (1.0 - metallic) * (1.0 - p.transmission);
+        let glass_weight = (1.0 - metallic) * p.transmission;
+        let mut probabilities = [diffuse_weight, 1.0 - glass_weight, glass_weight, 0.25 * p.clearcoat];
+        let total: f64 = probabilities.iter().sum();
+        for probability in &mut probabilities {
+            *probability /= total;
+        }
+
+        Blend {
+            frame: intersection.shading_frame(),
+            base_color,
+            tint,
+            specular,
+            roughness,
+            distribution: MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, alpha, alpha),
+            diffuse_weight,
+            glass_weight,
+            probabilities,
+        }
+    }
+
+    fn clearcoat_alpha(&self) -> f64 {
+        mix(CLEARCOAT_ALPHA.1, CLEARCOAT_ALPHA.0, self.parameters.clearcoat_gloss.clamp(0.0, 1.0))
+    }
+
+    // Evaluates the reflective lobes for directions in the upper hemisphere of the
+    // shading frame
+    fn evaluate_reflection(&self, blend: &Blend, wo: &Vector, wi: &Vector) -> Vector {
+        let wm = *wo + *wi;
+        if wo.z <= 0.0 || wi.z <= 0.0 || wm.length() == 0.0 {
+            return Vector::zero();
+        }
+        let wm = wm.normalize();
+        let cos_theta_d = wi.dot(&wm);
+        let (fo, fi, fd) = (schlick_weight(wo.z), schlick_weight(wi.z), schlick_weight(cos_theta_d));
+        let p = &self.parameters;
+        let mut f = Vector::zero();
+
+        if blend.diffuse_weight > 0.0 {
+            // Diffuse, with a retro-reflective boost at grazing angles on rough
+            // surfaces, blended with the subsurface approximation
+            let fd90 = 0.5 + 2.0 * blend.roughness * cos_theta_d * cos_theta_d;
+            let diffuse = mix(1.0, fd90, fo) * mix(1.0, fd90, fi);
+            let fss90 = blend.roughness * cos_theta_d * cos_theta_d;
+            let fss = mix(1.0, fss90, fo) * mix(1.0, fss90, fi);
+            let subsurface = 1.25 * (fss * (1.0 / (wo.z + wi.z) - 0.5) + 0.5);
+            let sheen = (Vector::one() * (1.0 - p.sheen_tint) + blend.tint * p.sheen_tint) * (p.sheen * fd);
+            f += (blend.base_color * (mix(diffuse, subsurface, p.subsurface) / f64::consts::PI) + sheen) *
+                 blend.diffuse_weight;
+        }
+
+        // Specular reflection, with Schlick's approximation of the Fresnel
+        // reflectance
+        let distribution = &blend.distribution;
+        let fresnel = blend.specular.lerp(&Vector::one(), fd);
+        f += fresnel *
+             (distribution.d(&wm) * distribution.g(wo, wi) * (1.0 - blend.glass_weight) / (4.0 * wo.z * wi.z));
+
+        if p.clearcoat > 0.0 {
+            // The clear coat has a fixed index of refraction of 1.5 and fixed
+            // masking and shadowing
+            let masking = MicrofacetDistribution::new(MicrofacetModel::TrowbridgeReitz, 0.25, 0.25);
+            let clearcoat = gtr1(wm.z, self.clearcoat_alpha()) * mix(0.04, 1.0, fd) * masking.g(wo, wi) /
+                            (4.0 * wo.z * wi.z);
+            f += Vector::one() * (0.25 * p.clearcoat * clearcoat);
+        }
+        f
+    }
+
+    // The probability densities of sampling the reflective lobes, in the same
+    // order as the blend's probabilities
+    fn reflection_pdfs(&self, blend: &Blend, wo: &Vector, wi: &Vector) -> (f64, f64, f64) {
+        let wm = *wo + *wi;
+        if wo.z <= 0.0 || wi.z <= 0.0 || wm.length() == 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let wm = wm.normalize();
+        let diffuse = sampling::cosine_hemisphere_pdf(wi.z);
+        let specular = blend.distribution.pdf(wo, &wm) / (4.0 * wo.dot(&wm));
+        let clearcoat = gtr1(wm.z, self.clearcoat_alpha()) * wm.z / (4.0 * wo.dot(&wm));
+        (diffuse, specular, clearcoat)
+    }
+
+    // Transmitted light is tinted by the base color
+    fn glass_tint(blend: &Blend, wo: &Vector, wi: &Vector) -> Vector {
+        if blend.frame.to_local(wo).z * blend.frame.to_local(wi).z < 0.0 {
+            blend.base_color * blend.glass_weight
+        } else {
+            Vector::one() * blend.glass_weight
+        }
+    }
+}
+
+impl Material for Principled {
+    fn lobes(&self, intersection: &DifferentialGeometry) -> Lobes {
+        let lobes = Lobes::DIFFUSE | Lobes::GLOSSY | Lobes::REFLECTION;
+        if self.parameters.transmission > 0.0 && self.metallic.evaluate(intersection) < 1.0 {
+            lobes | self.glass.lobes(intersection)
+        } else {
+            lobes
+        }
+    }
+
+    fn sample(&self, wo: &Vector, intersection: &DifferentialGeometry) -> Option<BsdfSample> {
+        let blend = self.blend(intersection);
+        let local = blend.frame.to_local(wo);
+        let flipped = local.z < 0.0;
+        let (wo_local, _) = orient(&local, &local);
+
+        // Choose a lobe to sample
+        let mut rng = rand::thread_rng();
+        let u = rng.next_f64();
+        let [diffuse, specular, glass, _] = blend.probabilities;
+        let mut wi = if u < diffuse {
+            sampling::cosine_sample_hemisphere((rng.next_f64(), rng.next_f64()))
+        } else if u < diffuse + specular {
+            let wm = blend.distribution.sample_wm(&wo_local, (rng.next_f64(), rng.next_f64()));
+            (-wo_local).reflect(&wm)
+        } else if u < diffuse + specular + glass {
+            let sample = self.glass.sample(wo, intersection)?;
+            if sample.lobe.contains(Lobes::SPECULAR) {
+                // The glass is perfectly smooth, so its sample can't be combined
+                // with the other lobes
+                let f = sample.f * Principled::glass_tint(&blend, wo, &sample.wi);
+                return Some(BsdfSample::new(&sample.wi, &f, sample.pdf * glass, sample.lobe));
+            }
+            let wi = blend.frame.to_local(&sample.wi);
+            if flipped { Vector::new(wi.x, wi.y, -wi.z) } else { wi }
+        } else {
+            // Sample the clear coat's distribution of normals
+            let alpha2 = self.clearcoat_alpha().powi(2);
+            let cos_theta = ((1.0 - alpha2.powf(1.0 - rng.next_f64())) / (1.0 - alpha2)).max(0.0).sqrt();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * f64::consts::PI * rng.next_f64();
+            let wm = Vector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            (-wo_local).reflect(&wm)
+        };
+        if wi.z == 0.0 {
+            return None;
+        }
+
+        // Weight the sample by the combined density of every way of generating it
+        if flipped {
+            wi.z = -wi.z;
+        }
+        let wi = blend.frame.to_world(&wi);
+        let pdf = self.pdf(wo, &wi, intersection);
+        if pdf <= 0.0 {
+            return None;
+        }
+        let lobe = if local.z * blend.frame.to_local(&wi).z < 0.0 {
+            Lobes::GLOSSY | Lobes::TRANSMISSION
+        } else if u < diffuse {
+            Lobes::DIFFUSE | Lobes::REFLECTION
+        } else {
+            Lobes::GLOSSY | Lobes::REFLECTION
+        };
+        Some(BsdfSample::new(&wi, &self.evaluate(wo, &wi, intersection), pdf, lobe))
+    }
+
+    fn evaluate(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> Vector {
+        let blend = self.blend(intersection);
+        let (wo_local, wi_local) = orient(&blend.frame.to_local(wo), &blend.frame.to_local(wi));
+        let mut f = self.evaluate_reflection(&blend, &wo_local, &wi_local);
+        if blend.glass_weight > 0.0 {
+            f += self.glass.evaluate(wo, wi, intersection) * Principled::glass_tint(&blend, wo, wi);
+        }
+        f
+    }
+
+    fn pdf(&self, wo: &Vector, wi: &Vector, intersection: &DifferentialGeometry) -> f64 {
+        let blend = self.blend(intersection);
+        let (wo_local, wi_local) = orient(&blend.frame.to_local(wo), &blend.frame.to_local(wi));
+        let (diffuse, specular, clearcoat) = self.reflection_pdfs(&blend, &wo_local, &wi_local);
+        let glass = if blend.glass_weight > 0.0 { self.glass.pdf(wo, wi, intersection) } else { 0.0 };
+        let p = &blend.probabilities;
+        p[0] * diffuse + p[1] * specular + p[2] * glass + p[3] * clearcoat
+    }
+}
+
+impl Principled {
+    pub fn new(parameters: &PrincipledParameters) -> Principled {
+        Principled::textured(Arc::new(ConstantTexture::new(parameters.base_color)),
+                             Arc::new(ConstantTexture::new(parameters.metallic)),
+                             Arc::new(ConstantTexture::new(parameters.roughness)),
+                             parameters)
+    }
+
+    pub fn textured(base_color: Arc<dyn Texture<Vector>>,
+                    metallic: Arc<dyn Texture<f64>>,
+                    roughness: Arc<dyn Texture<f64>>,
+                    parameters: &PrincipledParameters)
+                    -> Principled {
+        Principled {
+            base_color,
+            metallic,
+            roughness: roughness.clone(),
+            parameters: parameters.clamped(),
+            glass: RoughDielectric::textured(Arc::new(ConstantTexture::new(parameters.ior)), roughness),
+        }
+    }
+}
+
+#[test]
+fn test_principled() {
+    use material::{sphere_intersection, assert_sample_consistent};
+
+    let (dg, wo) = sphere_intersection(0.2, 0.4);
+    for description in &["base_color=1 roughness=0.6 sheen=1 subsurface=0.5",
+                         "base_color=0.9,0.6,0.2 metallic=1 roughness=0.3",
+                         "roughness=0.4 transmission=1 clearcoat=1 clearcoat_gloss=0.5"] {
+        let material = Principled::new(&PrincipledParameters::parse(description).unwrap());
+        assert_sample_consistent(&material, &wo, &dg);
+    }
+
+    assert!(PrincipledParameters::parse("metallic").is_err());
+    assert!(PrincipledParameters::parse("glossiness=1").is_err());
+    assert!(PrincipledParameters::parse("base_color=1,0").is_err());
+    assert!(PrincipledParameters::parse("base_color=1,nan,0").is_err());
+    assert!(PrincipledParameters::parse("specular=-0.5").is_err());
+    assert!(PrincipledParameters::parse("transmission=2").is_err());
+    assert!(PrincipledParameters::parse("ior=inf").is_err());
+}
@@ -1,10 +1,22 @@
 use vector::Vector;
 
+// Two auxiliary rays, offset by one pixel horizontally and vertically from the
+// main ray, which are used to estimate the footprint of the ray on a surface
+#[derive(Copy, Clone)]
+pub struct RayDifferential {
+    pub rx_origin: Vector,
+    pub rx_direction: Vector,
+    pub ry_origin: Vector,
+    pub ry_direction: Vector,
+}
+
 pub struct Ray {
     pub origin: Vector,
     pub direction: Vector,
     pub t_min: f64,
     pub t_max: f64,
+    // Only camera rays carry differentials
+    pub differential: Option<RayDifferential>,
 }
 
 impl Ray {
@@ -12,8 +24,9 @@ impl Ray {
         Ray {
             origin: *o,
             direction: d.normalize(),
-            t_min: t_min,
-            t_max: t_max,
+            t_min,
+            t_max,
+            differential: None,
         }
     }
 
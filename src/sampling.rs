@@ -0,0 +1,170 @@
+// Warps uniformly distributed random numbers to other distributions, such as
+// points on a disk or directions on a hemisphere
+use vector::Vector;
+
+use std::f64;
+
+// Maps a point in the unit square to the unit disk (Shirley and Chiu 1997), which
+// preserves the relative areas and adjacency of samples
+pub fn concentric_sample_disk(u: (f64, f64)) -> (f64, f64) {
+    let (x, y) = (2.0 * u.0 - 1.0, 2.0 * u.1 - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, f64::consts::FRAC_PI_4 * (y / x))
+    } else {
+        (y, f64::consts::FRAC_PI_2 - f64::consts::FRAC_PI_4 * (x / y))
+    };
+    (r * theta.cos(), r * theta.sin())
+}
+
+// Samples a direction in the hemisphere around the z-axis with a density that is
+// proportional to the cosine of its angle with the axis, by projecting a point on
+// the unit disk up onto the hemisphere (Malley's method)
+pub fn cosine_sample_hemisphere(u: (f64, f64)) -> Vector {
+    let (x, y) = concentric_sample_disk(u);
+    Vector::new(x, y, (1.0 - x * x - y * y).max(0.0).sqrt())
+}
+
+pub fn cosine_hemisphere_pdf(cos_theta: f64) -> f64 {
+    cos_theta.max(0.0) / f64::consts::PI
+}
+
+// Samples a direction uniformly over the unit sphere
+pub fn uniform_sample_sphere(u: (f64, f64)) -> Vector {
+    let z = 1.0 - 2.0 * u.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * f64::consts::PI * u.1;
+    Vector::new(r * phi.cos(), r * phi.sin(), z)
+}
+
+// Samples a direction uniformly within a cone around the z-axis
+pub fn uniform_sample_cone(u: (f64, f64), cos_theta_max: f64) -> Vector {
+    let cos_theta = 1.0 - u.0 + u.0 * cos_theta_max;
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * f64::consts::PI * u.1;
+    Vector::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta)
+}
+
+pub fn uniform_cone_pdf(cos_theta_max: f64) -> f64 {
+    1.0 / (2.0 * f64::consts::PI * (1.0 - cos_theta_max))
+}
+
+// Samples a point uniformly over a triangle, returning its first two barycentric
+// coordinates
+pub fn uniform_sample_triangle(u: (f64, f64)) -> (f64, f64) {
+    let root = u.0.sqrt();
+    (1.0 - root, u.1 * root)
+}
+
+// Weighs a sample from one of two sampling strategies for multiple importance
+// sampling (Veach 1997), given the densities of both strategies
+pub fn power_heuristic(pdf: f64, other_pdf: f64) -> f64 {
+    let (f, g) = (pdf * pdf, other_pdf * other_pdf);
+    if f + g == 0.0 { 0.0 } else { f / (f + g) }
+}
+
+// A piecewise-constant distribution over the range 0..1, which is sampled by
+// inverting its cumulative distribution function
+pub struct Distribution1D {
+    pub function: Vec<f64>,
+    pub cdf: Vec<f64>,
+    // The integral of the function over the range 0..1
+    pub integral: f64,
+}
+
+impl Distribution1D {
+    // Functions that are zero everywhere are sampled uniformly
+    pub fn new(function: &[f64]) -> Distribution1D {
+        assert!(!function.is_empty(), "distributions require at least one value");
+        let n = function.len() as f64;
+        let mut function: Vec<f64> = function.iter().map(|f| f.abs()).collect();
+        if function.iter().all(|&f| f == 0.0) {
+            function = vec![1.0; function.len()];
+        }
+        let mut cdf = vec![0.0; function.len() + 1];
+        for i in 0..function.len() {
+            cdf[i + 1] = cdf[i] + function[i] / n;
+        }
+        let integral = cdf[function.len()];
+        for value in &mut cdf {
+            *value /= integral;
+        }
+        Distribution1D { function, cdf, integral }
+    }
+
+    pub fn count(&self) -> usize {
+        self.function.len()
+    }
+
+    // Returns a point in the range 0..1, its probability density and the index of
+    // the segment that contains it
+    pub fn sample_continuous(&self, u: f64) -> (f64, f64, usize) {
+        // Find the last segment whose cumulative value doesn't exceed `u`
+        let offset = match self.cdf.binary_search_by(|c| c.total_cmp(&u)) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        let offset = offset.min(self.count() - 1);
+        let width = self.cdf[offset + 1] - self.cdf[offset];
+        let du = if width > 0.0 { (u - self.cdf[offset]) / width } else { 0.0 };
+        ((offset as f64 + du) / self.count() as f64, self.function[offset] / self.integral, offset)
+    }
+
+    pub fn pdf(&self, x: f64) -> f64 {
+        let offset = ((x * self.count() as f64) as usize).min(self.count() - 1);
+        self.function[offset] / self.integral
+    }
+}
+
+// A piecewise-constant distribution over the unit square, which is sampled by
+// choosing a row from the marginal distribution and then a column from that row's
+// conditional distribution
+pub struct Distribution2D {
+    pub conditional: Vec<Distribution1D>,
+    pub marginal: Distribution1D,
+}
+
+impl Distribution2D {
+    // The function is given in rows of `width` values
+    pub fn new(function: &[f64], width: usize, height: usize) -> Distribution2D {
+        assert_eq!(function.len(), width * height);
+        let conditional: Vec<Distribution1D> =
+            function.chunks(width).map(Distribution1D::new).collect();
+        // Rows that are zero everywhere are never chosen
+        let integrals: Vec<f64> = function.chunks(width)
+            .map(|row| row.iter().map(|f| f.abs()).sum::<f64>() / width as f64)
+            .collect();
+        let marginal = Distribution1D::new(&integrals);
+        Distribution2D { conditional, marginal }
+    }
+
+    // Returns a point in the unit square and its probability density
+    pub fn sample_continuous(&self, u: (f64, f64)) -> ((f64, f64), f64) {
+        let (y, pdf_y, row) = self.marginal.sample_continuous(u.1);
+        let (x, pdf_x, _) = self.conditional[row].sample_continuous(u.0);
+        ((x, y), pdf_x * pdf_y)
+    }
+
+    pub fn pdf(&self, p: (f64, f64)) -> f64 {
+        let row = ((p.1 * self.marginal.count() as f64) as usize).min(self.marginal.count() - 1);
+        self.conditional[row].pdf(p.0) * self.marginal.pdf(p.1)
+    }
+}
+
+#[test]
+fn test_cosine_sample_hemisphere() {
+    // The directions must be unit length and the mean of their cosines must match
+    // that of the distribution, which is 2 / 3
+    let n = 64;
+    let mut total = 0.0;
+    for i in 0..n {
+        for j in 0..n {
+            let w = cosine_sample_hemisphere(((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64));
+            assert!((w.length() - 1.0).abs() < 1.0e-9 && w.z > 0.0);
+            total += w.z;
+        }
+    }
+    assert!((total / (n * n) as f64 - 2.0 / 3.0).abs() < 2.0e-3);
+}
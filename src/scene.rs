@@ -1,33 +1,127 @@
 use shape::DifferentialGeometry;
 use ray::Ray;
-use material::Material;
-use primitive::Primitive;
+use primitive::{Primitive, Sidedness};
+use light::{Light, AreaLight};
 
 use std::sync::Arc;
 
-// Scenes contain a list of primitives
+// Scenes contain a list of primitives, along with the lights that can be sampled
+// directly: primitives and the environment can only be set through `add` and
+// `set_environment`, so that every emitter that paths can hit is also a light
 pub struct Scene {
-    pub items: Vec<Primitive>,
+    items: Vec<Primitive>,
+    pub lights: Vec<Arc<dyn Light>>,
+    // The light that surrounds the scene, which rays that miss every primitive
+    // see
+    environment: Option<Arc<dyn Light>>,
 }
 
 impl Scene {
     pub fn new() -> Scene {
-        Scene { items: Vec::new() }
+        Scene {
+            items: Vec::new(),
+            lights: Vec::new(),
+            environment: None,
+        }
+    }
+
+    // Adds a primitive to the scene, along with an area light if it is emissive
+    pub fn add(&mut self, primitive: Primitive) {
+        if let Some(emission) = primitive.emission {
+            let mut light = AreaLight::new(primitive.shape.clone(), &emission);
+            light.two_sided = primitive.sidedness == Sidedness::TwoSided;
+            light.flip_normals = primitive.flip_normals;
+            self.lights.push(Arc::new(light));
+        }
+        self.items.push(primitive);
+    }
+
+    // Surrounds the scene with a light, which is also sampled directly: it
+    // replaces any previous environment, both here and among the lights
+    pub fn set_environment(&mut self, light: Arc<dyn Light>) {
+        let previous = self.environment
+            .as_ref()
+            .and_then(|environment| self.lights.iter().position(|l| Arc::ptr_eq(l, environment)));
+        match previous {
+            Some(index) => self.lights[index] = light.clone(),
+            None => self.lights.push(light.clone()),
+        }
+        self.environment = Some(light);
+    }
+
+    pub fn environment(&self) -> Option<&Arc<dyn Light>> {
+        self.environment.as_ref()
     }
 
-    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry, Arc<Material>)> {
+    // Whether anything blocks the ray before it reaches its maximum distance
+    pub fn is_occluded(&self, r: &Ray) -> bool {
+        self.items.iter().any(|item| match item.intersect(r) {
+            Some(dg) => dg.t < r.t_max,
+            None => false,
+        })
+    }
+
+    // Returns the closest point of intersection, ready for shading, along with the
+    // primitive that was hit
+    pub fn intersect(&self, incident: &Ray) -> Option<(DifferentialGeometry<'_>, &Primitive)> {
         let mut closest_intersection = None;
         let mut closest_t = incident.t_max;
 
         // Test against every object and find the closest point of intersection
         for item in &self.items {
-            if let Some((dg, mtl)) = item.intersect(&incident) {
+            if let Some(dg) = item.intersect(incident) {
                 if dg.t < closest_t {
                     closest_t = dg.t;
-                    closest_intersection = Some((dg, mtl));
+                    closest_intersection = Some((item, dg));
                 }
             }
         }
-        closest_intersection
+        closest_intersection.map(|(item, mut dg)| {
+            item.prepare_for_shading(&mut dg, incident);
+            (dg, item)
+        })
+    }
+}
+
+#[test]
+fn test_sidedness() {
+    use vector::Vector;
+    use shape::Plane;
+    use material::Lambertian;
+    use primitive::Sidedness;
+    use sky::Sky;
+
+    let up = Ray::new(&Vector::new(0.0, -1.0, 0.0), &Vector::new(0.0, 1.0, 0.0), 0.0, 10.0);
+    let scene_with = |sidedness: Sidedness, flip_normals: bool| {
+        let mut primitive = Primitive::new(Arc::new(Plane::new(&Vector::zero(), &Vector::new(0.0, 1.0, 0.0))),
+                                           Arc::new(Lambertian::new(&Vector::one())));
+        primitive.sidedness = sidedness;
+        primitive.flip_normals = flip_normals;
+        let mut scene = Scene::new();
+        scene.add(primitive);
+        scene
+    };
+
+    // The ray hits the back of the plane, unless its normal is flipped
+    for &(sidedness, flip_normals, front_face, absorbs) in &[(Sidedness::Outward, false, false, false),
+                                                            (Sidedness::TwoSided, false, true, false),
+                                                            (Sidedness::OneSided, false, false, true),
+                                                            (Sidedness::OneSided, true, true, false)] {
+        let scene = scene_with(sidedness, flip_normals);
+        let (dg, item) = scene.intersect(&up).unwrap();
+        assert_eq!(dg.front_face, front_face);
+        assert_eq!(dg.normal.y < 0.0, front_face);
+        assert_eq!(item.absorbs(&dg), absorbs);
     }
+
+    // Setting the environment again replaces the previous one
+    let mut scene = scene_with(Sidedness::Outward, false);
+    let sky = Arc::new(Sky::new(&Vector::new(0.0, 1.0, 0.0), 3.0, 1.0));
+    let dusk = Arc::new(Sky::new(&Vector::new(1.0, 0.1, 0.0).normalize(), 3.0, 1.0));
+    scene.set_environment(sky);
+    scene.set_environment(dusk.clone());
+    let dusk: Arc<dyn Light> = dusk;
+    assert_eq!(scene.lights.len(), 1);
+    assert!(Arc::ptr_eq(&scene.lights[0], &dusk));
+    assert!(Arc::ptr_eq(scene.environment().unwrap(), &dusk));
 }
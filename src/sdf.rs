@@ -0,0 +1,190 @@
+use vector::Vector;
+use ray::Ray;
+use shape::{Shape, DifferentialGeometry, EPSILON};
+
+// A node in a tree of signed distance functions: leaves are primitives, while
+// interior nodes combine or deform the distance fields of their children
+pub enum SdfNode {
+    Sphere { radius: f64 },
+    Cuboid { half_extents: Vector },
+    // A torus lying in the xz-plane
+    Torus { major_radius: f64, minor_radius: f64 },
+    // The Mandelbulb fractal (the classic variant uses a `power` of 8), which fits
+    // inside of a sphere of radius ~1.2 centered at the origin
+    Mandelbulb { power: f64, iterations: u32 },
+    Translate { offset: Vector, node: Box<SdfNode> },
+    Union(Box<SdfNode>, Box<SdfNode>),
+    Intersection(Box<SdfNode>, Box<SdfNode>),
+    Difference(Box<SdfNode>, Box<SdfNode>),
+    // A union that blends the two surfaces together over a distance `k`
+    SmoothUnion { a: Box<SdfNode>, b: Box<SdfNode>, k: f64 },
+    // Infinite repetition of a node, with one copy centered in each cell
+    Repeat { period: Vector, node: Box<SdfNode> },
+    // Rotates each horizontal slice of a node about the y-axis by `rate` radians
+    // per unit of height
+    Twist { rate: f64, node: Box<SdfNode> },
+    // Offsets the surface of a node by a sinusoidal pattern
+    Displace { amplitude: f64, frequency: f64, node: Box<SdfNode> },
+}
+
+impl SdfNode {
+    pub fn distance(&self, p: &Vector) -> f64 {
+        match *self {
+            SdfNode::Sphere { radius } => p.length() - radius,
+            SdfNode::Cuboid { ref half_extents } => {
+                let q = p.abs() - *half_extents;
+                q.max(&Vector::zero()).length() + q.max_component().min(0.0)
+            }
+            SdfNode::Torus { major_radius, minor_radius } => {
+                let qx = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (qx * qx + p.y * p.y).sqrt() - minor_radius
+            }
+            SdfNode::Mandelbulb { power, iterations } => mandelbulb(p, power, iterations),
+            SdfNode::Translate { ref offset, ref node } => node.distance(&(*p - *offset)),
+            SdfNode::Union(ref a, ref b) => a.distance(p).min(b.distance(p)),
+            SdfNode::Intersection(ref a, ref b) => a.distance(p).max(b.distance(p)),
+            SdfNode::Difference(ref a, ref b) => a.distance(p).max(-b.distance(p)),
+            SdfNode::SmoothUnion { ref a, ref b, k } => {
+                // Polynomial smooth minimum, which becomes a hard union as k
+                // goes to zero
+                let (da, db) = (a.distance(p), b.distance(p));
+                if k <= 0.0 {
+                    return da.min(db);
+                }
+                let h = (0.5 + 0.5 * (db - da) / k).clamp(0.0, 1.0);
+                db * (1.0 - h) + da * h - k * h * (1.0 - h)
+            }
+            SdfNode::Repeat { ref period, ref node } => {
+                let wrap = |x: f64, c: f64| x - c * (x / c).round();
+                node.distance(&Vector::new(wrap(p.x, period.x),
+                                           wrap(p.y, period.y),
+                                           wrap(p.z, period.z)))
+            }
+            SdfNode::Twist { rate, ref node } => {
+                let (s, c) = (rate * p.y).sin_cos();
+                node.distance(&Vector::new(c * p.x - s * p.z, p.y, s * p.x + c * p.z))
+            }
+            SdfNode::Displace { amplitude, frequency, ref node } => {
+                let displacement = (p.x * frequency).sin() * (p.y * frequency).sin() *
+                                   (p.z * frequency).sin();
+                node.distance(p) + displacement * amplitude
+            }
+        }
+    }
+
+    // Estimates the gradient of the distance field from samples at the four
+    // vertices of a tetrahedron around the point
+    pub fn gradient(&self, p: &Vector) -> Vector {
+        const H: f64 = 1.0e-5;
+        let k0 = Vector::new(1.0, -1.0, -1.0);
+        let k1 = Vector::new(-1.0, -1.0, 1.0);
+        let k2 = Vector::new(-1.0, 1.0, -1.0);
+        let k3 = Vector::new(1.0, 1.0, 1.0);
+        k0 * self.distance(&(*p + k0 * H)) + k1 * self.distance(&(*p + k1 * H)) +
+        k2 * self.distance(&(*p + k2 * H)) + k3 * self.distance(&(*p + k3 * H))
+    }
+}
+
+fn mandelbulb(p: &Vector, power: f64, iterations: u32) -> f64 {
+    const BAILOUT: f64 = 2.0;
+    let mut z = *p;
+    let mut dr = 1.0;
+    let mut r = z.length();
+
+    for _ in 0..iterations {
+        if r > BAILOUT {
+            break;
+        }
+        // The origin stays fixed under the iteration, so it lies in the set
+        if r == 0.0 {
+            return 0.0;
+        }
+
+        // Convert to spherical coordinates, raise to `power`, and convert back
+        let theta = (z.z / r).acos() * power;
+        let phi = z.y.atan2(z.x) * power;
+        dr = r.powf(power - 1.0) * power * dr + 1.0;
+        z = Vector::new(theta.sin() * phi.cos(), phi.sin() * theta.sin(), theta.cos()) *
+            r.powf(power) + *p;
+        r = z.length();
+    }
+    0.5 * r.ln() * r / dr
+}
+
+// A shape defined implicitly by a signed distance function, which is rendered
+// via sphere tracing
+pub struct Sdf {
+    pub root: SdfNode,
+    // The maximum number of steps taken along a ray before giving up
+    pub max_steps: u32,
+    // The maximum distance travelled along a ray before giving up
+    pub max_distance: f64,
+    // Rays closer than this distance to the surface are considered a hit
+    pub threshold: f64,
+    // Deformations like twisting and displacement can cause the distance
+    // field to overestimate the true distance, so steps are scaled by this
+    // factor (in the range 0..1) to avoid overshooting the surface
+    pub step_scale: f64,
+}
+
+impl Sdf {
+    pub fn new(root: SdfNode) -> Sdf {
+        Sdf {
+            root,
+            max_steps: 256,
+            max_distance: 100.0,
+            threshold: 1.0e-4,
+            step_scale: 1.0,
+        }
+    }
+}
+
+impl Shape for Sdf {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        // Rays that start inside of the surface (i.e. after refraction) march
+        // through the negative part of the distance field instead
+        let mut t = EPSILON;
+        let sign = if self.root.distance(&r.point_at(t)) < 0.0 { -1.0 } else { 1.0 };
+
+        for _ in 0..self.max_steps {
+            let position = r.point_at(t);
+            let distance = self.root.distance(&position) * sign;
+            if distance < self.threshold {
+                let normal = self.root.gradient(&position).normalize();
+                return Some(DifferentialGeometry::from_normal(r, t, &normal, self));
+            }
+
+            // The distance field guarantees that there is no surface within a
+            // sphere of radius `distance` around the current position
+            t += distance * self.step_scale;
+            if t > self.max_distance {
+                break;
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn test_sphere_tracing() {
+    use std::f64;
+
+    let sdf = Sdf::new(SdfNode::Sphere { radius: 1.0 });
+    let r = Ray::new(&Vector::new(0.0, 0.0, 5.0), &Vector::new(0.0, 0.0, -1.0), 0.0, f64::MAX);
+    let dg = sdf.intersect(&r).unwrap();
+    assert!((dg.t - 4.0).abs() < 1.0e-3);
+    assert!((dg.normal - Vector::new(0.0, 0.0, 1.0)).length() < 1.0e-3);
+
+    // Degenerate parameters still give finite distances
+    let hard = SdfNode::SmoothUnion {
+        a: Box::new(SdfNode::Sphere { radius: 1.0 }),
+        b: Box::new(SdfNode::Translate {
+            offset: Vector::new(1.5, 0.0, 0.0),
+            node: Box::new(SdfNode::Sphere { radius: 1.0 }),
+        }),
+        k: 0.0,
+    };
+    assert!((hard.distance(&Vector::new(0.75, 1.0, 0.0)) - 0.25).abs() < 1.0e-12);
+    let bulb = SdfNode::Mandelbulb { power: 8.0, iterations: 8 };
+    assert_eq!(bulb.distance(&Vector::zero()), 0.0);
+}
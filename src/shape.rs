@@ -1,7 +1,15 @@
 use vector::Vector;
 use ray::Ray;
+use bounds::BoundingBox;
+use frame::Frame;
+use sampling;
 
-const EPSILON: f64 = 0.001;
+use std::f64;
+
+extern crate rand;
+use rand::Rng;
+
+pub const EPSILON: f64 = 0.001;
 
 #[derive(Clone)]
 pub struct DifferentialGeometry<'a> {
@@ -9,25 +17,236 @@ pub struct DifferentialGeometry<'a> {
     pub t: f64,
     // Point of intersection
     pub position: Vector,
-    // Normal at point of intersection
+    // Geometric normal at point of intersection, which always faces outward
     pub normal: Vector,
+    // Normal used for shading, which may be interpolated or perturbed but always
+    // lies in the same hemisphere as the geometric normal
+    pub shading_normal: Vector,
+    // Surface parameterization at point of intersection
+    pub uv: (f64, f64),
+    // Partial derivatives of the position with respect to u and v
+    pub dpdu: Vector,
+    pub dpdv: Vector,
+    // Partial derivatives of the position and uv-coordinates with respect to
+    // the image plane, which are zero unless the ray carried differentials
+    pub dpdx: Vector,
+    pub dpdy: Vector,
+    pub dudx: f64,
+    pub dvdx: f64,
+    pub dudy: f64,
+    pub dvdy: f64,
+    // Whether the ray hit the outside of the surface
+    pub front_face: bool,
     // Shape that was hit
-    pub shape: &'a Shape,
+    pub shape: &'a dyn Shape,
 }
 
 impl<'a> DifferentialGeometry<'a> {
-    pub fn new(t: f64, p: &Vector, n: &Vector, s: &'a Shape) -> DifferentialGeometry<'a> {
+    pub fn new(r: &Ray,
+               t: f64,
+               n: &Vector,
+               uv: (f64, f64),
+               dpdu: &Vector,
+               dpdv: &Vector,
+               s: &'a dyn Shape)
+               -> DifferentialGeometry<'a> {
         DifferentialGeometry {
-            t: t,
-            position: *p,
+            t,
+            position: r.point_at(t),
             normal: *n,
+            shading_normal: *n,
+            uv,
+            dpdu: *dpdu,
+            dpdv: *dpdv,
+            dpdx: Vector::zero(),
+            dpdy: Vector::zero(),
+            dudx: 0.0,
+            dvdx: 0.0,
+            dudy: 0.0,
+            dvdy: 0.0,
+            front_face: r.direction.dot(n) < 0.0,
             shape: s,
         }
     }
+
+    // Builds the geometry for surfaces without a natural parameterization: the
+    // tangents are arbitrary and the uv-coordinates are the projection of the
+    // point onto them, which is only consistent locally
+    pub fn from_normal(r: &Ray, t: f64, n: &Vector, s: &'a dyn Shape) -> DifferentialGeometry<'a> {
+        let (dpdu, dpdv) = n.coordinate_system();
+        let position = r.point_at(t);
+        let uv = (position.dot(&dpdu), position.dot(&dpdv));
+        DifferentialGeometry::new(r, t, n, uv, &dpdu, &dpdv, s)
+    }
+
+    // Sets the shading normal, flipping it if necessary so that it lies in the
+    // same hemisphere as the geometric normal
+    pub fn set_shading_normal(&mut self, ns: &Vector) {
+        self.shading_normal = if ns.dot(&self.normal) < 0.0 { -*ns } else { *ns };
+    }
+
+    // Estimates the footprint of the ray on the surface by intersecting its
+    // differentials with the tangent plane at the point of intersection
+    pub fn compute_differentials(&mut self, r: &Ray) {
+        let differential = match r.differential {
+            Some(differential) => differential,
+            None => return,
+        };
+        let n = self.normal;
+        let distance = n.dot(&self.position);
+        let tx = (distance - n.dot(&differential.rx_origin)) / n.dot(&differential.rx_direction);
+        let ty = (distance - n.dot(&differential.ry_origin)) / n.dot(&differential.ry_direction);
+        if !tx.is_finite() || !ty.is_finite() {
+            return;
+        }
+        self.dpdx = differential.rx_origin + differential.rx_direction * tx - self.position;
+        self.dpdy = differential.ry_origin + differential.ry_direction * ty - self.position;
+
+        // Solve for the uv derivatives in the least-squares sense by dropping the
+        // dimension along which the normal is largest
+        let (a, b) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+            (1, 2)
+        } else if n.y.abs() > n.z.abs() {
+            (0, 2)
+        } else {
+            (0, 1)
+        };
+        let determinant = self.dpdu[a] * self.dpdv[b] - self.dpdv[a] * self.dpdu[b];
+        if determinant.abs() < 1.0e-12 {
+            return;
+        }
+        let solve = |d: &Vector| {
+            ((self.dpdv[b] * d[a] - self.dpdv[a] * d[b]) / determinant,
+             (self.dpdu[a] * d[b] - self.dpdu[b] * d[a]) / determinant)
+        };
+        let (dudx, dvdx) = solve(&self.dpdx);
+        let (dudy, dvdy) = solve(&self.dpdy);
+        self.dudx = dudx;
+        self.dvdx = dvdx;
+        self.dudy = dudy;
+        self.dvdy = dvdy;
+    }
+
+    // Returns a frame around the shading normal, aligned with the parameterization
+    pub fn shading_frame(&self) -> Frame {
+        Frame::new(&self.shading_normal, &self.dpdu)
+    }
+
+    // Turns the surface inside out
+    pub fn flip(&mut self) {
+        self.normal = -self.normal;
+        self.shading_normal = -self.shading_normal;
+        self.front_face = !self.front_face;
+    }
+}
+
+// A segment of a ray that lies inside of a closed shape, bounded by the points
+// where the ray enters and exits the shape
+#[derive(Clone)]
+pub struct Interval<'a> {
+    pub enter: DifferentialGeometry<'a>,
+    pub exit: DifferentialGeometry<'a>,
+}
+
+impl<'a> Interval<'a> {
+    pub fn new(enter: DifferentialGeometry<'a>, exit: DifferentialGeometry<'a>) -> Interval<'a> {
+        Interval { enter, exit }
+    }
 }
 
 pub trait Shape: Sync + Send {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry>;
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>>;
+
+    // Returns every interval along the (infinite) line through the ray that lies
+    // inside of the shape, sorted by distance and including those behind the ray
+    // origin: this is only meaningful for closed shapes, so by default a shape
+    // reports no intervals and cannot participate in constructive solid geometry
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        Vec::new()
+    }
+
+    // The surface area of the shape, or zero if it is unbounded or can't be
+    // sampled
+    fn area(&self) -> f64 {
+        0.0
+    }
+
+    // Chooses a point on the surface that is visible from `point`, such as a
+    // point on a light source, and returns its position and normal along with
+    // the density of the sample with respect to solid angle at `point`: shapes
+    // that can't be sampled return nothing
+    fn sample(&self, point: &Vector) -> Option<(Vector, Vector, f64)> {
+        None
+    }
+
+    // The density with respect to solid angle at `point` with which `sample`
+    // chooses the direction `wi`, which is zero if it misses the shape: by
+    // default, points are assumed to be chosen uniformly by area
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+        match self.intersect(&Ray::new(point, wi, 0.0, f64::INFINITY)) {
+            Some(dg) => area_to_solid_angle(point, &dg.position, &dg.normal, 1.0 / area),
+            None => 0.0,
+        }
+    }
+}
+
+// Converts a density with respect to surface area at `position` to one with
+// respect to solid angle at `point`
+fn area_to_solid_angle(point: &Vector, position: &Vector, normal: &Vector, pdf: f64) -> f64 {
+    let offset = *position - *point;
+    let distance_squared = offset.squared_length();
+    let cos_theta = normal.abs_dot(&offset) / distance_squared.sqrt();
+    if cos_theta <= 0.0 || distance_squared == 0.0 {
+        return 0.0;
+    }
+    pdf * distance_squared / cos_theta
+}
+
+// Turns a point chosen uniformly by area into a sample for `Shape::sample`
+fn area_sample(point: &Vector, position: Vector, normal: Vector, area: f64) -> Option<(Vector, Vector, f64)> {
+    let pdf = area_to_solid_angle(point, &position, &normal, 1.0 / area);
+    if pdf > 0.0 && pdf.is_finite() {
+        Some((position, normal, pdf))
+    } else {
+        None
+    }
+}
+
+fn random_pair() -> (f64, f64) {
+    let mut rng = rand::thread_rng();
+    (rng.next_f64(), rng.next_f64())
+}
+
+// Moller-Trumbore ray-triangle intersection: returns the distance along the ray
+// (which may be negative) and the barycentric coordinates of the hit relative to
+// `p1` and `p2`
+pub fn intersect_triangle(r: &Ray, p0: &Vector, p1: &Vector, p2: &Vector) -> Option<(f64, f64, f64)> {
+    let e1 = *p1 - *p0;
+    let e2 = *p2 - *p0;
+    let p = r.direction.cross(&e2);
+    let determinant = e1.dot(&p);
+
+    // The ray is parallel to the plane of the triangle
+    if determinant.abs() < 1.0e-12 {
+        return None;
+    }
+    let inv_determinant = 1.0 / determinant;
+
+    let s = r.origin - *p0;
+    let b1 = s.dot(&p) * inv_determinant;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(&e1);
+    let b2 = r.direction.dot(&q) * inv_determinant;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    Some((e2.dot(&q) * inv_determinant, b1, b2))
 }
 
 #[derive(Clone)]
@@ -37,7 +256,7 @@ pub struct Sphere {
 }
 
 impl Shape for Sphere {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry> {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
         // Sphere: dot((p - c), (p - c)) = r * r;
         // Ray: a + b * t = p
         // Substitute: dot((a + b * t - c), (a + b * t - c)) = r * r
@@ -59,19 +278,71 @@ impl Shape for Sphere {
         let solution_1 = -b - discriminant;
 
         if solution_1 > EPSILON {
-            let t: f64 = solution_1 * 0.5;
-            let position = r.point_at(t);
-            let normal = (position - self.center) / self.radius;
-            Some(DifferentialGeometry::new(t, &position, &normal, self))
+            Some(self.geometry_at(r, solution_1 * 0.5))
         } else if solution_0 > EPSILON {
-            let t: f64 = solution_0 * 0.5;
-            let position = r.point_at(t);
-            let normal = (position - self.center) / self.radius;
-            Some(DifferentialGeometry::new(t, &position, &normal, self))
+            Some(self.geometry_at(r, solution_0 * 0.5))
         } else {
             None
         }
     }
+
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        let b = ((r.origin - self.center) * 2.0).dot(&r.direction);
+        let c = (r.origin - self.center).dot(&(r.origin - self.center)) - self.radius * self.radius;
+        let discriminant = b * b - 4.0 * c;
+        if discriminant <= 0.0 {
+            return Vec::new();
+        }
+
+        let t_near = (-b - discriminant.sqrt()) * 0.5;
+        let t_far = (-b + discriminant.sqrt()) * 0.5;
+        vec![Interval::new(self.geometry_at(r, t_near), self.geometry_at(r, t_far))]
+    }
+
+    fn area(&self) -> f64 {
+        4.0 * f64::consts::PI * self.radius * self.radius
+    }
+
+    fn sample(&self, point: &Vector) -> Option<(Vector, Vector, f64)> {
+        // Points inside of the sphere see all of it, so it is sampled by area
+        let to_center = self.center - *point;
+        let distance_squared = to_center.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            let normal = sampling::uniform_sample_sphere(random_pair());
+            return area_sample(point, self.center + normal * self.radius, normal, self.area());
+        }
+
+        // Otherwise, only directions in the cone that the sphere subtends are
+        // sampled, which avoids choosing points on the far side of the sphere
+        let sin_theta_max_squared = self.radius * self.radius / distance_squared;
+        let cos_theta_max = (1.0 - sin_theta_max_squared).max(0.0).sqrt();
+        let axis = to_center / distance_squared.sqrt();
+        let wi = Frame::from_normal(&axis).to_world(&sampling::uniform_sample_cone(random_pair(), cos_theta_max));
+
+        // Find the nearest point along the sampled direction: directions at the
+        // edge of the cone may graze the sphere, so the discriminant is clamped
+        let b = wi.dot(&to_center);
+        let t = b - (b * b - distance_squared + self.radius * self.radius).max(0.0).sqrt();
+        let position = *point + wi * t;
+        let normal = (position - self.center).normalize();
+        Some((position, normal, sampling::uniform_cone_pdf(cos_theta_max)))
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        let to_center = self.center - *point;
+        let distance_squared = to_center.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return match self.intersect(&Ray::new(point, wi, 0.0, f64::INFINITY)) {
+                Some(dg) => area_to_solid_angle(point, &dg.position, &dg.normal, 1.0 / self.area()),
+                None => 0.0,
+            };
+        }
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).max(0.0).sqrt();
+        if wi.normalize().dot(&to_center) < cos_theta_max * distance_squared.sqrt() {
+            return 0.0;
+        }
+        sampling::uniform_cone_pdf(cos_theta_max)
+    }
 }
 
 impl Default for Sphere {
@@ -90,6 +361,35 @@ impl Sphere {
             radius: r,
         }
     }
+
+    fn geometry_at(&self, r: &Ray, t: f64) -> DifferentialGeometry<'_> {
+        let normal = (r.point_at(t) - self.center) / self.radius;
+
+        // Spherical coordinates, where theta is measured from the +y-axis and phi
+        // is measured from the +x-axis towards the -z-axis, such that the tangents
+        // and the normal form a right-handed frame
+        let theta = normal.y.clamp(-1.0, 1.0).acos();
+        let mut phi = (-normal.z).atan2(normal.x);
+        if phi < 0.0 {
+            phi += 2.0 * f64::consts::PI;
+        }
+        let uv = (phi / (2.0 * f64::consts::PI), 1.0 - theta / f64::consts::PI);
+
+        // Differentiate (r * sin(theta) * cos(phi), r * cos(theta), -r * sin(theta) * sin(phi))
+        // with respect to u = phi / 2 * pi and v = 1 - theta / pi
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let mut dpdu = Vector::new(-sin_theta * sin_phi, 0.0, -sin_theta * cos_phi) *
+                       (2.0 * f64::consts::PI * self.radius);
+        let dpdv = Vector::new(cos_theta * cos_phi, -sin_theta, -cos_theta * sin_phi) *
+                   (-f64::consts::PI * self.radius);
+
+        // The parameterization is degenerate at the poles
+        if dpdu.squared_length() == 0.0 {
+            dpdu = normal.coordinate_system().0;
+        }
+        DifferentialGeometry::new(r, t, &normal, uv, &dpdu, &dpdv, self)
+    }
 }
 
 #[derive(Clone)]
@@ -99,7 +399,7 @@ pub struct Plane {
 }
 
 impl Shape for Plane {
-    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry> {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
         // Ignore cases where the ray direction is parallel to the plane
         let denominator = r.direction.dot(&self.normal);
         if denominator.abs() > EPSILON {
@@ -108,11 +408,34 @@ impl Shape for Plane {
 
             // TODO: this is not correct - planes should be infinite
             if t >= EPSILON && r.point_at(t).y < 1.0 {
-                return Some(DifferentialGeometry::new(t, &r.point_at(t), &self.normal, self));
+                return Some(self.geometry_at(r, t));
             }
         }
         None
     }
+
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        // A plane is treated as the boundary of the half-space that lies behind it
+        let dg_at = |t: f64| self.geometry_at(r, t);
+        let denominator = r.direction.dot(&self.normal);
+        let distance = (r.origin - self.center).dot(&self.normal);
+
+        if denominator == 0.0 {
+            // The ray is parallel to the plane, so it is either entirely inside or
+            // entirely outside of the half-space
+            if distance < 0.0 {
+                return vec![Interval::new(dg_at(-f64::INFINITY), dg_at(f64::INFINITY))];
+            }
+            return Vec::new();
+        }
+
+        let t = -distance / denominator;
+        if denominator < 0.0 {
+            vec![Interval::new(dg_at(t), dg_at(f64::INFINITY))]
+        } else {
+            vec![Interval::new(dg_at(-f64::INFINITY), dg_at(t))]
+        }
+    }
 }
 
 impl Default for Plane {
@@ -131,4 +454,401 @@ impl Plane {
             normal: *n,
         }
     }
+
+    fn geometry_at(&self, r: &Ray, t: f64) -> DifferentialGeometry<'_> {
+        // The uv-coordinates are the planar coordinates of the point, measured from
+        // the plane's center along an arbitrary pair of tangent vectors
+        let (dpdu, dpdv) = self.normal.coordinate_system();
+        let offset = r.point_at(t) - self.center;
+        let uv = (offset.dot(&dpdu), offset.dot(&dpdv));
+        DifferentialGeometry::new(r, t, &self.normal, uv, &dpdu, &dpdv, self)
+    }
+}
+
+#[derive(Clone)]
+pub struct AxisAlignedBox {
+    pub bounds: BoundingBox,
+}
+
+impl Shape for AxisAlignedBox {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let (t_near, t_far) = self.bounds.intersect(r)?;
+
+        // If the ray starts inside of the box, the first hit is where it exits
+        let t = if t_near > EPSILON {
+            t_near
+        } else if t_far > EPSILON {
+            t_far
+        } else {
+            return None;
+        };
+        Some(self.geometry_at(r, t))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        match self.bounds.intersect(r) {
+            Some((t_near, t_far)) => {
+                vec![Interval::new(self.geometry_at(r, t_near), self.geometry_at(r, t_far))]
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Default for AxisAlignedBox {
+    fn default() -> AxisAlignedBox {
+        AxisAlignedBox::new(&Vector::new(-0.5, -0.5, -0.5), &Vector::new(0.5, 0.5, 0.5))
+    }
+}
+
+impl AxisAlignedBox {
+    pub fn new(a: &Vector, b: &Vector) -> AxisAlignedBox {
+        AxisAlignedBox { bounds: BoundingBox::new(a, b) }
+    }
+
+    fn geometry_at(&self, r: &Ray, t: f64) -> DifferentialGeometry<'_> {
+        let (normal, uv, dpdu, dpdv) = self.bounds.surface_at(&r.point_at(t));
+        DifferentialGeometry::new(r, t, &normal, uv, &dpdu, &dpdv, self)
+    }
+}
+
+#[derive(Clone)]
+pub struct OrientedBox {
+    pub center: Vector,
+    // Half of the box's size along each of its local axes
+    pub half_extents: Vector,
+    // An orthonormal basis describing the orientation of the box
+    pub axes: [Vector; 3],
+}
+
+impl OrientedBox {
+    pub fn new(c: &Vector, half_extents: &Vector, x_axis: &Vector, y_axis: &Vector) -> OrientedBox {
+        // Gram-Schmidt: make sure that the provided axes are orthonormal
+        let x = x_axis.normalize();
+        let y = (*y_axis - x * y_axis.dot(&x)).normalize();
+        let z = x.cross(&y);
+        OrientedBox {
+            center: *c,
+            half_extents: *half_extents,
+            axes: [x, y, z],
+        }
+    }
+
+    fn to_local(&self, v: &Vector) -> Vector {
+        Vector::new(v.dot(&self.axes[0]), v.dot(&self.axes[1]), v.dot(&self.axes[2]))
+    }
+
+    fn to_world(&self, v: &Vector) -> Vector {
+        self.axes[0] * v.x + self.axes[1] * v.y + self.axes[2] * v.z
+    }
+
+    fn local_bounds(&self) -> BoundingBox {
+        BoundingBox::new(&-self.half_extents, &self.half_extents)
+    }
+
+    fn to_local_ray(&self, r: &Ray) -> Ray {
+        Ray::new(&self.to_local(&(r.origin - self.center)),
+                 &self.to_local(&r.direction),
+                 r.t_min,
+                 r.t_max)
+    }
+
+    fn geometry_at(&self, r: &Ray, local_ray: &Ray, t: f64) -> DifferentialGeometry<'_> {
+        let (normal, uv, dpdu, dpdv) = self.local_bounds().surface_at(&local_ray.point_at(t));
+        DifferentialGeometry::new(r,
+                                  t,
+                                  &self.to_world(&normal),
+                                  uv,
+                                  &self.to_world(&dpdu),
+                                  &self.to_world(&dpdv),
+                                  self)
+    }
+}
+
+impl Shape for OrientedBox {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        // Transform the ray into the local space of the box, where it is
+        // axis-aligned: since the basis is orthonormal, distances along the
+        // ray are preserved
+        let local_ray = self.to_local_ray(r);
+        let (t_near, t_far) = self.local_bounds().intersect(&local_ray)?;
+
+        let t = if t_near > EPSILON {
+            t_near
+        } else if t_far > EPSILON {
+            t_far
+        } else {
+            return None;
+        };
+        Some(self.geometry_at(r, &local_ray, t))
+    }
+
+    fn intervals(&self, r: &Ray) -> Vec<Interval<'_>> {
+        let local_ray = self.to_local_ray(r);
+        match self.local_bounds().intersect(&local_ray) {
+            Some((t_near, t_far)) => {
+                vec![Interval::new(self.geometry_at(r, &local_ray, t_near),
+                                   self.geometry_at(r, &local_ray, t_far))]
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+// A parallelogram spanned by two edges from one of its corners, whose normal
+// is the cross product of the edges
+#[derive(Clone)]
+pub struct Quad {
+    pub corner: Vector,
+    pub edge_u: Vector,
+    pub edge_v: Vector,
+}
+
+impl Quad {
+    pub fn new(corner: &Vector, edge_u: &Vector, edge_v: &Vector) -> Quad {
+        Quad {
+            corner: *corner,
+            edge_u: *edge_u,
+            edge_v: *edge_v,
+        }
+    }
+}
+
+impl Shape for Quad {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let n = self.edge_u.cross(&self.edge_v);
+        let denominator = r.direction.dot(&n);
+        if denominator == 0.0 {
+            return None;
+        }
+        let t = (self.corner - r.origin).dot(&n) / denominator;
+        if t < EPSILON {
+            return None;
+        }
+
+        // Express the point of intersection in terms of the edges, which need
+        // not be perpendicular
+        let offset = r.point_at(t) - self.corner;
+        let w = n / n.squared_length();
+        let u = w.dot(&offset.cross(&self.edge_v));
+        let v = w.dot(&self.edge_u.cross(&offset));
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some(DifferentialGeometry::new(r, t, &n.normalize(), (u, v), &self.edge_u, &self.edge_v, self))
+    }
+
+    fn area(&self) -> f64 {
+        self.edge_u.cross(&self.edge_v).length()
+    }
+
+    fn sample(&self, point: &Vector) -> Option<(Vector, Vector, f64)> {
+        let (u, v) = random_pair();
+        let position = self.corner + self.edge_u * u + self.edge_v * v;
+        area_sample(point, position, self.edge_u.cross(&self.edge_v).normalize(), self.area())
+    }
+}
+
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Vector,
+    pub normal: Vector,
+    pub radius: f64,
+}
+
+impl Disk {
+    pub fn new(c: &Vector, n: &Vector, r: f64) -> Disk {
+        Disk {
+            center: *c,
+            normal: n.normalize(),
+            radius: r,
+        }
+    }
+}
+
+impl Shape for Disk {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let denominator = r.direction.dot(&self.normal);
+        if denominator == 0.0 {
+            return None;
+        }
+        let t = (self.center - r.origin).dot(&self.normal) / denominator;
+        if t < EPSILON {
+            return None;
+        }
+        let offset = r.point_at(t) - self.center;
+        let distance = offset.length();
+        if distance > self.radius {
+            return None;
+        }
+
+        // Polar coordinates, where u is the angle around the normal and v runs
+        // from the rim to the center, such that the tangents and the normal form a
+        // right-handed frame
+        let (s, t_axis) = self.normal.coordinate_system();
+        let mut phi = offset.dot(&t_axis).atan2(offset.dot(&s));
+        if phi < 0.0 {
+            phi += 2.0 * f64::consts::PI;
+        }
+        let (sin_phi, cos_phi) = phi.sin_cos();
+        let radial = s * cos_phi + t_axis * sin_phi;
+        let mut dpdu = (t_axis * cos_phi - s * sin_phi) * (2.0 * f64::consts::PI * distance);
+        let dpdv = -radial * self.radius;
+
+        // The parameterization is degenerate at the center
+        if dpdu.squared_length() == 0.0 {
+            dpdu = s;
+        }
+        Some(DifferentialGeometry::new(r,
+                                       t,
+                                       &self.normal,
+                                       (phi / (2.0 * f64::consts::PI), 1.0 - distance / self.radius),
+                                       &dpdu,
+                                       &dpdv,
+                                       self))
+    }
+
+    fn area(&self) -> f64 {
+        f64::consts::PI * self.radius * self.radius
+    }
+
+    fn sample(&self, point: &Vector) -> Option<(Vector, Vector, f64)> {
+        let (x, y) = sampling::concentric_sample_disk(random_pair());
+        let (s, t) = self.normal.coordinate_system();
+        let position = self.center + (s * x + t * y) * self.radius;
+        area_sample(point, position, self.normal, self.area())
+    }
+}
+
+// A single triangle, whose normal follows the counter-clockwise winding of its
+// vertices
+#[derive(Clone)]
+pub struct Triangle {
+    pub p0: Vector,
+    pub p1: Vector,
+    pub p2: Vector,
+}
+
+impl Triangle {
+    pub fn new(p0: &Vector, p1: &Vector, p2: &Vector) -> Triangle {
+        Triangle {
+            p0: *p0,
+            p1: *p1,
+            p2: *p2,
+        }
+    }
+
+    fn normal(&self) -> Vector {
+        (self.p1 - self.p0).cross(&(self.p2 - self.p0)).normalize()
+    }
+}
+
+impl Shape for Triangle {
+    fn intersect(&self, r: &Ray) -> Option<DifferentialGeometry<'_>> {
+        let (t, b1, b2) = intersect_triangle(r, &self.p0, &self.p1, &self.p2)?;
+        if t < EPSILON {
+            return None;
+        }
+        Some(DifferentialGeometry::new(r,
+                                       t,
+                                       &self.normal(),
+                                       (b1, b2),
+                                       &(self.p1 - self.p0),
+                                       &(self.p2 - self.p0),
+                                       self))
+    }
+
+    fn area(&self) -> f64 {
+        0.5 * (self.p1 - self.p0).cross(&(self.p2 - self.p0)).length()
+    }
+
+    fn sample(&self, point: &Vector) -> Option<(Vector, Vector, f64)> {
+        let (b0, b1) = sampling::uniform_sample_triangle(random_pair());
+        let position = self.p0 * b0 + self.p1 * b1 + self.p2 * (1.0 - b0 - b1);
+        area_sample(point, position, self.normal(), self.area())
+    }
+}
+
+#[test]
+fn test_differential_geometry() {
+    let close = |a: Vector, b: Vector| (a - b).length() < 1.0e-9;
+    let ray = |o: Vector, d: Vector| Ray::new(&o, &d, 0.0, f64::MAX);
+
+    // The front of the unit sphere, where u wraps around from the +x-axis
+    let sphere = Sphere::default();
+    let dg = sphere.intersect(&ray(Vector::new(0.0, 0.0, 5.0), Vector::new(0.0, 0.0, -1.0))).unwrap();
+    assert!((dg.t - 4.0).abs() < 1.0e-9);
+    assert!(close(dg.normal, Vector::new(0.0, 0.0, 1.0)));
+    assert!(close(dg.shading_normal, dg.normal));
+    assert!((dg.uv.0 - 0.75).abs() < 1.0e-9 && (dg.uv.1 - 0.5).abs() < 1.0e-9);
+    assert!(close(dg.dpdu, Vector::new(2.0 * f64::consts::PI, 0.0, 0.0)));
+    assert!(close(dg.dpdv, Vector::new(0.0, f64::consts::PI, 0.0)));
+    assert!(dg.front_face);
+
+    // From inside of the sphere, the normal still faces outward
+    let dg = sphere.intersect(&ray(Vector::zero(), Vector::new(1.0, 0.0, 0.0))).unwrap();
+    assert!(close(dg.normal, Vector::new(1.0, 0.0, 0.0)));
+    assert!(!dg.front_face);
+
+    // The plane's tangents lie in the plane, and its uv-coordinates are measured
+    // along them from its center
+    let plane = Plane::default();
+    let dg = plane.intersect(&ray(Vector::new(2.0, 3.0, 1.0), Vector::new(0.0, -1.0, 0.0))).unwrap();
+    assert!(close(dg.position, Vector::new(2.0, 0.0, 1.0)));
+    assert!(dg.dpdu.dot(&dg.normal).abs() < 1.0e-9 && dg.dpdv.dot(&dg.normal).abs() < 1.0e-9);
+    assert!((dg.uv.0 - dg.position.dot(&dg.dpdu)).abs() < 1.0e-9);
+    assert!((dg.uv.1 - dg.position.dot(&dg.dpdv)).abs() < 1.0e-9);
+    assert!(dg.front_face);
+    let dg = plane.intersect(&ray(Vector::new(0.0, -1.0, 0.0), Vector::new(0.0, 1.0, 0.0))).unwrap();
+    assert!(!dg.front_face);
+
+    // The +z face of the unit box, parameterized over its full extent
+    let cube = AxisAlignedBox::default();
+    let dg = cube.intersect(&ray(Vector::new(0.1, 0.2, 5.0), Vector::new(0.0, 0.0, -1.0))).unwrap();
+    assert!((dg.t - 4.5).abs() < 1.0e-9);
+    assert!(close(dg.normal, Vector::new(0.0, 0.0, 1.0)));
+    assert!((dg.uv.0 - 0.6).abs() < 1.0e-9 && (dg.uv.1 - 0.7).abs() < 1.0e-9);
+    assert!(close(dg.dpdu, Vector::new(1.0, 0.0, 0.0)));
+    assert!(close(dg.dpdv, Vector::new(0.0, 1.0, 0.0)));
+    assert!(dg.front_face);
+    let dg = cube.intersect(&ray(Vector::zero(), Vector::new(0.0, 0.0, 1.0))).unwrap();
+    assert!((dg.t - 0.5).abs() < 1.0e-9);
+    assert!(close(dg.normal, Vector::new(0.0, 0.0, 1.0)));
+    assert!(!dg.front_face);
+}
+
+#[test]
+fn test_oriented_box() {
+    let close = |a: Vector, b: Vector| (a - b).length() < 1.0e-9;
+    let ray = |o: Vector, d: Vector| Ray::new(&o, &d, 0.0, f64::MAX);
+
+    // A box turned 45 degrees about the y-axis, so that its local z-axis points
+    // along (-1, 0, 1)
+    let center = Vector::new(0.0, 1.0, 0.0);
+    let cube = OrientedBox::new(&center,
+                                &Vector::new(1.0, 0.5, 1.0),
+                                &Vector::new(1.0, 0.0, 1.0),
+                                &Vector::new(0.0, 1.0, 0.0));
+    let (x_axis, z_axis) = (Vector::new(1.0, 0.0, 1.0).normalize(), Vector::new(-1.0, 0.0, 1.0).normalize());
+    assert!(close(cube.axes[2], z_axis));
+
+    // Straight at the local +z face, off its center
+    let origin = center + z_axis * 5.0 + x_axis * 0.3;
+    let dg = cube.intersect(&ray(origin, -z_axis)).unwrap();
+    assert!((dg.t - 4.0).abs() < 1.0e-9);
+    assert!(close(dg.normal, z_axis));
+    assert!(close(dg.dpdu, x_axis * 2.0));
+    assert!(dg.front_face);
+
+    // The rotated box reaches further along the x-axis than its half extent, up to
+    // its corner at sqrt(2)
+    assert!(cube.intersect(&ray(Vector::new(1.3, 1.0, 5.0), Vector::new(0.0, 0.0, -1.0))).is_some());
+    assert!(cube.intersect(&ray(Vector::new(1.5, 1.0, 5.0), Vector::new(0.0, 0.0, -1.0))).is_none());
+    assert!(cube.intersect(&ray(Vector::new(0.0, 1.6, 5.0), Vector::new(0.0, 0.0, -1.0))).is_none());
+
+    // From the inside, the far face is hit from behind
+    let dg = cube.intersect(&ray(center, x_axis)).unwrap();
+    assert!((dg.t - 1.0).abs() < 1.0e-9);
+    assert!(close(dg.normal, x_axis));
+    assert!(!dg.front_face);
 }
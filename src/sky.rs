@@ -0,0 +1,319 @@
+// An analytic model of the clear daytime sky and the sun (Preetham, Shirley and
+// Smits 1999), which surrounds outdoor scenes in place of an environment map:
+// radiance is spectral, in W / (m^2 sr nm), and the sky is dark below the horizon
+use vector::Vector;
+use frame::Frame;
+use light::{Light, LightSample};
+use sampling::{self, Distribution2D};
+use spectrum::{self, LUMINOUS_EFFICACY};
+
+use std::f64;
+
+extern crate rand;
+use rand::Rng;
+
+// The angle that the sun's disk subtends, in radians
+const SUN_ANGULAR_RADIUS: f64 = 0.00465;
+const SUN_TEMPERATURE: f64 = 5778.0;
+
+// The resolution of the table with which the sky is sampled, over the azimuth and
+// the zenith angle of the upper hemisphere
+const SAMPLING_WIDTH: usize = 64;
+const SAMPLING_HEIGHT: usize = 16;
+
+// The coefficients of the Perez function for the luminance and chromaticity of the
+// sky, which are linear in the turbidity
+const PEREZ_LUMINANCE: [(f64, f64); 5] = [(0.1787, -1.4630),
+                                          (-0.3554, 0.4275),
+                                          (-0.0227, 5.3251),
+                                          (0.1206, -2.5771),
+                                          (-0.0670, 0.3703)];
+const PEREZ_X: [(f64, f64); 5] = [(-0.0193, -0.2592),
+                                  (-0.0665, 0.0008),
+                                  (-0.0004, 0.2125),
+                                  (-0.0641, -0.8989),
+                                  (-0.0033, 0.0452)];
+const PEREZ_Y: [(f64, f64); 5] = [(-0.0167, -0.2608),
+                                  (-0.0950, 0.0092),
+                                  (-0.0079, 0.2102),
+                                  (-0.0441, -1.6537),
+                                  (-0.0109, 0.0529)];
+
+// The relative distribution of light over the sky, given the zenith angle of a
+// direction and its angle to the sun
+fn perez(coefficients: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let [a, b, c, d, e] = *coefficients;
+    (1.0 + a * (b / cos_theta.max(0.0)).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+}
+
+// Evaluates a cubic in the sun's zenith angle for each power of the turbidity
+fn zenith_chromaticity(rows: [[f64; 4]; 3], turbidity: f64, theta: f64) -> f64 {
+    let cubic = |row: [f64; 4]| ((row[0] * theta + row[1]) * theta + row[2]) * theta + row[3];
+    turbidity * turbidity * cubic(rows[0]) + turbidity * cubic(rows[1]) + cubic(rows[2])
+}
+
+pub struct Sky {
+    // Points towards the sun: the y-axis is up, and the sun is due south along
+    // the +z-axis at noon in the northern hemisphere
+    pub sun_direction: Vector,
+    // How hazy the atmosphere is, from 2 (very clear) to 10 (hazy)
+    pub turbidity: f64,
+    // Scales the radiance of the sky and the sun
+    pub intensity: f64,
+    perez: [[f64; 5]; 3],
+    // The luminance, in cd / m^2, and chromaticity of the sky at the zenith
+    zenith: (f64, f64, f64),
+    daylight_basis: [Vector; 3],
+    sun_rgb: Vector,
+    cos_sun_max: f64,
+    distribution: Distribution2D,
+    // How often the sun is sampled rather than the sky
+    sun_probability: f64,
+}
+
+impl Sky {
+    pub fn new(sun_direction: &Vector, turbidity: f64, intensity: f64) -> Sky {
+        let turbidity = turbidity.clamp(2.0, 10.0);
+        let sun_direction = sun_direction.normalize();
+        // The model is only defined while the sun is above the horizon
+        let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+
+        let coefficients = |table: &[(f64, f64); 5]| {
+            let mut row = [0.0; 5];
+            for (value, &(slope, offset)) in row.iter_mut().zip(table.iter()) {
+                *value = slope * turbidity + offset;
+            }
+            row
+        };
+        let perez = [coefficients(&PEREZ_LUMINANCE), coefficients(&PEREZ_X), coefficients(&PEREZ_Y)];
+
+        let chi = (4.0 / 9.0 - turbidity / 120.0) * (f64::consts::PI - 2.0 * theta_sun);
+        let zenith_luminance = ((4.0453 * turbidity - 4.9710) * chi.tan() - 0.2155 * turbidity + 2.4192) * 1000.0;
+        let zenith_x = zenith_chromaticity([[0.00166, -0.00375, 0.00209, 0.0],
+                                            [-0.02903, 0.06377, -0.03202, 0.00394],
+                                            [0.11693, -0.21196, 0.06052, 0.25886]],
+                                           turbidity,
+                                           theta_sun);
+        let zenith_y = zenith_chromaticity([[0.00275, -0.00610, 0.00317, 0.0],
+                                            [-0.04214, 0.08970, -0.04153, 0.00516],
+                                            [0.15346, -0.26756, 0.06670, 0.26688]],
+                                           turbidity,
+                                           theta_sun);
+
+        let mut sky = Sky {
+            sun_direction,
+            turbidity,
+            intensity,
+            perez,
+            zenith: (zenith_luminance, zenith_x, zenith_y),
+            daylight_basis: spectrum::daylight_basis_xyz(),
+            sun_rgb: Vector::zero(),
+            cos_sun_max: SUN_ANGULAR_RADIUS.cos(),
+            distribution: Distribution2D::new(&[1.0], 1, 1),
+            sun_probability: 0.0,
+        };
+        sky.sun_rgb = spectrum::xyz_to_linear_srgb(&spectrum::spectrum_to_xyz(|lambda| sky.sun_radiance(lambda)));
+
+        // Sample the sky in proportion to its luminance, and the sun in proportion
+        // to its share of the light that reaches the ground
+        let mut function = Vec::with_capacity(SAMPLING_WIDTH * SAMPLING_HEIGHT);
+        for y in 0..SAMPLING_HEIGHT {
+            for x in 0..SAMPLING_WIDTH {
+                let uv = ((x as f64 + 0.5) / SAMPLING_WIDTH as f64, (y as f64 + 0.5) / SAMPLING_HEIGHT as f64);
+                let (_, _, luminance) = sky.sky_xy_luminance(&Sky::uv_to_direction(uv));
+                function.push(luminance * (uv.1 * f64::consts::FRAC_PI_2).sin());
+            }
+        }
+        sky.distribution = Distribution2D::new(&function, SAMPLING_WIDTH, SAMPLING_HEIGHT);
+        if sky.sun_direction.y > 0.0 {
+            let sky_power = sky.distribution.marginal.integral * f64::consts::PI * f64::consts::PI;
+            let sun_solid_angle = 2.0 * f64::consts::PI * (1.0 - sky.cos_sun_max);
+            let sun_power = sky.sun_rgb.luminance() * LUMINOUS_EFFICACY * sun_solid_angle;
+            sky.sun_probability = (sun_power / (sun_power + sky_power)).clamp(0.1, 0.9);
+        }
+        sky
+    }
+
+    // Places the sun for the given latitude in degrees, day of the year (1 to 365)
+    // and solar time in hours, such that the sun is highest at 12
+    pub fn at_time(latitude: f64, day: f64, solar_time: f64, turbidity: f64, intensity: f64) -> Sky {
+        let latitude = latitude.to_radians();
+        let declination = 0.4093 * (2.0 * f64::consts::PI * (day - 81.0) / 368.0).sin();
+        let hour_angle = f64::consts::PI * solar_time / 12.0;
+
+        let elevation = (latitude.sin() * declination.sin() -
+                         latitude.cos() * declination.cos() * hour_angle.cos())
+            .asin();
+        // The azimuth is measured from the south towards the west
+        let azimuth = (-declination.cos() * hour_angle.sin())
+            .atan2(latitude.cos() * declination.sin() - latitude.sin() * declination.cos() * hour_angle.cos());
+        let direction = Vector::new(-azimuth.sin() * elevation.cos(),
+                                    elevation.sin(),
+                                    azimuth.cos() * elevation.cos());
+        Sky::new(&direction, turbidity, intensity)
+    }
+
+    // Maps the unit square to the upper hemisphere, for sampling
+    fn uv_to_direction(uv: (f64, f64)) -> Vector {
+        let (sin_theta, cos_theta) = (uv.1 * f64::consts::FRAC_PI_2).sin_cos();
+        let (sin_phi, cos_phi) = (uv.0 * 2.0 * f64::consts::PI).sin_cos();
+        Vector::new(sin_theta * cos_phi, cos_theta, sin_theta * sin_phi)
+    }
+
+    fn direction_to_uv(direction: &Vector) -> (f64, f64) {
+        let mut phi = direction.z.atan2(direction.x);
+        if phi < 0.0 {
+            phi += 2.0 * f64::consts::PI;
+        }
+        let theta = (direction.x * direction.x + direction.z * direction.z).sqrt().atan2(direction.y);
+        (phi / (2.0 * f64::consts::PI), theta / f64::consts::FRAC_PI_2)
+    }
+
+    // The chromaticity and luminance, in cd / m^2, of the sky in a direction
+    fn sky_xy_luminance(&self, direction: &Vector) -> (f64, f64, f64) {
+        if direction.y <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let cos_theta = direction.y;
+        let cos_sun = self.sun_direction.y.clamp(0.0, 1.0);
+        let gamma = direction.dot(&self.sun_direction).clamp(-1.0, 1.0).acos();
+        let theta_sun = cos_sun.acos();
+        let relative = |i: usize| perez(&self.perez[i], cos_theta, gamma) / perez(&self.perez[i], 1.0, theta_sun);
+        (self.zenith.1 * relative(1), self.zenith.2 * relative(2), self.zenith.0 * relative(0))
+    }
+
+    // The transmittance of the atmosphere along the path of sunlight, which
+    // accounts for scattering by molecules and aerosols: the weaker absorption by
+    // ozone and water vapor is ignored
+    fn sun_transmittance(&self, lambda: f64) -> f64 {
+        let zenith_angle = self.sun_direction.y.clamp(0.0, 1.0).acos();
+        let air_mass = 1.0 / (zenith_angle.cos() + 0.15 * (93.885 - zenith_angle.to_degrees()).powf(-1.253));
+        let micrometers = lambda * 1.0e-3;
+        let rayleigh = 0.008735 * micrometers.powf(-4.08);
+        let beta = 0.04608365822050 * self.turbidity - 0.04586025928522;
+        let aerosol = beta * micrometers.powf(-1.3);
+        (-(rayleigh + aerosol) * air_mass).exp()
+    }
+
+    // The spectral radiance of the sun's disk, as seen from the ground
+    fn sun_radiance(&self, lambda: f64) -> f64 {
+        if self.sun_direction.y <= 0.0 {
+            return 0.0;
+        }
+        spectrum::blackbody(lambda, SUN_TEMPERATURE) * self.sun_transmittance(lambda)
+    }
+
+    fn in_sun(&self, direction: &Vector) -> bool {
+        direction.normalize().dot(&self.sun_direction) >= self.cos_sun_max
+    }
+
+    // The spectral radiance of the sky, and the sun if it lies in the direction
+    pub fn spectral_radiance(&self, direction: &Vector, lambda: f64) -> f64 {
+        let (x, y, luminance) = self.sky_xy_luminance(&direction.normalize());
+        let mut radiance = 0.0;
+        if luminance > 0.0 {
+            // The spectrum of daylight with the sky's chromaticity, scaled to its
+            // luminance
+            let (m1, m2) = spectrum::daylight_weights((x, y));
+            let basis_luminance = self.daylight_basis[0].y + m1 * self.daylight_basis[1].y +
+                                  m2 * self.daylight_basis[2].y;
+            radiance += luminance / LUMINOUS_EFFICACY * spectrum::daylight((x, y), lambda) / basis_luminance;
+        }
+        if self.in_sun(direction) {
+            radiance += self.sun_radiance(lambda);
+        }
+        radiance * self.intensity
+    }
+
+    fn sky_pdf(&self, direction: &Vector) -> f64 {
+        if direction.y <= 0.0 {
+            return 0.0;
+        }
+        let uv = Sky::direction_to_uv(direction);
+        let sin_theta = (uv.1 * f64::consts::FRAC_PI_2).sin();
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        self.distribution.pdf(uv) / (f64::consts::PI * f64::consts::PI * sin_theta)
+    }
+}
+
+impl Light for Sky {
+    fn sample(&self, point: &Vector) -> Option<LightSample> {
+        let mut rng = rand::thread_rng();
+        let wi = if rng.next_f64() < self.sun_probability {
+            let local = sampling::uniform_sample_cone((rng.next_f64(), rng.next_f64()), self.cos_sun_max);
+            Frame::from_normal(&self.sun_direction).to_world(&local)
+        } else {
+            let (uv, _) = self.distribution.sample_continuous((rng.next_f64(), rng.next_f64()));
+            Sky::uv_to_direction(uv)
+        };
+        let pdf = self.pdf(point, &wi);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            radiance: self.radiance(&wi),
+            pdf,
+            distance: f64::INFINITY,
+        })
+    }
+
+    fn pdf(&self, point: &Vector, wi: &Vector) -> f64 {
+        let wi = wi.normalize();
+        let mut pdf = (1.0 - self.sun_probability) * self.sky_pdf(&wi);
+        if self.sun_probability > 0.0 && self.in_sun(&wi) {
+            pdf += self.sun_probability * sampling::uniform_cone_pdf(self.cos_sun_max);
+        }
+        pdf
+    }
+
+    fn radiance(&self, direction: &Vector) -> Vector {
+        let direction = direction.normalize();
+        let (x, y, luminance) = self.sky_xy_luminance(&direction);
+        let mut rgb = Vector::zero();
+        if luminance > 0.0 {
+            let (m1, m2) = spectrum::daylight_weights((x, y));
+            let xyz = self.daylight_basis[0] + self.daylight_basis[1] * m1 + self.daylight_basis[2] * m2;
+            rgb += spectrum::xyz_to_linear_srgb(&(xyz * (luminance / LUMINOUS_EFFICACY / xyz.y)));
+        }
+        if self.in_sun(&direction) {
+            rgb += self.sun_rgb;
+        }
+        rgb.max(&Vector::zero()) * self.intensity
+    }
+}
+
+#[test]
+fn test_sky() {
+    // At noon on an equinox, the sun is due south at a zenith angle equal to the
+    // latitude
+    let sky = Sky::at_time(40.0, 81.0, 12.0, 3.0, 1.0);
+    assert!((sky.sun_direction.y - 40.0f64.to_radians().cos()).abs() < 1.0e-9);
+    assert!(sky.sun_direction.z > 0.0 && sky.sun_direction.x.abs() < 1.0e-9);
+    // The sun rises in the east
+    assert!(Sky::at_time(40.0, 81.0, 9.0, 3.0, 1.0).sun_direction.x > 0.0);
+
+    // The sky is blue overhead, brightest around the sun and dark below the
+    // horizon, and the sun is far brighter than the sky
+    let zenith = sky.radiance(&Vector::new(0.0, 1.0, 0.0));
+    assert!(zenith.z > zenith.x);
+    let circumsolar = sky.radiance(&Frame::from_normal(&sky.sun_direction).to_world(&Vector::new(0.1, 0.0, 1.0)));
+    let opposite = sky.radiance(&Vector::new(-sky.sun_direction.x, sky.sun_direction.y, -sky.sun_direction.z));
+    assert!(circumsolar.luminance() > opposite.luminance());
+    assert_eq!(sky.radiance(&Vector::new(0.3, -0.2, 0.1)), Vector::zero());
+    assert!(sky.radiance(&sky.sun_direction).luminance() > 1000.0 * circumsolar.luminance());
+
+    // The spectral radiance agrees with the RGB radiance
+    let xyz = spectrum::spectrum_to_xyz(|lambda| sky.spectral_radiance(&Vector::new(0.0, 1.0, 0.0), lambda));
+    assert!((xyz.y - zenith.luminance()).abs() < 0.05 * xyz.y);
+
+    // Sampled densities agree with the ones that are evaluated for the same
+    // directions
+    for _ in 0..1000 {
+        let sample = sky.sample(&Vector::zero()).unwrap();
+        assert!((sky.pdf(&Vector::zero(), &sample.wi) - sample.pdf).abs() <= 1.0e-6 * sample.pdf);
+        assert!(sample.wi.y > 0.0);
+    }
+}
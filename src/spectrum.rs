@@ -0,0 +1,289 @@
+// Conversions from spectral quantities to the RGB colors that the renderer works
+// with: wavelengths are in nanometers
+use vector::Vector;
+
+use std::f64;
+
+// The range of visible wavelengths and the spacing at which spectra are sampled
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+pub const LAMBDA_STEP: f64 = 5.0;
+
+// The luminous efficacy of radiation at 555 nm, in lm / W, which relates
+// radiometric quantities to photometric ones
+pub const LUMINOUS_EFFICACY: f64 = 683.0;
+
+// A piecewise Gaussian with different widths on either side of its mean
+fn lobe(lambda: f64, mean: f64, below: f64, above: f64) -> f64 {
+    let t = (lambda - mean) / if lambda < mean { below } else { above };
+    (-0.5 * t * t).exp()
+}
+
+// The CIE 1931 color matching functions, using the multi-lobe fit of Wyman, Sloan
+// and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> Vector {
+    Vector::new(1.056 * lobe(lambda, 599.8, 37.9, 31.0) + 0.362 * lobe(lambda, 442.0, 16.0, 26.7) -
+                0.065 * lobe(lambda, 501.1, 20.4, 26.2),
+                0.821 * lobe(lambda, 568.8, 46.9, 40.5) + 0.286 * lobe(lambda, 530.9, 16.3, 31.1),
+                1.217 * lobe(lambda, 437.0, 11.8, 36.0) + 0.681 * lobe(lambda, 459.0, 26.0, 13.8))
+}
+
+// Converts CIE XYZ to linear sRGB (with a D65 white point)
+pub fn xyz_to_linear_srgb(xyz: &Vector) -> Vector {
+    Vector::new(3.2404542 * xyz.x - 1.5371385 * xyz.y - 0.4985314 * xyz.z,
+                -0.9692660 * xyz.x + 1.8760108 * xyz.y + 0.0415560 * xyz.z,
+                0.0556434 * xyz.x - 0.2040259 * xyz.y + 1.0572252 * xyz.z)
+}
+
+// Integrates a spectrum against the color matching functions, e.g. to find the
+// tristimulus values of a spectral radiance in W / (m^2 sr nm)
+pub fn spectrum_to_xyz<F: Fn(f64) -> f64>(spectrum: F) -> Vector {
+    let mut xyz = Vector::zero();
+    let mut lambda = LAMBDA_MIN;
+    while lambda <= LAMBDA_MAX {
+        xyz += cie_xyz(lambda) * (spectrum(lambda) * LAMBDA_STEP);
+        lambda += LAMBDA_STEP;
+    }
+    xyz
+}
+
+// Converts a reflectance spectrum to linear sRGB by integrating it against the
+// color matching functions, white balanced so that constant spectra map to grays:
+// saturated colors outside of the sRGB gamut are clamped
+pub fn reflectance_to_rgb<F: Fn(f64) -> f64>(reflectance: F) -> Vector {
+    let white = xyz_to_linear_srgb(&spectrum_to_xyz(|_| 1.0));
+    (xyz_to_linear_srgb(&spectrum_to_xyz(reflectance)) / white).max(&Vector::zero()).min(&Vector::one())
+}
+
+// The mean and the first two characteristic vectors of CIE daylight, from 380 to
+// 780 nm in steps of 10 nm
+const DAYLIGHT_S0: [f64; 41] = [63.4, 65.8, 94.8, 104.8, 105.9, 96.8, 113.9, 125.6, 125.5, 121.3, 121.3, 113.5,
+                                113.1, 110.8, 106.5, 108.8, 105.3, 104.4, 100.0, 96.0, 95.1, 89.1, 90.5, 90.3,
+                                88.4, 84.0, 85.1, 81.9, 82.6, 84.9, 81.3, 71.9, 74.3, 76.4, 63.3, 71.7, 77.0,
+                                65.2, 47.7, 68.6, 65.0];
+const DAYLIGHT_S1: [f64; 41] = [38.5, 35.0, 43.4, 46.3, 43.9, 37.1, 36.7, 35.9, 32.6, 27.9, 24.3, 20.1, 16.2,
+                                13.2, 8.6, 6.1, 4.2, 1.9, 0.0, -1.6, -3.5, -3.5, -5.8, -7.2, -8.6, -9.5, -10.9,
+                                -10.7, -12.0, -14.0, -13.6, -12.0, -13.3, -12.9, -10.6, -11.6, -12.2, -10.2,
+                                -7.8, -11.2, -10.4];
+const DAYLIGHT_S2: [f64; 41] = [3.0, 1.2, -1.1, -0.5, -0.7, -1.2, -2.6, -2.9, -2.8, -2.6, -2.6, -1.8, -1.5, -1.3,
+                                -1.2, -1.0, -0.5, -0.3, 0.0, 0.2, 0.5, 2.1, 3.2, 4.1, 4.7, 5.1, 6.7, 7.3, 8.6,
+                                9.8, 10.2, 8.3, 9.6, 8.5, 7.0, 7.6, 8.0, 6.7, 5.2, 7.4, 6.8];
+
+// Linearly interpolates a table of values with the given spacing in nm, starting
+// at `LAMBDA_MIN`, and clamps wavelengths outside of it to the ends
+fn interpolate_table(table: &[f64], spacing: f64, lambda: f64) -> f64 {
+    let x = ((lambda - LAMBDA_MIN) / spacing).max(0.0).min((table.len() - 1) as f64);
+    let i = (x as usize).min(table.len() - 2);
+    let t = x - i as f64;
+    table[i] * (1.0 - t) + table[i + 1] * t
+}
+
+// The relative spectral power distribution of daylight with the given chromaticity,
+// which should lie near the daylight locus, normalized to 100 at 560 nm
+pub fn daylight(chromaticity: (f64, f64), lambda: f64) -> f64 {
+    let (m1, m2) = daylight_weights(chromaticity);
+    interpolate_table(&DAYLIGHT_S0, 10.0, lambda) + m1 * interpolate_table(&DAYLIGHT_S1, 10.0, lambda) +
+    m2 * interpolate_table(&DAYLIGHT_S2, 10.0, lambda)
+}
+
+// The weights of the characteristic vectors of daylight with the given
+// chromaticity
+pub fn daylight_weights(chromaticity: (f64, f64)) -> (f64, f64) {
+    let (x, y) = chromaticity;
+    let denominator = 0.0241 + 0.2562 * x - 0.7341 * y;
+    ((-1.3515 - 1.7703 * x + 5.9114 * y) / denominator,
+     (0.0300 - 31.4424 * x + 30.0717 * y) / denominator)
+}
+
+// The tristimulus values of the mean and characteristic vectors of daylight, from
+// which the color of any daylight follows without integrating its spectrum
+pub fn daylight_basis_xyz() -> [Vector; 3] {
+    [spectrum_to_xyz(|lambda| interpolate_table(&DAYLIGHT_S0, 10.0, lambda)),
+     spectrum_to_xyz(|lambda| interpolate_table(&DAYLIGHT_S1, 10.0, lambda)),
+     spectrum_to_xyz(|lambda| interpolate_table(&DAYLIGHT_S2, 10.0, lambda))]
+}
+
+// Interpolates an RGB quantity, such as an index of refraction, to a wavelength
+// by treating each channel as a sample at a representative wavelength
+pub fn rgb_at_wavelength(rgb: &Vector, lambda: f64) -> f64 {
+    const BLUE: f64 = 450.0;
+    const GREEN: f64 = 550.0;
+    const RED: f64 = 650.0;
+    if lambda <= BLUE {
+        rgb.z
+    } else if lambda <= GREEN {
+        rgb.z + (rgb.y - rgb.z) * (lambda - BLUE) / (GREEN - BLUE)
+    } else if lambda <= RED {
+        rgb.y + (rgb.x - rgb.y) * (lambda - GREEN) / (RED - GREEN)
+    } else {
+        rgb.x
+    }
+}
+
+// Spectral radiance emitted by a black body at the given temperature in kelvin,
+// in W / (m^2 sr nm)
+pub fn blackbody(lambda: f64, temperature: f64) -> f64 {
+    const H: f64 = 6.62607015e-34;
+    const C: f64 = 2.99792458e8;
+    const K: f64 = 1.380649e-23;
+    let lambda = lambda * 1.0e-9;
+    2.0 * H * C * C / (lambda.powi(5) * ((H * C / (lambda * K * temperature)).exp() - 1.0)) * 1.0e-9
+}
+
+// The chromaticity of daylight with the given correlated color temperature, from
+// 4000 K to 25000 K
+pub fn daylight_chromaticity(temperature: f64) -> (f64, f64) {
+    let t = temperature.clamp(4000.0, 25000.0);
+    let x = if t <= 7000.0 {
+        -4.6070e9 / (t * t * t) + 2.9678e6 / (t * t) + 0.09911e3 / t + 0.244063
+    } else {
+        -2.0064e9 / (t * t * t) + 1.9018e6 / (t * t) + 0.24748e3 / t + 0.237040
+    };
+    (x, -3.0 * x * x + 2.870 * x - 0.275)
+}
+
+// Representative fluorescent lamps from 380 to 780 nm in steps of 5 nm: a standard
+// cool white (F2), a broadband daylight (F7) and a narrow-band tri-phosphor lamp
+// (F11)
+const FLUORESCENT_F2: [f64; 81] = [1.18, 1.48, 1.84, 2.15, 3.44, 15.69, 3.85, 3.74, 4.19, 4.62, 5.06, 34.98,
+                                   11.81, 6.27, 6.63, 6.93, 7.19, 7.40, 7.54, 7.62, 7.65, 7.62, 7.62, 7.45,
+                                   7.28, 7.15, 7.05, 7.04, 7.16, 7.47, 8.04, 8.88, 10.01, 24.88, 16.64, 14.59,
+                                   16.16, 17.56, 18.62, 21.47, 22.79, 19.29, 18.66, 17.73, 16.54, 15.21,
+                                   13.80, 12.36, 10.95, 9.65, 8.40, 7.32, 6.31, 5.43, 4.68, 4.02, 3.45, 2.96,
+                                   2.55, 2.19, 1.89, 1.64, 1.53, 1.27, 1.10, 0.99, 0.88, 0.76, 0.68, 0.61,
+                                   0.56, 0.54, 0.51, 0.47, 0.47, 0.43, 0.46, 0.47, 0.40, 0.33, 0.27];
+const FLUORESCENT_F7: [f64; 81] = [2.56, 3.18, 3.84, 4.53, 6.15, 19.37, 7.37, 7.05, 7.71, 8.41, 9.15, 44.14,
+                                   17.52, 11.35, 12.00, 12.58, 13.08, 13.45, 13.71, 13.88, 13.95, 13.93,
+                                   13.82, 13.64, 13.43, 13.25, 13.08, 12.93, 12.78, 12.60, 12.44, 12.33,
+                                   12.26, 29.52, 17.05, 12.44, 12.58, 12.72, 12.83, 15.46, 16.75, 12.83,
+                                   12.67, 12.45, 12.19, 11.89, 11.60, 11.35, 11.12, 10.95, 10.76, 10.42,
+                                   10.11, 10.04, 10.02, 10.11, 9.87, 8.65, 7.27, 6.44, 5.83, 5.41, 5.04, 4.57,
+                                   4.12, 3.77, 3.46, 3.08, 2.73, 2.47, 2.25, 2.06, 1.90, 1.75, 1.62, 1.54,
+                                   1.45, 1.32, 1.17, 0.99, 0.81];
+const FLUORESCENT_F11: [f64; 81] = [0.91, 0.63, 0.46, 0.37, 1.29, 12.68, 1.59, 1.79, 2.46, 3.33, 4.49, 33.94,
+                                    12.13, 6.95, 7.19, 7.12, 6.72, 6.13, 5.46, 4.79, 5.66, 14.29, 14.96, 8.97,
+                                    4.72, 2.33, 1.47, 1.10, 0.89, 0.83, 1.18, 4.90, 39.59, 72.84, 32.61, 7.52,
+                                    2.83, 1.96, 1.67, 4.43, 11.28, 14.76, 12.73, 9.74, 7.33, 9.72, 55.27,
+                                    42.58, 13.18, 13.16, 12.26, 5.11, 2.07, 2.34, 3.58, 3.01, 2.48, 2.14,
+                                    1.54, 1.33, 1.46, 1.94, 2.00, 1.20, 1.35, 4.10, 5.58, 2.51, 0.57, 0.27,
+                                    0.23, 0.21, 0.24, 0.24, 0.20, 0.24, 0.32, 0.26, 0.16, 0.12, 0.09];
+
+// The spectra of light sources, which describe the color of emitters physically:
+// their brightness is given separately as a luminance or a luminous power
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Illuminant {
+    // An ideal thermal emitter at the given temperature in kelvin
+    Blackbody(f64),
+    // Daylight with the given correlated color temperature in kelvin
+    Daylight(f64),
+    // Incandescent tungsten light
+    A,
+    // Horizon light and noon daylight, the white points of print and sRGB
+    D50,
+    D65,
+    F2,
+    F7,
+    F11,
+}
+
+impl Illuminant {
+    // The relative spectral power distribution of the illuminant
+    pub fn spectrum(&self, lambda: f64) -> f64 {
+        match *self {
+            Illuminant::Blackbody(temperature) => blackbody(lambda, temperature),
+            Illuminant::Daylight(temperature) => daylight(daylight_chromaticity(temperature), lambda),
+            // Defined by Planck's law with the constants in use when the
+            // illuminant was standardized, normalized to 100 at 560 nm
+            Illuminant::A => {
+                const C2: f64 = 1.435e7;
+                100.0 * (560.0 / lambda).powi(5) * ((C2 / (2848.0 * 560.0)).exp() - 1.0) /
+                ((C2 / (2848.0 * lambda)).exp() - 1.0)
+            }
+            // The temperatures are scaled by the change to the second radiation
+            // constant since the illuminants were defined
+            Illuminant::D50 => daylight(daylight_chromaticity(5000.0 * 1.4388 / 1.438), lambda),
+            Illuminant::D65 => daylight(daylight_chromaticity(6500.0 * 1.4388 / 1.438), lambda),
+            Illuminant::F2 => interpolate_table(&FLUORESCENT_F2, 5.0, lambda),
+            Illuminant::F7 => interpolate_table(&FLUORESCENT_F7, 5.0, lambda),
+            Illuminant::F11 => interpolate_table(&FLUORESCENT_F11, 5.0, lambda),
+        }
+    }
+
+    // The spectral radiance of the illuminant with the given luminance in cd / m^2,
+    // in W / (m^2 sr nm)
+    pub fn spectral_radiance(&self, luminance: f64, lambda: f64) -> f64 {
+        let photometric = spectrum_to_xyz(|lambda| self.spectrum(lambda)).y * LUMINOUS_EFFICACY;
+        luminance * self.spectrum(lambda) / photometric
+    }
+
+    // The radiance in linear sRGB of the illuminant with the given luminance in
+    // cd / m^2, which is in the same units as `spectrum_to_xyz`: colors outside of
+    // the sRGB gamut are clamped
+    pub fn radiance(&self, luminance: f64) -> Vector {
+        let xyz = spectrum_to_xyz(|lambda| self.spectrum(lambda));
+        xyz_to_linear_srgb(&(xyz * (luminance / (xyz.y * LUMINOUS_EFFICACY)))).max(&Vector::zero())
+    }
+
+    // The radiance of a surface with the given area in m^2 that emits the given
+    // luminous power in lm evenly from one side, for use with emissive primitives
+    pub fn radiance_for_power(&self, power: f64, area: f64) -> Vector {
+        self.radiance(power / (f64::consts::PI * area))
+    }
+}
+
+#[test]
+fn test_reflectance_to_rgb() {
+    // Constant spectra are gray, while one that only reflects long wavelengths is
+    // red
+    let gray = reflectance_to_rgb(|_| 0.5);
+    assert!((gray - Vector::one() * 0.5).length() < 1.0e-9);
+    let red = reflectance_to_rgb(|lambda| if lambda > 600.0 { 1.0 } else { 0.0 });
+    assert!(red.x > 0.5 && red.y < 0.1 && red.z < 0.1);
+
+    // Daylight with the chromaticity of D65, the white point of sRGB, is white
+    let d65 = xyz_to_linear_srgb(&spectrum_to_xyz(|lambda| daylight((0.3127, 0.3290), lambda)));
+    assert!((d65.x / d65.y - 1.0).abs() < 0.03 && (d65.z / d65.y - 1.0).abs() < 0.03);
+
+    // The peak of the luminous efficiency function is at 555 nm
+    assert!(cie_xyz(555.0).y > cie_xyz(530.0).y && cie_xyz(555.0).y > cie_xyz(580.0).y);
+}
+
+#[test]
+fn test_illuminants() {
+    // The chromaticities of the standard illuminants match their definitions, to
+    // within the accuracy of the fit to the color matching functions
+    let chromaticity = |illuminant: Illuminant| {
+        let xyz = spectrum_to_xyz(|lambda| illuminant.spectrum(lambda));
+        let sum = xyz.x + xyz.y + xyz.z;
+        (xyz.x / sum, xyz.y / sum)
+    };
+    for &(illuminant, expected) in &[(Illuminant::A, (0.4476, 0.4074)),
+                                     (Illuminant::D50, (0.3457, 0.3585)),
+                                     (Illuminant::D65, (0.3127, 0.3290)),
+                                     (Illuminant::F2, (0.3721, 0.3751)),
+                                     (Illuminant::F7, (0.3129, 0.3292)),
+                                     (Illuminant::F11, (0.3805, 0.3769))] {
+        let (x, y) = chromaticity(illuminant);
+        assert!((x - expected.0).abs() < 0.003 && (y - expected.1).abs() < 0.003);
+    }
+
+    // A black body at 2856 K has nearly the same color as illuminant A, and its
+    // spectrum peaks where Wien's displacement law predicts
+    let (x, y) = chromaticity(Illuminant::Blackbody(2856.0));
+    assert!((x - 0.4476).abs() < 0.003 && (y - 0.4074).abs() < 0.003);
+    let peak = 2.897772e6 / 5000.0;
+    assert!(blackbody(peak, 5000.0) > blackbody(peak - 5.0, 5000.0));
+    assert!(blackbody(peak, 5000.0) > blackbody(peak + 5.0, 5000.0));
+
+    // Emitters are normalized by their luminance or luminous power, regardless of
+    // their color
+    for &illuminant in &[Illuminant::A, Illuminant::D65, Illuminant::F11, Illuminant::Blackbody(3000.0)] {
+        let radiance = illuminant.radiance(683.0);
+        assert!((radiance.luminance() - 1.0).abs() < 1.0e-3);
+        let spectral = spectrum_to_xyz(|lambda| illuminant.spectral_radiance(683.0, lambda));
+        assert!((spectral.y - 1.0).abs() < 1.0e-9);
+        let power = illuminant.radiance_for_power(1000.0, 0.5);
+        assert!((power.luminance() * LUMINOUS_EFFICACY * f64::consts::PI * 0.5 - 1000.0).abs() < 1.0);
+    }
+    assert!(Illuminant::A.radiance(1.0).x > Illuminant::A.radiance(1.0).z);
+}
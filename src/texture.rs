@@ -0,0 +1,477 @@
+use vector::Vector;
+use shape::DifferentialGeometry;
+use image::{Image, ColorSpace};
+use mipmap::{MipMap, FilterMode, WrapMode};
+use noise::{self, NoiseBasis};
+
+use std::f64;
+use std::io;
+use std::ops::{Add, Mul};
+use std::path::Path;
+use std::sync::Arc;
+
+// Values that can be produced by a texture, i.e. scalars or colors: these need to
+// support blending
+pub trait Texel: Copy + Send + Sync + Add<Output = Self> + Mul<f64, Output = Self> {}
+
+impl<T> Texel for T where T: Copy + Send + Sync + Add<Output = T> + Mul<f64, Output = T> {}
+
+pub trait Texture<T>: Sync + Send {
+    // Evaluate the texture at a point on a surface
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T;
+}
+
+// Texture mappings generate 2D texture coordinates for a point on a surface
+pub trait TextureMapping: Sync + Send {
+    fn map(&self, dg: &DifferentialGeometry) -> (f64, f64);
+
+    // Returns the derivatives of the texture coordinates with respect to the x and
+    // y axes of the image plane: by default these are estimated by mapping the
+    // points offset by the surface's own differentials, which is exact for
+    // mappings that are linear in the position and uv-coordinates
+    fn differentials(&self, dg: &DifferentialGeometry) -> ((f64, f64), (f64, f64)) {
+        let st = self.map(dg);
+        let mut offset = dg.clone();
+        offset.position = dg.position + dg.dpdx;
+        offset.uv = (dg.uv.0 + dg.dudx, dg.uv.1 + dg.dvdx);
+        let stx = self.map(&offset);
+        offset.position = dg.position + dg.dpdy;
+        offset.uv = (dg.uv.0 + dg.dudy, dg.uv.1 + dg.dvdy);
+        let sty = self.map(&offset);
+        ((stx.0 - st.0, stx.1 - st.1), (sty.0 - st.0, sty.1 - st.1))
+    }
+}
+
+// Uses the surface's own parameterization, scaled and offset
+pub struct UvMapping {
+    pub scale: (f64, f64),
+    pub offset: (f64, f64),
+}
+
+impl TextureMapping for UvMapping {
+    fn map(&self, dg: &DifferentialGeometry) -> (f64, f64) {
+        (dg.uv.0 * self.scale.0 + self.offset.0, dg.uv.1 * self.scale.1 + self.offset.1)
+    }
+}
+
+impl Default for UvMapping {
+    fn default() -> UvMapping {
+        UvMapping::new((1.0, 1.0), (0.0, 0.0))
+    }
+}
+
+impl UvMapping {
+    pub fn new(scale: (f64, f64), offset: (f64, f64)) -> UvMapping {
+        UvMapping { scale, offset }
+    }
+}
+
+// Projects points onto a sphere surrounding them, i.e. latitude / longitude
+pub struct SphericalMapping {
+    pub center: Vector,
+}
+
+impl TextureMapping for SphericalMapping {
+    fn map(&self, dg: &DifferentialGeometry) -> (f64, f64) {
+        let direction = (dg.position - self.center).normalize();
+        let theta = direction.y.clamp(-1.0, 1.0).acos();
+        let phi = (-direction.z).atan2(direction.x);
+        (phi / (2.0 * f64::consts::PI) + 0.5, 1.0 - theta / f64::consts::PI)
+    }
+
+    // Offsets that straddle the seam at s = 0 would otherwise produce a
+    // footprint that spans the entire texture
+    fn differentials(&self, dg: &DifferentialGeometry) -> ((f64, f64), (f64, f64)) {
+        let st = self.map(dg);
+        let mut offset = dg.clone();
+        let wrap = |ds: f64| ds - ds.round();
+        offset.position = dg.position + dg.dpdx;
+        let stx = self.map(&offset);
+        offset.position = dg.position + dg.dpdy;
+        let sty = self.map(&offset);
+        ((wrap(stx.0 - st.0), stx.1 - st.1), (wrap(sty.0 - st.0), sty.1 - st.1))
+    }
+}
+
+impl SphericalMapping {
+    pub fn new(c: &Vector) -> SphericalMapping {
+        SphericalMapping { center: *c }
+    }
+}
+
+// Projects points onto a plane spanned by two (not necessarily unit) vectors
+pub struct PlanarMapping {
+    pub s: Vector,
+    pub t: Vector,
+    pub offset: (f64, f64),
+}
+
+impl TextureMapping for PlanarMapping {
+    fn map(&self, dg: &DifferentialGeometry) -> (f64, f64) {
+        (dg.position.dot(&self.s) + self.offset.0, dg.position.dot(&self.t) + self.offset.1)
+    }
+}
+
+impl PlanarMapping {
+    pub fn new(s: &Vector, t: &Vector, offset: (f64, f64)) -> PlanarMapping {
+        PlanarMapping {
+            s: *s,
+            t: *t,
+            offset,
+        }
+    }
+}
+
+pub struct ConstantTexture<T> {
+    pub value: T,
+}
+
+impl<T: Texel> Texture<T> for ConstantTexture<T> {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T {
+        self.value
+    }
+}
+
+impl<T: Texel> ConstantTexture<T> {
+    pub fn new(value: T) -> ConstantTexture<T> {
+        ConstantTexture { value }
+    }
+}
+
+// Alternates between two textures in a checkerboard pattern, with one square per
+// unit of texture space
+pub struct CheckerboardTexture<T> {
+    pub even: Arc<dyn Texture<T>>,
+    pub odd: Arc<dyn Texture<T>>,
+    pub mapping: Arc<dyn TextureMapping>,
+}
+
+impl<T: Texel> Texture<T> for CheckerboardTexture<T> {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T {
+        let (s, t) = self.mapping.map(dg);
+        if (s.floor() + t.floor()) as i64 % 2 == 0 {
+            self.even.evaluate(dg)
+        } else {
+            self.odd.evaluate(dg)
+        }
+    }
+}
+
+impl<T: Texel> CheckerboardTexture<T> {
+    pub fn new(even: Arc<dyn Texture<T>>,
+               odd: Arc<dyn Texture<T>>,
+               mapping: Arc<dyn TextureMapping>)
+               -> CheckerboardTexture<T> {
+        CheckerboardTexture { even, odd, mapping }
+    }
+}
+
+// Blends linearly between two textures along the first texture coordinate, which
+// is clamped to the range 0..1
+pub struct GradientTexture<T> {
+    pub start: Arc<dyn Texture<T>>,
+    pub end: Arc<dyn Texture<T>>,
+    pub mapping: Arc<dyn TextureMapping>,
+}
+
+impl<T: Texel> Texture<T> for GradientTexture<T> {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T {
+        let s = self.mapping.map(dg).0.clamp(0.0, 1.0);
+        self.start.evaluate(dg) * (1.0 - s) + self.end.evaluate(dg) * s
+    }
+}
+
+impl<T: Texel> GradientTexture<T> {
+    pub fn new(start: Arc<dyn Texture<T>>,
+               end: Arc<dyn Texture<T>>,
+               mapping: Arc<dyn TextureMapping>)
+               -> GradientTexture<T> {
+        GradientTexture { start, end, mapping }
+    }
+}
+
+// Which part of an image a scalar texture reads
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum ImageChannel {
+    Luminance,
+    Red,
+    Green,
+    Blue,
+    // Opacity, which is 1 for images without an alpha channel: this is how alpha
+    // masks are usually stored
+    Alpha,
+}
+
+// Looks up a mip-mapped image, using the ray's footprint to select how many
+// texels to filter: the top row of the image corresponds to t = 1
+pub struct ImageTexture {
+    pub mipmap: Arc<MipMap>,
+    pub mapping: Arc<dyn TextureMapping>,
+    // Used when the image drives a scalar parameter
+    pub channel: ImageChannel,
+}
+
+impl ImageTexture {
+    pub fn new(mipmap: Arc<MipMap>, mapping: Arc<dyn TextureMapping>) -> ImageTexture {
+        ImageTexture {
+            mipmap,
+            mapping,
+            channel: ImageChannel::Luminance,
+        }
+    }
+
+    pub fn with_channel(mut self, channel: ImageChannel) -> ImageTexture {
+        self.channel = channel;
+        self
+    }
+
+    // Loads an image from disk, converting it to linear values if necessary
+    pub fn load(path: &Path,
+                mapping: Arc<dyn TextureMapping>,
+                filter: FilterMode,
+                wrap: WrapMode,
+                color_space: ColorSpace)
+                -> io::Result<ImageTexture> {
+        let mut image = Image::load(path)?;
+        if color_space == ColorSpace::Srgb {
+            image.srgb_to_linear();
+        }
+        Ok(ImageTexture::new(Arc::new(MipMap::new(image, wrap, filter)), mapping))
+    }
+
+    fn lookup(&self, dg: &DifferentialGeometry) -> Vector {
+        let (dstdx, dstdy) = self.mapping.differentials(dg);
+        self.mipmap.lookup(self.mapping.map(dg), dstdx, dstdy)
+    }
+}
+
+impl Texture<Vector> for ImageTexture {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> Vector {
+        self.lookup(dg)
+    }
+}
+
+impl Texture<f64> for ImageTexture {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> f64 {
+        match self.channel {
+            ImageChannel::Luminance => self.lookup(dg).luminance(),
+            ImageChannel::Red => self.lookup(dg).x,
+            ImageChannel::Green => self.lookup(dg).y,
+            ImageChannel::Blue => self.lookup(dg).z,
+            ImageChannel::Alpha => {
+                let (dstdx, dstdy) = self.mapping.differentials(dg);
+                self.mipmap.lookup_alpha(self.mapping.map(dg), dstdx, dstdy)
+            }
+        }
+    }
+}
+
+// Projects a texture along each of the three world axes and blends the results
+// based on the orientation of the surface, which avoids the need for uvs
+pub struct TriplanarTexture<T> {
+    pub texture: Arc<dyn Texture<T>>,
+    // The number of texture repeats per world-space unit
+    pub scale: f64,
+    // Higher values produce sharper transitions between projections
+    pub sharpness: f64,
+}
+
+impl<T: Texel> Texture<T> for TriplanarTexture<T> {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T {
+        let weights = dg.shading_normal.abs().powf(self.sharpness);
+        let weights = weights / (weights.x + weights.y + weights.z);
+        let p = dg.position * self.scale;
+
+        let dpdx = dg.dpdx * self.scale;
+        let dpdy = dg.dpdy * self.scale;
+
+        // Evaluate the texture once per projection, replacing the uvs and their
+        // derivatives
+        let mut projected = dg.clone();
+        let mut project = |u: usize, v: usize| {
+            projected.uv = (p[u], p[v]);
+            projected.dudx = dpdx[u];
+            projected.dvdx = dpdx[v];
+            projected.dudy = dpdy[u];
+            projected.dvdy = dpdy[v];
+            self.texture.evaluate(&projected)
+        };
+        let x = project(2, 1);
+        let y = project(0, 2);
+        let z = project(0, 1);
+        x * weights.x + y * weights.y + z * weights.z
+    }
+}
+
+impl<T: Texel> TriplanarTexture<T> {
+    pub fn new(texture: Arc<dyn Texture<T>>, scale: f64, sharpness: f64) -> TriplanarTexture<T> {
+        TriplanarTexture {
+            texture,
+            scale,
+            sharpness,
+        }
+    }
+}
+
+// Blends between two textures based on fractal noise evaluated at the point of
+// intersection (scaled), so that no parameterization is needed
+pub struct NoiseTexture<T> {
+    pub low: Arc<dyn Texture<T>>,
+    pub high: Arc<dyn Texture<T>>,
+    pub basis: NoiseBasis,
+    // The frequency of the first octave, in world-space units
+    pub scale: f64,
+    pub octaves: u32,
+    // Whether to sum the absolute value of each octave, rather than the signed
+    // value
+    pub turbulence: bool,
+}
+
+impl<T: Texel> Texture<T> for NoiseTexture<T> {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> T {
+        let p = dg.position * self.scale;
+        let value = if self.turbulence {
+            noise::turbulence(self.basis, &p, self.octaves, 2.0, 0.5)
+        } else {
+            noise::fbm(self.basis, &p, self.octaves, 2.0, 0.5) * 0.5 + 0.5
+        };
+        let value = value.clamp(0.0, 1.0);
+        self.low.evaluate(dg) * (1.0 - value) + self.high.evaluate(dg) * value
+    }
+}
+
+impl<T: Texel> NoiseTexture<T> {
+    pub fn new(low: Arc<dyn Texture<T>>,
+               high: Arc<dyn Texture<T>>,
+               basis: NoiseBasis,
+               scale: f64,
+               octaves: u32,
+               turbulence: bool)
+               -> NoiseTexture<T> {
+        NoiseTexture {
+            low,
+            high,
+            basis,
+            scale,
+            octaves,
+            turbulence,
+        }
+    }
+}
+
+// Marble: bands along the x-axis that are distorted by turbulence, with thin
+// veins where the bands are darkest
+pub struct MarbleTexture {
+    pub base: Vector,
+    pub vein: Vector,
+    pub scale: f64,
+    // How strongly the turbulence distorts the bands
+    pub variation: f64,
+    pub octaves: u32,
+    // Higher values produce thinner veins
+    pub sharpness: f64,
+}
+
+impl Texture<Vector> for MarbleTexture {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> Vector {
+        let p = dg.position * self.scale;
+        let distortion = noise::turbulence(NoiseBasis::Perlin, &p, self.octaves, 2.0, 0.5);
+        let bands = 0.5 + 0.5 * ((p.x + self.variation * distortion) * f64::consts::PI).sin();
+        self.base.lerp(&self.vein, (1.0 - bands).powf(self.sharpness))
+    }
+}
+
+impl Default for MarbleTexture {
+    fn default() -> MarbleTexture {
+        MarbleTexture::new(&Vector::new(0.9, 0.9, 0.88), &Vector::new(0.25, 0.25, 0.3), 1.5, 2.5, 6, 3.0)
+    }
+}
+
+impl MarbleTexture {
+    pub fn new(base: &Vector,
+               vein: &Vector,
+               scale: f64,
+               variation: f64,
+               octaves: u32,
+               sharpness: f64)
+               -> MarbleTexture {
+        MarbleTexture {
+            base: *base,
+            vein: *vein,
+            scale,
+            variation,
+            octaves,
+            sharpness,
+        }
+    }
+}
+
+// Wood: concentric growth rings around the y-axis, perturbed by noise
+pub struct WoodTexture {
+    pub light: Vector,
+    pub dark: Vector,
+    pub scale: f64,
+    // The number of rings per (scaled) unit of distance from the axis
+    pub rings: f64,
+    // How strongly the noise distorts the rings
+    pub distortion: f64,
+    pub octaves: u32,
+}
+
+impl Texture<Vector> for WoodTexture {
+    fn evaluate(&self, dg: &DifferentialGeometry) -> Vector {
+        let p = dg.position * self.scale;
+        let warp = noise::fbm(NoiseBasis::Perlin, &p, self.octaves, 2.0, 0.5);
+        let radius = (p.x * p.x + p.z * p.z).sqrt() + self.distortion * warp;
+
+        // Each ring fades in slowly from the light earlywood and ends abruptly with
+        // the dark latewood
+        let ring = (radius * self.rings).rem_euclid(1.0);
+        self.light.lerp(&self.dark, ring * ring * ring)
+    }
+}
+
+impl Default for WoodTexture {
+    fn default() -> WoodTexture {
+        WoodTexture::new(&Vector::new(0.75, 0.55, 0.33), &Vector::new(0.4, 0.23, 0.1), 1.0, 8.0, 0.3, 4)
+    }
+}
+
+impl WoodTexture {
+    pub fn new(light: &Vector,
+               dark: &Vector,
+               scale: f64,
+               rings: f64,
+               distortion: f64,
+               octaves: u32)
+               -> WoodTexture {
+        WoodTexture {
+            light: *light,
+            dark: *dark,
+            scale,
+            rings,
+            distortion,
+            octaves,
+        }
+    }
+}
+
+#[test]
+fn test_checkerboard() {
+    use ray::Ray;
+    use shape::{Shape, Plane};
+
+    let black = Arc::new(ConstantTexture::new(Vector::zero()));
+    let white = Arc::new(ConstantTexture::new(Vector::one()));
+    let mapping = Arc::new(PlanarMapping::new(&Vector::new(1.0, 0.0, 0.0),
+                                              &Vector::new(0.0, 0.0, 1.0),
+                                              (0.0, 0.0)));
+    let checkerboard = CheckerboardTexture::new(black, white, mapping);
+
+    let plane = Plane::default();
+    let down = Vector::new(0.0, -1.0, 0.0);
+    let dg = plane.intersect(&Ray::new(&Vector::new(0.5, 0.5, 0.5), &down, 0.0, f64::MAX)).unwrap();
+    assert_eq!(checkerboard.evaluate(&dg), Vector::zero());
+    let dg = plane.intersect(&Ray::new(&Vector::new(1.5, 0.5, 0.5), &down, 0.0, f64::MAX)).unwrap();
+    assert_eq!(checkerboard.evaluate(&dg), Vector::one());
+}
@@ -1,9 +1,9 @@
 extern crate rand;
 
-use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg};
+use std::ops::{Add, AddAssign, Sub, SubAssign, Mul, MulAssign, Div, DivAssign, Neg, Index};
 use rand::Rng;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Vector {
     pub x: f64,
     pub y: f64,
@@ -12,7 +12,7 @@ pub struct Vector {
 
 impl Vector {
     pub fn new(x: f64, y: f64, z: f64) -> Vector {
-        Vector { x: x, y: y, z: z }
+        Vector { x, y, z }
     }
 
     pub fn squared_length(&self) -> f64 {
@@ -35,6 +35,23 @@ impl Vector {
         self.x.max(self.y).max(self.z)
     }
 
+    pub fn min(&self, rhs: &Vector) -> Vector {
+        Vector::new(self.x.min(rhs.x), self.y.min(rhs.y), self.z.min(rhs.z))
+    }
+
+    pub fn max(&self, rhs: &Vector) -> Vector {
+        Vector::new(self.x.max(rhs.x), self.y.max(rhs.y), self.z.max(rhs.z))
+    }
+
+    pub fn abs(&self) -> Vector {
+        Vector::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    // The relative luminance of a linear RGB color (Rec. 709 primaries)
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
+
     pub fn powf(&self, exp: f64) -> Vector {
         Vector::new(self.x.powf(exp), self.y.powf(exp), self.z.powf(exp))
     }
@@ -55,6 +72,16 @@ impl Vector {
         }
     }
 
+    // Builds two unit vectors that, along with this (normalized) vector, form an
+    // orthonormal basis (Duff et al. 2017)
+    pub fn coordinate_system(&self) -> (Vector, Vector) {
+        let sign = 1.0f64.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (Vector::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+         Vector::new(b, sign + self.y * self.y * a, -self.y))
+    }
+
     pub fn reflect(&self, n: &Vector) -> Vector {
         *self - *n * 2.0 * self.dot(n)
     }
@@ -73,14 +100,12 @@ impl Vector {
         // unit sphere: pick a point inside of the unit cube
         // and return if it is also inside of the unit sphere
         let mut rng = rand::thread_rng();
-        let mut p = Vector::origin();
         loop {
-            p = Vector::new(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 2.0 - Vector::one();
+            let p = Vector::new(rng.next_f64(), rng.next_f64(), rng.next_f64()) * 2.0 - Vector::one();
             if p.squared_length() <= 1.0 {
-                break;
+                return p;
             }
         }
-        p
     }
 
     pub fn origin() -> Vector {
@@ -236,6 +261,20 @@ impl DivAssign<f64> for Vector {
     }
 }
 
+// Vector[i]
+impl Index<usize> for Vector {
+    type Output = f64;
+
+    fn index(&self, i: usize) -> &f64 {
+        match i {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("vector index out of range: {}", i),
+        }
+    }
+}
+
 // -Vector
 impl Neg for Vector {
     type Output = Vector;